@@ -0,0 +1,123 @@
+//! Minimal client for `--serve`: prints every frame as text and sends the Chip-8 keys typed on stdin.
+//!
+//! ```sh
+//! cargo run --example client -- 127.0.0.1:7000
+//! ```
+//!
+//! Typing a hexadecimal digit followed by enter presses and releases that key.
+
+use std::io::{self, BufRead, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+/// Sent by the server: width and height as big endian u16, followed by the packed pixels
+pub const FRAME_MESSAGE: u8 = 0x01;
+pub const KEY_DOWN_MESSAGE: u8 = 0x02;
+pub const KEY_UP_MESSAGE: u8 = 0x03;
+
+/// A display received from the server
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    /// pixels row by row with 8 pixels per byte, the most significant bit first
+    pub pixels: Vec<u8>,
+}
+
+impl Frame {
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        let index = y * self.width + x;
+        return self.pixels[index / 8] & (0x80 >> (index % 8)) != 0;
+    }
+}
+
+pub struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        return Ok(Self { stream });
+    }
+
+    /// Waits for the next frame, other messages are skipped
+    pub fn read_frame(&mut self) -> io::Result<Frame> {
+        loop {
+            let mut length = [0u8; 4];
+            self.stream.read_exact(&mut length)?;
+            let mut message = vec![0u8; u32::from_be_bytes(length) as usize];
+            self.stream.read_exact(&mut message)?;
+            if message.len() < 5 || message[0] != FRAME_MESSAGE {
+                continue;
+            }
+            return Ok(Frame {
+                width: u16::from_be_bytes([message[1], message[2]]) as usize,
+                height: u16::from_be_bytes([message[3], message[4]]) as usize,
+                pixels: message[5..].to_vec(),
+            });
+        }
+    }
+
+    pub fn key_down(&mut self, key: u8) -> io::Result<()> {
+        return self.write_message(KEY_DOWN_MESSAGE, key);
+    }
+
+    pub fn key_up(&mut self, key: u8) -> io::Result<()> {
+        return self.write_message(KEY_UP_MESSAGE, key);
+    }
+
+    fn write_message(&mut self, message_type: u8, key: u8) -> io::Result<()> {
+        self.stream.write_all(&2u32.to_be_bytes())?;
+        return self.stream.write_all(&[message_type, key]);
+    }
+
+    /// Makes `read_frame` fail instead of waiting forever
+    pub fn set_read_timeout(&self, timeout: Duration) -> io::Result<()> {
+        return self.stream.set_read_timeout(Some(timeout));
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        return Ok(Self {
+            stream: self.stream.try_clone()?,
+        });
+    }
+}
+
+fn main() -> io::Result<()> {
+    let address = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:7000".to_string());
+    let mut client = Client::connect(&address)?;
+
+    let mut key_client = client.try_clone()?;
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            let Ok(key) = u8::from_str_radix(line.trim(), 16) else {
+                eprintln!("Expected a key from 0 to F");
+                continue;
+            };
+            if key > 0xF
+                || key_client
+                    .key_down(key)
+                    .and(key_client.key_up(key))
+                    .is_err()
+            {
+                eprintln!("Failed to send key '{}'", line.trim());
+            }
+        }
+    });
+
+    loop {
+        let frame = client.read_frame()?;
+        let mut text = String::new();
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                text.push(if frame.pixel(x, y) { '#' } else { '.' });
+            }
+            text.push('\n');
+        }
+        println!("{}", text);
+    }
+}
@@ -0,0 +1,56 @@
+use std::{fs::File, io::Read, path::Path};
+
+use anyhow::{anyhow, Result};
+use zip::ZipArchive;
+
+/// Whether the path names a zip archive rather than a rom file
+pub fn is_zip_archive(path: &str) -> bool {
+    return Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"));
+}
+
+/// Reads a `.ch8` rom from a zip archive. An archive with several roms requires the name of one of them,
+/// either its full path inside of the archive or only its file name.
+pub fn load_rom(path: &str, entry: Option<&str>) -> Result<Vec<u8>> {
+    let file = File::open(path).map_err(|e| anyhow!("Failed to open '{}': {}", path, e))?;
+    let mut archive =
+        ZipArchive::new(file).map_err(|e| anyhow!("'{}' is no valid zip archive: {}", path, e))?;
+    let mut rom_names: Vec<String> = archive
+        .file_names()
+        .filter(|name| name.to_ascii_lowercase().ends_with(".ch8"))
+        .map(String::from)
+        .collect();
+    rom_names.sort();
+
+    let rom_name = match (entry, rom_names.as_slice()) {
+        (_, []) => return Err(anyhow!("'{}' contains no .ch8 roms", path)),
+        (Some(entry), _) => rom_names
+            .iter()
+            .find(|name| *name == entry || name.rsplit('/').next() == Some(entry))
+            .ok_or_else(|| {
+                anyhow!(
+                    "'{}' contains no rom '{}', it contains: {}",
+                    path,
+                    entry,
+                    rom_names.join(", ")
+                )
+            })?,
+        (None, [rom_name]) => rom_name,
+        (None, _) => {
+            return Err(anyhow!(
+                "'{}' contains several roms, select one with '--zip-entry': {}",
+                path,
+                rom_names.join(", ")
+            ))
+        }
+    };
+
+    let read_error = |e: &dyn std::fmt::Display| {
+        return anyhow!("Failed to read '{}' from '{}': {}", rom_name, path, e);
+    };
+    let mut file = archive.by_name(rom_name).map_err(|e| read_error(&e))?;
+    let mut rom = Vec::new();
+    file.read_to_end(&mut rom).map_err(|e| read_error(&e))?;
+    return Ok(rom);
+}
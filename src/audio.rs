@@ -2,29 +2,114 @@ use std::time::Duration;
 
 use rodio::{source::SineWave, OutputStream, Sink, Source};
 
-pub struct Audio {
+/// Open output device, the stream has to be kept alive for the sink to play
+pub struct AudioOutput {
     _stream: OutputStream,
     sink: Sink,
 }
 
+pub struct Audio {
+    /// `None` when audio is disabled, then no output device is ever opened
+    output: Option<AudioOutput>,
+    /// volume between 0.0 (silent) and 1.0 (full)
+    volume: f32,
+    /// muting only silences the sink, so the output device stays open and unmuting is instant
+    muted: bool,
+    /// plays one continuous tone exactly while the emulated sound timer runs, instead of queueing a beep per timer frame
+    timer_gated: bool,
+    /// whether the timer gated tone is currently audible
+    gate: bool,
+}
+
 impl Audio {
     pub fn new() -> Self {
         let (stream, stream_handle) = OutputStream::try_default().unwrap();
         let sink = Sink::try_new(&stream_handle).unwrap();
         return Self {
-            _stream: stream,
-            sink,
+            output: Some(AudioOutput {
+                _stream: stream,
+                sink,
+            }),
+            volume: 1.0,
+            muted: false,
+            timer_gated: false,
+            gate: false,
+        };
+    }
+
+    /// Audio that never plays anything and does not touch the audio device
+    pub fn silent() -> Self {
+        return Self {
+            output: None,
+            volume: 1.0,
+            muted: false,
+            timer_gated: false,
+            gate: false,
         };
     }
 
     pub fn play(&self, duration_secs: u8) {
-        let source = SineWave::new(1000.0)
-            .take_duration(Duration::from_secs_f32(duration_secs as f32))
-            .amplify(1.0);
-        self.sink.append(source);
+        if let Some(output) = &self.output {
+            let source = SineWave::new(1000.0)
+                .take_duration(Duration::from_secs_f32(duration_secs as f32))
+                .amplify(1.0);
+            output.sink.append(source);
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.gate = false;
+        if let Some(output) = &self.output {
+            output.sink.stop();
+        }
+    }
+
+    pub fn timer_gated(&self) -> bool {
+        return self.timer_gated;
+    }
+
+    pub fn set_timer_gated(&mut self, timer_gated: bool) {
+        self.timer_gated = timer_gated;
+    }
+
+    /// Starts or pauses the continuous tone of timer gated audio. Since the gate follows the emulated
+    /// sound timer, the tone lasts as many timer frames as the rom asked for at any emulation speed.
+    pub fn set_gate(&mut self, gate: bool) {
+        if gate == self.gate {
+            return;
+        }
+        self.gate = gate;
+        if let Some(output) = &self.output {
+            if gate {
+                if output.sink.empty() {
+                    output.sink.append(SineWave::new(1000.0).amplify(1.0));
+                }
+                output.sink.play();
+            } else {
+                output.sink.pause();
+            }
+        }
+    }
+
+    pub fn volume(&self) -> f32 {
+        return self.volume;
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+        self.apply_volume();
+    }
+
+    pub fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+        self.apply_volume();
     }
 
-    pub fn stop(&self) {
-        self.sink.stop()
+    fn apply_volume(&self) {
+        if let Some(output) = &self.output {
+            output
+                .sink
+                .set_volume(if self.muted { 0.0 } else { self.volume });
+        }
     }
 }
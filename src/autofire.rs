@@ -0,0 +1,73 @@
+use std::time::{Duration, Instant};
+
+use minifb::Key;
+
+use crate::keyboard::KeysChange;
+use crate::timing::FRAME_DURATION;
+
+/// Key that has to be held for the autofire to press and release its key, shift is taken by the hard reset
+pub const AUTOFIRE_MODIFIER_KEY: Key = Key::LeftCtrl;
+pub const DEFAULT_AUTOFIRE_RATE: u32 = 4;
+
+/// Repeatedly presses and releases a key while the modifier is held
+pub struct Autofire {
+    key: Key,
+    /// time between a synthetic press and the following release
+    toggle_interval: Duration,
+    /// set while the synthetic press is active
+    pressed_since: Option<Instant>,
+    released_since: Option<Instant>,
+}
+
+impl Autofire {
+    /// The key is toggled every `rate` 60 Hz frames
+    pub fn new(key: Key, rate: u32) -> Self {
+        return Self {
+            key,
+            toggle_interval: FRAME_DURATION * rate.max(1),
+            pressed_since: None,
+            released_since: None,
+        };
+    }
+
+    pub fn key(&self) -> Key {
+        return self.key;
+    }
+
+    /// Adds the synthetic presses and releases to the changes of the real keys.
+    /// A key that is physically held is never released by the autofire, so it can not get stuck in either state.
+    pub fn apply(&mut self, modifier_held: bool, key_held: bool, change: &mut KeysChange) {
+        let now = Instant::now();
+        if key_held {
+            // the real key takes over, the next autofire cycle starts from scratch
+            self.pressed_since = None;
+            self.released_since = None;
+            return;
+        }
+
+        match (self.pressed_since, modifier_held) {
+            (Some(pressed_since), true) if now - pressed_since >= self.toggle_interval => {
+                self.pressed_since = None;
+                self.released_since = Some(now);
+                change.released.push(self.key);
+            }
+            (Some(_), false) => {
+                self.pressed_since = None;
+                self.released_since = None;
+                change.released.push(self.key);
+            }
+            (None, true) => {
+                let release_finished = self
+                    .released_since
+                    .is_none_or(|released_since| now - released_since >= self.toggle_interval);
+                if release_finished {
+                    self.pressed_since = Some(now);
+                    self.released_since = None;
+                    change.pressed.push(self.key);
+                }
+            }
+            (Some(_), true) => {}
+            (None, false) => self.released_since = None,
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+use anyhow::{anyhow, Result};
+use tracing::debug;
+
+/// Name of the outermost frame, the code that runs outside of any subroutine
+const ROOT_FRAME: &str = "main";
+
+/// Records the subroutine calls of a program and counts the executed instructions per call stack.
+/// The result is written in the folded stack format, which flame graph tools like `inferno` read.
+pub struct CallTrace {
+    path: String,
+    /// target addresses of the active subroutine calls, the innermost call is last
+    call_targets: Vec<u16>,
+    /// instructions executed since the call stack last changed
+    current_count: u64,
+    /// executed instructions per call stack
+    counts: BTreeMap<Vec<u16>, u64>,
+}
+
+impl CallTrace {
+    pub fn new(path: String) -> Self {
+        return Self {
+            path,
+            call_targets: Vec::new(),
+            current_count: 0,
+            counts: BTreeMap::new(),
+        };
+    }
+
+    pub fn record_instruction(&mut self) {
+        self.current_count += 1;
+    }
+
+    pub fn enter(&mut self, target_address: u16) {
+        self.flush_count();
+        self.call_targets.push(target_address);
+        debug!(
+            "Entering subroutine {:#05x} at depth {}",
+            target_address,
+            self.call_targets.len()
+        );
+    }
+
+    pub fn exit(&mut self) {
+        self.flush_count();
+        if let Some(target_address) = self.call_targets.pop() {
+            debug!(
+                "Leaving subroutine {:#05x} at depth {}",
+                target_address,
+                self.call_targets.len() + 1
+            );
+        }
+    }
+
+    /// Writes one line per call stack, e.g. `main;0x2a0;0x2f4 42`
+    pub fn write(&mut self) -> Result<()> {
+        self.flush_count();
+        let folded: String = self
+            .counts
+            .iter()
+            .map(|(call_targets, count)| {
+                let frames: Vec<String> = std::iter::once(ROOT_FRAME.to_string())
+                    .chain(
+                        call_targets
+                            .iter()
+                            .map(|address| format!("{:#05x}", address)),
+                    )
+                    .collect();
+                format!("{} {}\n", frames.join(";"), count)
+            })
+            .collect();
+        return fs::write(&self.path, folded)
+            .map_err(|e| anyhow!("Failed to write call trace to '{}': {}", self.path, e));
+    }
+
+    fn flush_count(&mut self) {
+        if self.current_count > 0 {
+            *self.counts.entry(self.call_targets.clone()).or_insert(0) += self.current_count;
+            self.current_count = 0;
+        }
+    }
+}
@@ -0,0 +1,529 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use tracing::Level;
+use u4::{U4x2, U4};
+
+use crate::autofire::DEFAULT_AUTOFIRE_RATE;
+use crate::config::Config;
+use crate::cpu::{CpuConfig, MAX_STACK_SIZE};
+use crate::debugger::{parse_condition, parse_opcode_pattern, Condition};
+use crate::keyboard::{DEFAULT_KEY_REPEAT_DELAY, DEFAULT_KEY_REPEAT_INTERVAL};
+use crate::memory::{FONT_SIZE, MAX_MEMORY_SIZE, PROGRAM_START_ADDRESS};
+use crate::palette::Palette;
+use crate::renderer::{DEFAULT_SCREEN_HEIGHT, DEFAULT_SCREEN_WIDTH, MAX_SCREEN_DIMENSION};
+
+#[derive(Clone)]
+pub struct Options {
+    /// path to the rom file, the default rom is used when none is given
+    pub rom_path: Option<String>,
+    /// rom to load when the rom path is a zip archive with several roms
+    pub zip_entry: Option<String>,
+    /// Octo source that is assembled and run instead of a rom file
+    pub assemble_path: Option<String>,
+    pub cpu_config: CpuConfig,
+    /// addresses at which the emulation is paused
+    pub breakpoints: Vec<u16>,
+    /// `(mask, value)` pairs of opcode patterns at which the emulation is paused
+    pub opcode_breakpoints: Vec<(u16, u16)>,
+    /// conditions at which the emulation is paused once they become true
+    pub condition_breakpoints: Vec<Condition>,
+    /// runs the emulator without a window
+    pub headless: bool,
+    /// number of instructions executed in headless mode
+    pub cycles: Option<u64>,
+    /// cycles in a row the cpu may wait for a key in headless and batch mode before the run stops
+    pub max_idle_cycles: Option<u64>,
+    /// prints the cpu state as json once the headless run finished
+    pub dump_state_json: bool,
+    /// time after which the emulator exits
+    pub exit_after: Option<Duration>,
+    /// number of instructions after which the emulator exits
+    pub exit_after_cycles: Option<u64>,
+    /// seed for reproducible random numbers, the operating system rng is used when none is given
+    pub seed: Option<u64>,
+    /// reads debugger commands from stdin
+    pub repl: bool,
+    /// address execution starts at instead of the program start
+    pub entry_address: Option<u16>,
+    /// fills the memory behind the rom with a pattern generated from the seed instead of zeros
+    pub noise_seed: Option<u64>,
+    /// file the opcode histogram is written to when the emulator exits
+    pub profile_path: Option<String>,
+    /// file the executed and not executed address ranges of the rom are written to on exit
+    pub coverage_path: Option<String>,
+    /// files that are written to memory at `(address, path)` after the rom was loaded
+    pub overlays: Vec<(u16, String)>,
+    /// file the state and the last instructions are written to when the cpu crashes
+    pub crash_dump_path: Option<String>,
+    /// file that is loaded as the whole memory content, replacing the font and the rom
+    pub memory_image_path: Option<String>,
+    /// file the subroutine calls are written to in the folded stack format
+    pub call_trace_path: Option<String>,
+    /// fails instead of warning when the rom does not look like a Chip-8 program
+    /// and warns about reads of never written memory
+    pub strict: bool,
+    /// highlights the pixels erased by sprite draws
+    pub show_collisions: bool,
+    /// shows presses of keys without a Chip-8 or emulator function in the window title
+    pub show_unmapped_keys: bool,
+    /// shows the measured instruction and timer rates in the window title
+    pub show_rates: bool,
+    /// prints what the rom did that hints at compatibility problems when the cpu stops
+    pub compatibility_report: bool,
+    /// prints how many display updates the window skipped when it exits
+    pub frame_stats: bool,
+    /// prints every sprite draw that erased a pixel
+    pub log_collisions: bool,
+    /// prints every executed instruction with the registers after it
+    pub log_instructions: bool,
+    /// prints what every executed instruction did in plain English
+    pub explain: bool,
+    /// directory whose roms are played one after another in the window
+    pub playlist_directory: Option<String>,
+    /// directory whose roms are run headless one after another, reporting the ones that failed
+    pub batch_directory: Option<String>,
+    /// prints the font characters as text instead of running the rom
+    pub show_font: bool,
+    /// reports unknown instructions of the rom instead of running it
+    pub validate: bool,
+    /// prints a listing of the rom that separates code from data instead of running it
+    pub disassemble: bool,
+    pub palette: Palette,
+    /// scales the display to the window size with black bars instead of letting the window stretch it
+    pub letterbox: bool,
+    pub screen_width: usize,
+    pub screen_height: usize,
+    /// Chip-8 key that is pressed repeatedly while the autofire modifier is held
+    pub autofire_key: Option<U4>,
+    /// number of 60 Hz frames between the autofire presses and releases
+    pub autofire_rate: u32,
+    /// initial volume of the beep between 0.0 (silent) and 1.0 (full)
+    pub volume: f32,
+    /// plays the beep exactly as many emulated timer frames as the sound timer holds
+    pub timer_gated_audio: bool,
+    /// runs a second cpu with every quirk toggled and shows both displays side by side
+    pub compare_quirks: bool,
+    /// address like `127.0.0.1:8088` the display is streamed from instead of showing a window
+    pub serve_address: Option<String>,
+    /// repeats the presses of held keys
+    pub key_repeat: bool,
+    /// time a key has to be held before its presses are repeated
+    pub key_repeat_delay: Duration,
+    /// time between the repeated presses of a held key
+    pub key_repeat_interval: Duration,
+    /// how long the splash screen is shown before the rom starts, zero disables it
+    pub splash_duration: Duration,
+    /// most verbose level of the log messages that are printed
+    pub log_level: Level,
+    /// only prints errors, overriding the log level
+    pub quiet: bool,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options> {
+        let mut options = Options {
+            rom_path: None,
+            zip_entry: None,
+            assemble_path: None,
+            cpu_config: CpuConfig::default(),
+            breakpoints: Vec::new(),
+            opcode_breakpoints: Vec::new(),
+            condition_breakpoints: Vec::new(),
+            headless: false,
+            cycles: None,
+            max_idle_cycles: None,
+            dump_state_json: false,
+            exit_after: None,
+            exit_after_cycles: None,
+            seed: None,
+            call_trace_path: None,
+            memory_image_path: None,
+            crash_dump_path: None,
+            overlays: Vec::new(),
+            profile_path: None,
+            coverage_path: None,
+            entry_address: None,
+            noise_seed: None,
+            repl: false,
+            batch_directory: None,
+            playlist_directory: None,
+            show_font: false,
+            validate: false,
+            disassemble: false,
+            strict: false,
+            log_collisions: false,
+            log_instructions: false,
+            explain: false,
+            frame_stats: false,
+            show_unmapped_keys: false,
+            show_rates: false,
+            compatibility_report: false,
+            show_collisions: false,
+            palette: Palette::default(),
+            letterbox: false,
+            screen_width: DEFAULT_SCREEN_WIDTH,
+            screen_height: DEFAULT_SCREEN_HEIGHT,
+            volume: 1.0,
+            timer_gated_audio: false,
+            splash_duration: Duration::ZERO,
+            log_level: Level::WARN,
+            quiet: false,
+            compare_quirks: false,
+            serve_address: None,
+            key_repeat: false,
+            key_repeat_delay: DEFAULT_KEY_REPEAT_DELAY,
+            key_repeat_interval: DEFAULT_KEY_REPEAT_INTERVAL,
+            autofire_key: None,
+            autofire_rate: DEFAULT_AUTOFIRE_RATE,
+        };
+
+        // the config file provides the defaults, so it is applied before any other option
+        if let Some(config_path) = find_config_path(args)? {
+            Config::load(config_path)?.apply(&mut options)?;
+        }
+
+        let mut args_iter = args.iter().skip(1);
+        while let Some(arg) = args_iter.next() {
+            match arg.as_str() {
+                "--memory-size" => {
+                    options.cpu_config.memory_size =
+                        parse_number(next_value(&mut args_iter, arg)?)?;
+                }
+                "--font-base" => {
+                    options.cpu_config.font_base = parse_address(next_value(&mut args_iter, arg)?)?;
+                }
+                "--stack-size" => {
+                    options.cpu_config.stack_size = parse_number(next_value(&mut args_iter, arg)?)?;
+                }
+                "--break" => {
+                    options
+                        .breakpoints
+                        .push(parse_address(next_value(&mut args_iter, arg)?)?);
+                }
+                "--break-opcode" => {
+                    options
+                        .opcode_breakpoints
+                        .push(parse_opcode_pattern(next_value(&mut args_iter, arg)?)?);
+                }
+                "--break-if" => {
+                    options
+                        .condition_breakpoints
+                        .push(parse_condition(next_value(&mut args_iter, arg)?)?);
+                }
+                "--clip-sprite-start" => options.cpu_config.quirks.clip_sprite_start = true,
+                "--wrap-x" => options.cpu_config.quirks.wrap_x = true,
+                "--wrap-y" => options.cpu_config.quirks.wrap_y = true,
+                "--sprite-read-wraps" => options.cpu_config.quirks.sprite_read_wraps = true,
+                "--memory-access-wraps" => options.cpu_config.quirks.memory_access_wraps = true,
+                "--large-sprite-dxy0" => options.cpu_config.quirks.large_sprite_dxy0 = true,
+                "--vip-timing" => options.cpu_config.vip_timing = true,
+                "--chip8x" => options.cpu_config.chip8x = true,
+                "--headless" => options.headless = true,
+                "--playlist" => {
+                    options.playlist_directory = Some(next_value(&mut args_iter, arg)?.to_string());
+                }
+                "--batch" => {
+                    options.batch_directory = Some(next_value(&mut args_iter, arg)?.to_string());
+                }
+                "--cycles" => {
+                    options.cycles = Some(parse_number(next_value(&mut args_iter, arg)?)? as u64);
+                }
+                "--max-idle-cycles" => {
+                    options.max_idle_cycles =
+                        Some(parse_number(next_value(&mut args_iter, arg)?)? as u64);
+                }
+                "--dump-state-json" => options.dump_state_json = true,
+                "--exit-after" => {
+                    let seconds = parse_number(next_value(&mut args_iter, arg)?)?;
+                    options.exit_after = Some(Duration::from_secs(seconds as u64));
+                }
+                "--exit-after-cycles" => {
+                    options.exit_after_cycles =
+                        Some(parse_number(next_value(&mut args_iter, arg)?)? as u64);
+                }
+                "--log-level" => {
+                    let value = next_value(&mut args_iter, arg)?;
+                    options.log_level = value.parse().map_err(|_| {
+                        anyhow!(
+                            "Unknown log level '{}', expected 'error', 'warn', 'info', 'debug' or 'trace'",
+                            value
+                        )
+                    })?;
+                }
+                "--quiet" => options.quiet = true,
+                "--entry" => {
+                    options.entry_address = Some(parse_address(next_value(&mut args_iter, arg)?)?);
+                }
+                "--init" => match next_value(&mut args_iter, arg)? {
+                    "noise" => options.noise_seed = Some(options.noise_seed.unwrap_or(0)),
+                    "zero" => options.noise_seed = None,
+                    value => {
+                        return Err(anyhow!(
+                            "Unknown memory initialization '{}', expected 'zero' or 'noise'",
+                            value
+                        ))
+                    }
+                },
+                "--init-seed" => {
+                    options.noise_seed =
+                        Some(parse_number(next_value(&mut args_iter, arg)?)? as u64);
+                }
+                "--seed" => {
+                    options.seed = Some(parse_number(next_value(&mut args_iter, arg)?)? as u64);
+                }
+                "--repl" => options.repl = true,
+                "--serve" => {
+                    options.serve_address = Some(next_value(&mut args_iter, arg)?.to_string());
+                }
+                "--assemble" => {
+                    options.assemble_path = Some(next_value(&mut args_iter, arg)?.to_string());
+                }
+                "--show-font" => options.show_font = true,
+                "--validate" => options.validate = true,
+                "--disassemble" => options.disassemble = true,
+                "--zip-entry" => {
+                    options.zip_entry = Some(next_value(&mut args_iter, arg)?.to_string());
+                }
+                "--strict" => options.strict = true,
+                "--log-collisions" => options.log_collisions = true,
+                "--log-instructions" => options.log_instructions = true,
+                "--explain" => options.explain = true,
+                "--frame-stats" => options.frame_stats = true,
+                "--show-unmapped-keys" => options.show_unmapped_keys = true,
+                "--show-rates" => options.show_rates = true,
+                "--compat-report" => options.compatibility_report = true,
+                "--show-collisions" => options.show_collisions = true,
+                "--compare-quirks" => options.compare_quirks = true,
+                "--color0" => {
+                    options.palette.color0 = parse_color(next_value(&mut args_iter, arg)?)?
+                }
+                "--color1" => {
+                    options.palette.color1 = parse_color(next_value(&mut args_iter, arg)?)?
+                }
+                "--color2" => {
+                    options.palette.color2 = parse_color(next_value(&mut args_iter, arg)?)?
+                }
+                "--color3" => {
+                    options.palette.color3 = parse_color(next_value(&mut args_iter, arg)?)?
+                }
+                "--dump-on-crash" => {
+                    options.crash_dump_path = Some(next_value(&mut args_iter, arg)?.to_string());
+                }
+                "--load" => {
+                    options
+                        .overlays
+                        .push(parse_overlay(next_value(&mut args_iter, arg)?)?);
+                }
+                "--mem-image" => {
+                    options.memory_image_path = Some(next_value(&mut args_iter, arg)?.to_string());
+                }
+                "--coverage-out" => {
+                    options.coverage_path = Some(next_value(&mut args_iter, arg)?.to_string());
+                }
+                "--profile-out" => {
+                    options.profile_path = Some(next_value(&mut args_iter, arg)?.to_string());
+                }
+                "--call-trace" => {
+                    options.call_trace_path = Some(next_value(&mut args_iter, arg)?.to_string());
+                }
+                "--letterbox" => options.letterbox = true,
+                "--screen-size" => {
+                    (options.screen_width, options.screen_height) =
+                        parse_screen_size(next_value(&mut args_iter, arg)?)?;
+                }
+                "--no-audio" => options.cpu_config.audio = false,
+                "--timer-gated-audio" => options.timer_gated_audio = true,
+                "--volume" => {
+                    let value = next_value(&mut args_iter, arg)?;
+                    options.volume = value
+                        .parse()
+                        .map_err(|e| anyhow!("Invalid volume '{}': {}", value, e))?;
+                }
+                "--key-repeat" => options.key_repeat = true,
+                "--key-repeat-delay" => {
+                    options.key_repeat_delay = parse_seconds(next_value(&mut args_iter, arg)?)?;
+                }
+                "--key-repeat-rate" => {
+                    options.key_repeat_interval = parse_seconds(next_value(&mut args_iter, arg)?)?;
+                }
+                "--splash" => {
+                    options.splash_duration = parse_seconds(next_value(&mut args_iter, arg)?)?;
+                }
+                "--autofire" => {
+                    options.autofire_key =
+                        Some(parse_chip_8_key(next_value(&mut args_iter, arg)?)?);
+                }
+                "--autofire-rate" => {
+                    options.autofire_rate = parse_number(next_value(&mut args_iter, arg)?)? as u32;
+                }
+                "--config" => {
+                    // already applied above
+                    next_value(&mut args_iter, arg)?;
+                }
+                _ if arg.starts_with("--") => return Err(anyhow!("Unknown option '{}'", arg)),
+                _ => options.rom_path = Some(arg.clone()),
+            }
+        }
+
+        let min_memory_size = PROGRAM_START_ADDRESS as usize + FONT_SIZE;
+        if !(min_memory_size..=MAX_MEMORY_SIZE).contains(&options.cpu_config.memory_size) {
+            return Err(anyhow!(
+                "The memory size should be between {:#05x} and {:#x} bytes",
+                min_memory_size,
+                MAX_MEMORY_SIZE
+            ));
+        }
+        if options.cpu_config.font_base as usize + FONT_SIZE > PROGRAM_START_ADDRESS as usize {
+            return Err(anyhow!(
+                "The font has to end before the program start {:#05x}",
+                PROGRAM_START_ADDRESS
+            ));
+        }
+        if !(1..=MAX_STACK_SIZE).contains(&options.cpu_config.stack_size) {
+            return Err(anyhow!(
+                "The stack size should be between 1 and {} nested calls",
+                MAX_STACK_SIZE
+            ));
+        }
+        if !(1..=MAX_SCREEN_DIMENSION).contains(&options.screen_width)
+            || !(1..=MAX_SCREEN_DIMENSION).contains(&options.screen_height)
+        {
+            return Err(anyhow!(
+                "Screen dimensions should be between 1 and {} pixels",
+                MAX_SCREEN_DIMENSION
+            ));
+        }
+        if options.quiet {
+            options.log_level = Level::ERROR;
+            // the traces go to stdout, which stays empty
+            options.log_instructions = false;
+            options.log_collisions = false;
+            options.explain = false;
+        }
+        if options.headless && options.cycles.is_none() {
+            return Err(anyhow!("Headless mode requires '--cycles'"));
+        }
+        if options.max_idle_cycles.is_some()
+            && !options.headless
+            && options.batch_directory.is_none()
+        {
+            return Err(anyhow!(
+                "'--max-idle-cycles' is only supported in headless and batch mode"
+            ));
+        }
+        if options.dump_state_json && !options.headless {
+            return Err(anyhow!(
+                "'--dump-state-json' is only supported in headless mode"
+            ));
+        }
+
+        return Ok(options);
+    }
+}
+
+fn find_config_path(args: &[String]) -> Result<Option<&str>> {
+    let mut args_iter = args.iter().skip(1);
+    while let Some(arg) = args_iter.next() {
+        if arg == "--config" {
+            return Ok(Some(next_value(&mut args_iter, arg)?));
+        }
+    }
+    return Ok(None);
+}
+
+fn next_value<'a>(
+    args_iter: &mut impl Iterator<Item = &'a String>,
+    option: &str,
+) -> Result<&'a str> {
+    return args_iter
+        .next()
+        .map(|value| value.as_str())
+        .ok_or_else(|| anyhow!("Option '{}' expects a value", option));
+}
+
+/// Parses a decimal number or a hexadecimal number prefixed with `0x`
+pub fn parse_number(value: &str) -> Result<usize> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    return parsed.map_err(|e| anyhow!("Invalid number '{}': {}", value, e));
+}
+
+pub fn parse_address(value: &str) -> Result<u16> {
+    return u16::try_from(parse_number(value)?)
+        .map_err(|_| anyhow!("Address '{}' exceeds the 16-bit address space", value));
+}
+
+/// Parses `<address>=<file>` like `0x400=data.bin`
+fn parse_overlay(value: &str) -> Result<(u16, String)> {
+    let (address, path) = value
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Overlay '{}' should look like <address>=<file>", value))?;
+    return Ok((parse_address(address)?, path.to_string()));
+}
+
+/// Parses a single hexadecimal digit like `5` or `A`
+fn parse_chip_8_key(value: &str) -> Result<U4> {
+    let key = u8::from_str_radix(value, 16)
+        .ok()
+        .filter(|key| *key <= 0xF)
+        .ok_or_else(|| anyhow!("Key '{}' should be a hexadecimal digit", value))?;
+    return Ok(U4x2::from(key).right());
+}
+
+/// Parses a screen size like `64x32`
+pub fn parse_screen_size(value: &str) -> Result<(usize, usize)> {
+    let (width, height) = value
+        .split_once('x')
+        .ok_or_else(|| anyhow!("Screen size '{}' should look like 64x32", value))?;
+    return Ok((parse_number(width)?, parse_number(height)?));
+}
+
+/// Parses a RGB color like `#009900`, `0x009900` or `009900`
+pub fn parse_color(value: &str) -> Result<u32> {
+    let hex = value
+        .strip_prefix('#')
+        .or_else(|| value.strip_prefix("0x"))
+        .unwrap_or(value);
+    if hex.len() != 6 {
+        return Err(anyhow!(
+            "Color '{}' should have 6 hexadecimal digits",
+            value
+        ));
+    }
+    return u32::from_str_radix(hex, 16).map_err(|e| anyhow!("Invalid color '{}': {}", value, e));
+}
+
+/// Parses a non-negative number of seconds like `1` or `0.5`
+pub fn parse_seconds(value: &str) -> Result<Duration> {
+    let seconds: f32 = value
+        .parse()
+        .map_err(|e| anyhow!("Invalid duration '{}': {}", value, e))?;
+    return Duration::try_from_secs_f32(seconds)
+        .map_err(|e| anyhow!("Invalid duration '{}': {}", value, e));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options> {
+        let args: Vec<String> = ["chip_8_emulator"]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.to_string())
+            .collect();
+        return Options::parse(&args);
+    }
+
+    #[test]
+    fn stack_size_is_validated() {
+        let options = parse(&["--stack-size", "32", "rom.ch8"]).unwrap();
+        assert_eq!(options.cpu_config.stack_size, 32);
+        assert!(parse(&["--stack-size", "1024", "rom.ch8"]).is_ok());
+        assert!(parse(&["--stack-size", "0", "rom.ch8"]).is_err());
+        assert!(parse(&["--stack-size", "1025", "rom.ch8"]).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::cpu::Cpu;
+
+/// Behaviour of a rom that hints at a compatibility problem with the emulator settings
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Observation {
+    /// `(address, opcode)` of an instruction that could not be decoded
+    UnknownOpcode(u16, u16),
+    /// `Dxy0` drew a 16x16 sprite with the large sprite quirk
+    LargeSpriteDxy0,
+    /// `Dxy0` drew nothing without the large sprite quirk
+    EmptySpriteDxy0,
+    /// the instruction like `Fx55` accessed memory beyond its end
+    MemoryAccessCrossedEnd(&'static str),
+    /// a sprite read from `I` continued at the start of memory
+    SpriteReadWrapped,
+    StackOverflow,
+    /// `00EE` without an active subroutine call
+    StackUnderflow,
+}
+
+impl fmt::Display for Observation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Observation::UnknownOpcode(address, opcode) => {
+                write!(f, "unknown instruction {:04x} at {:#05x}", opcode, address)
+            }
+            Observation::LargeSpriteDxy0 => {
+                write!(f, "Dxy0 drew SCHIP 16x16 sprites")
+            }
+            Observation::EmptySpriteDxy0 => {
+                write!(f, "Dxy0 drew nothing, SCHIP roms need --large-sprite-dxy0")
+            }
+            Observation::MemoryAccessCrossedEnd(access) => write!(
+                f,
+                "{} crossed the end of memory, --memory-access-wraps continues at its start",
+                access
+            ),
+            Observation::SpriteReadWrapped => {
+                write!(f, "a sprite read wrapped around the end of memory")
+            }
+            Observation::StackOverflow => write!(f, "stack overflow, try a larger --stack-size"),
+            Observation::StackUnderflow => {
+                write!(f, "return without a subroutine call")
+            }
+        };
+    }
+}
+
+/// Collects the observations of a run to explain afterwards why a rom might have misbehaved
+pub struct CompatibilityReport {
+    /// how often each observation was made
+    observations: BTreeMap<Observation, u64>,
+}
+
+impl CompatibilityReport {
+    pub fn new() -> Self {
+        return Self {
+            observations: BTreeMap::new(),
+        };
+    }
+
+    pub fn record(&mut self, observation: Observation) {
+        *self.observations.entry(observation).or_insert(0) += 1;
+    }
+}
+
+impl fmt::Display for CompatibilityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.observations.is_empty() {
+            return write!(f, "Compatibility report: nothing unusual observed");
+        }
+        write!(f, "Compatibility report:")?;
+        for (observation, count) in self.observations.iter() {
+            write!(f, "\n  {} ({}x)", observation, count)?;
+        }
+        return Ok(());
+    }
+}
+
+/// Runs the cpu and prints its compatibility report afterwards, if it collects one.
+/// A panic is passed on after the report was printed, as the report often explains the crash.
+pub fn run_with_report<T>(cpu: &mut Cpu, run: impl FnOnce(&mut Cpu) -> T) -> T {
+    if cpu.compatibility_report().is_none() {
+        return run(cpu);
+    }
+    let result = panic::catch_unwind(AssertUnwindSafe(|| run(cpu)));
+    if let Some(report) = cpu.compatibility_report() {
+        println!("{}", report);
+    }
+    return match result {
+        Ok(result) => result,
+        Err(payload) => panic::resume_unwind(payload),
+    };
+}
@@ -0,0 +1,93 @@
+use std::fs;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use crate::cli::{parse_color, parse_screen_size, Options};
+use crate::quirks::Quirks;
+
+/// Settings read from a TOML file given with `--config`.
+/// Every setting is optional and explicit command line options take precedence.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub memory_size: Option<usize>,
+    pub stack_size: Option<usize>,
+    pub font_base: Option<u16>,
+    /// e.g. `"64x32"`
+    pub screen_size: Option<String>,
+    pub seed: Option<u64>,
+    /// volume between 0.0 (silent) and 1.0 (full)
+    pub volume: Option<f32>,
+    pub quirks: Option<Quirks>,
+    pub vip_timing: Option<bool>,
+    /// seconds the splash screen is shown, 0 disables it
+    pub splash: Option<f32>,
+    #[serde(default)]
+    pub colors: ColorsConfig,
+}
+
+/// Display colors like `"#009900"`
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ColorsConfig {
+    pub color0: Option<String>,
+    pub color1: Option<String>,
+    pub color2: Option<String>,
+    pub color3: Option<String>,
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Config> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read config file '{}': {}", path, e))?;
+        return toml::from_str(&content)
+            .map_err(|e| anyhow!("Invalid config file '{}': {}", path, e));
+    }
+
+    /// Overwrites the options with every setting present in the config
+    pub fn apply(&self, options: &mut Options) -> Result<()> {
+        if let Some(memory_size) = self.memory_size {
+            options.cpu_config.memory_size = memory_size;
+        }
+        if let Some(stack_size) = self.stack_size {
+            options.cpu_config.stack_size = stack_size;
+        }
+        if let Some(font_base) = self.font_base {
+            options.cpu_config.font_base = font_base;
+        }
+        if let Some(screen_size) = &self.screen_size {
+            (options.screen_width, options.screen_height) = parse_screen_size(screen_size)?;
+        }
+        if let Some(seed) = self.seed {
+            options.seed = Some(seed);
+        }
+        if let Some(volume) = self.volume {
+            options.volume = volume;
+        }
+        if let Some(vip_timing) = self.vip_timing {
+            options.cpu_config.vip_timing = vip_timing;
+        }
+        if let Some(splash) = self.splash {
+            options.splash_duration = Duration::try_from_secs_f32(splash)
+                .map_err(|e| anyhow!("Invalid splash duration {}: {}", splash, e))?;
+        }
+        if let Some(quirks) = self.quirks {
+            options.cpu_config.quirks = quirks;
+        }
+
+        let colors = [
+            (&self.colors.color0, &mut options.palette.color0),
+            (&self.colors.color1, &mut options.palette.color1),
+            (&self.colors.color2, &mut options.palette.color2),
+            (&self.colors.color3, &mut options.palette.color3),
+        ];
+        for (config_color, palette_color) in colors {
+            if let Some(config_color) = config_color {
+                *palette_color = parse_color(config_color)?;
+            }
+        }
+        return Ok(());
+    }
+}
@@ -0,0 +1,49 @@
+use crate::cpu::CpuState;
+use crate::debugger::DebugCommand;
+
+/// Commands sent from the window thread to the cpu thread
+#[derive(Clone)]
+pub enum ControlCommand {
+    /// Pauses a running cpu or resumes a paused one
+    TogglePause,
+    /// Pauses a running cpu, a paused one stays paused
+    Pause,
+    /// Executes a single instruction while the cpu is paused
+    Step,
+    /// Executes the instructions of a single 60 Hz timer frame while the cpu is paused
+    StepFrame,
+    /// Like `Step`, but a subroutine call is executed until it returned
+    StepOver,
+    /// Runs until the current subroutine returned
+    StepOut,
+    /// Runs until the display is cleared or a sprite is drawn
+    RunUntilDisplayChange,
+    /// Raises or lowers the volume by the given amount
+    ChangeVolume(f32),
+    ToggleMute,
+    /// Resets the cpu and runs the program, a hard reset also clears the memory like switching the machine off and on
+    Reset {
+        program: Vec<u8>,
+        hard: bool,
+    },
+    /// Command entered in the debugger console
+    Debug(DebugCommand),
+    /// Stops the cpu thread
+    Shutdown,
+}
+
+/// Events sent from the cpu thread back to the window thread
+pub enum CpuEvent {
+    /// The cpu stopped executing, either on request or because a breakpoint was hit
+    Paused(CpuState),
+    Resumed,
+    /// Instructions and timer frames per second measured over the last second of running
+    Rates {
+        instructions_per_second: f64,
+        timer_frames_per_second: f64,
+    },
+}
+
+pub type ControlSender = std::sync::mpsc::Sender<ControlCommand>;
+pub type ControlReceiver = std::sync::mpsc::Receiver<ControlCommand>;
+pub type CpuEventSender = std::sync::mpsc::Sender<CpuEvent>;
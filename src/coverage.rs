@@ -0,0 +1,94 @@
+use std::fmt::Write;
+use std::fs;
+
+use anyhow::{anyhow, Result};
+
+use crate::memory::PROGRAM_START_ADDRESS;
+
+/// Records which instruction addresses were executed at least once, to find dead code in a rom
+/// or to check whether a routine was reached
+pub struct Coverage {
+    path: String,
+    /// one entry per 2-byte aligned address, an instruction at an odd address marks the entry before it
+    executed: Vec<bool>,
+    /// number of bytes of the loaded program, the coverage is reported for this range
+    program_length: usize,
+}
+
+impl Coverage {
+    pub fn new(path: String, memory_size: usize, program_length: usize) -> Self {
+        return Self {
+            path,
+            executed: vec![false; memory_size.div_ceil(2)],
+            program_length,
+        };
+    }
+
+    pub fn record(&mut self, address: u16) {
+        self.executed[address as usize / 2] = true;
+    }
+
+    /// Whether the instruction at the 2-byte aligned address was executed
+    pub fn is_executed(&self, address: u16) -> bool {
+        return self.executed[address as usize / 2];
+    }
+
+    /// The 2-byte aligned addresses of the program
+    fn program_addresses(&self) -> impl Iterator<Item = u16> {
+        let end = (PROGRAM_START_ADDRESS as usize + self.program_length)
+            .min(self.executed.len() * 2) as u16;
+        return (PROGRAM_START_ADDRESS..end).step_by(2);
+    }
+
+    /// Share of the program words that were executed, between 0.0 and 100.0
+    pub fn percentage(&self) -> f64 {
+        let total = self.program_addresses().count();
+        if total == 0 {
+            return 0.0;
+        }
+        let executed = self
+            .program_addresses()
+            .filter(|address| self.is_executed(*address))
+            .count();
+        return executed as f64 * 100.0 / total as f64;
+    }
+
+    /// The program as ranges of executed and not executed addresses, one range per line like
+    /// `0x200-0x20e executed`, both ends inclusive
+    pub fn listing(&self) -> String {
+        let mut listing = String::new();
+        let mut range: Option<(u16, u16, bool)> = None;
+        for address in self.program_addresses() {
+            let executed = self.is_executed(address);
+            match range.as_mut() {
+                Some((_, end, range_executed)) if *range_executed == executed => *end = address,
+                _ => {
+                    if let Some(finished) = range.replace((address, address, executed)) {
+                        write_range(&mut listing, finished);
+                    }
+                }
+            }
+        }
+        if let Some(finished) = range {
+            write_range(&mut listing, finished);
+        }
+        return listing;
+    }
+
+    /// Writes the percentage followed by the listing
+    pub fn write(&self) -> Result<()> {
+        let report = format!("{:.1}% executed\n{}", self.percentage(), self.listing());
+        return fs::write(&self.path, report)
+            .map_err(|e| anyhow!("Failed to write coverage to '{}': {}", self.path, e));
+    }
+}
+
+fn write_range(listing: &mut String, (start, end, executed): (u16, u16, bool)) {
+    let _ = writeln!(
+        listing,
+        "{:#05x}-{:#05x} {}",
+        start,
+        end,
+        if executed { "executed" } else { "not executed" }
+    );
+}
@@ -1,18 +1,36 @@
 use std::borrow::BorrowMut;
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::thread;
 use std::time::Instant;
 
-use tracing::debug;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, trace};
 use u4::{U4x2, U4};
 
 use crate::audio::Audio;
+use crate::call_trace::CallTrace;
+use crate::compatibility::{CompatibilityReport, Observation};
+use crate::coverage::Coverage;
+use crate::display::Display;
 use crate::instruction::Instruction;
 use crate::keyboard::Keyboard;
-use crate::memory::Memory;
+use crate::memory::{
+    Memory, DEFAULT_FONT_BASE, DEFAULT_MEMORY_SIZE, FONT_CHARACTER_SIZE, PROGRAM_START_ADDRESS,
+};
+use crate::opcode::Opcode;
+use crate::profile::Profile;
 use crate::program_counter::ProgramCounter;
+use crate::quirks::Quirks;
+use crate::random::{OsRandom, RandomSource, SeededRandom};
 use crate::renderer::Renderer;
+use crate::timing::{vip_instruction_micros, FRAME_DURATION, FRAME_MICROS};
 
 const CARRY_REG_ADDRESS: usize = 0xF;
 
+#[derive(Clone)]
 struct Registers {
     /// 16 general purpose 8-bit registers, usually referred to as Vx, where x is a hexadecimal digit (0 through F)
     general_registers: [u8; 16],
@@ -23,28 +41,301 @@ struct Registers {
     /// When register is non-zero, they are automatically decremented at a rate of 60Hz
     sound_timer: u8,
     program_counter: ProgramCounter,
-    /// points to topmost level of the stack
-    stack_pointer: Option<u8>,
 }
 
+impl Registers {
+    /// Value of the general register Vx
+    fn reg(&self, x: usize) -> u8 {
+        return self.general_registers[x];
+    }
+
+    fn set_reg(&mut self, x: usize, value: u8) {
+        self.general_registers[x] = value;
+    }
+}
+
+/// Progress of a Fx0A instruction, which spans multiple cycles
+#[derive(Clone)]
+enum KeyWait {
+    /// waiting for any key to be pressed, which is stored in the register
+    Press(usize),
+    /// the key was stored in Vx, waiting for the same key to be released
+    Release(U4),
+}
+
+/// Why an instruction wrote VF, for tracing flag related bugs
+#[derive(Clone, Copy)]
+enum FlagCause {
+    Carry,
+    Borrow,
+    ShiftOut,
+    Collision,
+    /// 8xy1, 8xy2 and 8xy3 reset VF
+    LogicReset,
+    /// VF was the target register of the instruction
+    RegisterWrite,
+}
+
+impl fmt::Display for FlagCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cause = match self {
+            FlagCause::Carry => "carry",
+            FlagCause::Borrow => "borrow",
+            FlagCause::ShiftOut => "shift-out",
+            FlagCause::Collision => "collision",
+            FlagCause::LogicReset => "logic reset",
+            FlagCause::RegisterWrite => "register write",
+        };
+        return write!(f, "{}", cause);
+    }
+}
+
+/// Errors that stop the execution of a rom
+#[derive(Debug)]
+pub enum CpuError {
+    /// An instruction accesses memory from `I` on beyond the end of memory
+    MemoryOutOfBounds {
+        instruction: &'static str,
+        address: u16,
+        last_address: usize,
+        memory_size: usize,
+    },
+    /// The instruction at the program counter crosses the end of memory
+    FetchOutOfBounds { address: u16, memory_size: usize },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            CpuError::MemoryOutOfBounds {
+                instruction,
+                address,
+                last_address,
+                memory_size,
+            } => write!(
+                f,
+                "{} at {:#05x} accesses memory up to {:#x}, beyond the {} bytes of memory",
+                instruction, address, last_address, memory_size
+            ),
+            CpuError::FetchOutOfBounds {
+                address,
+                memory_size,
+            } => write!(
+                f,
+                "Instruction at {:#05x} crosses the end of the {} bytes of memory",
+                address, memory_size
+            ),
+        };
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+pub const DEFAULT_STACK_SIZE: usize = 16;
+/// Deeper nesting than this is a runaway recursion rather than a rom that needs it
+pub const MAX_STACK_SIZE: usize = 1024;
+/// Number of instructions kept for crash dumps
+const RECENT_INSTRUCTION_COUNT: usize = 32;
+/// Instructions executed per 60 Hz frame when stepping frame by frame or running headless,
+/// roughly the speed of the original interpreter
+pub const INSTRUCTIONS_PER_FRAME: usize = 11;
+
+/// Settings of the emulated machine that are fixed once the cpu is created
+#[derive(Clone, Copy)]
+pub struct CpuConfig {
+    pub memory_size: usize,
+    /// maximum number of nested subroutine calls
+    pub stack_size: usize,
+    pub quirks: Quirks,
+    /// address the font is stored at
+    pub font_base: u16,
+    /// paces the emulation by the approximate instruction durations of the COSMAC VIP
+    pub vip_timing: bool,
+    /// plays the beep, disabling it never opens the audio device
+    pub audio: bool,
+    /// decodes the color instructions of CHIP-8X and shows the display in color zones
+    pub chip8x: bool,
+}
+
+impl Default for CpuConfig {
+    fn default() -> Self {
+        return Self {
+            memory_size: DEFAULT_MEMORY_SIZE,
+            stack_size: DEFAULT_STACK_SIZE,
+            quirks: Quirks::default(),
+            font_base: DEFAULT_FONT_BASE,
+            vip_timing: false,
+            audio: true,
+            chip8x: false,
+        };
+    }
+}
+
+/// Snapshot of the cpu state, used to inspect the emulator from outside the cpu thread
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct CpuState {
+    pub general_registers: [u8; 16],
+    pub i: u16,
+    pub program_counter: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    /// return addresses of the active subroutine calls, the innermost call is last
+    pub stack: Vec<u16>,
+    /// the bytes of the instruction that is executed next
+    pub next_instruction: [u8; 2],
+    /// one hexadecimal string per display row, where each bit is a pixel and the most significant bit is the left most pixel.
+    /// Rows with a width that is not a multiple of 4 are padded with unset pixels.
+    pub display: Vec<String>,
+}
+
+impl fmt::Display for CpuState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "PC={:#05x} [{:02x}{:02x}] I={:#05x} DT={:02x} ST={:02x} SP={} V=",
+            self.program_counter,
+            self.next_instruction[0],
+            self.next_instruction[1],
+            self.i,
+            self.delay_timer,
+            self.sound_timer,
+            self.stack.len(),
+        )?;
+        for register in self.general_registers.iter() {
+            write!(f, "{:02x} ", register)?;
+        }
+        return Ok(());
+    }
+}
+
+/// Complete state of the emulated machine, which can be restored to go back in time
+pub struct Snapshot {
+    registers: Registers,
+    stack: Vec<u16>,
+    memory: Vec<u8>,
+    display: Display,
+    key_wait: Option<KeyWait>,
+    frame_micros: u32,
+}
+
+impl Snapshot {
+    pub fn program_counter(&self) -> u16 {
+        return self.registers.program_counter.address();
+    }
+}
+
+/// Copy of the memory only, to find out which bytes a rom changes over time
+pub struct MemorySnapshot {
+    memory: Vec<u8>,
+}
+
+/// The whole cpu state in a comparable form for tests, with the memory and the display reduced to hashes.
+/// The `Debug` output has one field per line in hexadecimal, so a failing `assert_eq!` shows which fields differ.
+#[cfg(test)]
+#[derive(Clone, PartialEq, Eq)]
+pub struct StateSnapshot {
+    pub registers: [u8; 16],
+    pub i: u16,
+    pub program_counter: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub stack: Vec<u16>,
+    pub memory_hash: u64,
+    pub display_hash: u64,
+}
+
+#[cfg(test)]
+impl fmt::Debug for StateSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f)?;
+        for (x, value) in self.registers.iter().enumerate() {
+            writeln!(f, "  V{:X} = {:#04x}", x, value)?;
+        }
+        writeln!(f, "  I  = {:#05x}", self.i)?;
+        writeln!(f, "  PC = {:#05x}", self.program_counter)?;
+        writeln!(f, "  DT = {:#04x}", self.delay_timer)?;
+        writeln!(f, "  ST = {:#04x}", self.sound_timer)?;
+        writeln!(f, "  stack = {:#05x?}", self.stack)?;
+        writeln!(f, "  memory hash = {:016x}", self.memory_hash)?;
+        writeln!(f, "  display hash = {:016x}", self.display_hash)?;
+        return Ok(());
+    }
+}
+
+/// Called when a sprite draw erases a pixel, with the sprite coordinates and the general registers after the draw
+pub type CollisionCallback = Box<dyn FnMut(u8, u8, &[u8; 16]) + Send>;
+
+/// What an instruction hook gets to see of an executed instruction
+pub struct InstructionContext<'a> {
+    /// address the instruction was fetched from
+    pub address: u16,
+    pub opcode: u16,
+    pub decoded: Opcode,
+    /// address of the instruction executed next
+    pub next_address: u16,
+    pub general_registers_before: [u8; 16],
+    /// general registers after the instruction
+    pub general_registers: &'a [u8; 16],
+    pub i_before: u16,
+    /// I after the instruction
+    pub i: u16,
+}
+
+/// Called after every executed instruction, e.g. for tracing or coverage tools
+pub type InstructionHook = Box<dyn FnMut(&InstructionContext) + Send>;
+
 pub struct Cpu {
     registers: Registers,
-    /// an array of 16 16-bit values, used to store the address that the interpreter should return to when finished with a subroutine
-    stack: [u16; 16],
+    /// 16-bit values, used to store the address that the interpreter should return to when finished with a subroutine.
+    /// The innermost call is last.
+    stack: Vec<u16>,
+    /// maximum number of entries on the stack
+    stack_size: usize,
 
     memory: Memory,
 
     renderer: Renderer,
 
     keyboard: Keyboard,
+    /// progress of the currently executing Fx0A instruction
+    key_wait: Option<KeyWait>,
 
     audio: Audio,
 
     time_since_timer_update: Option<Instant>,
+    vip_timing: bool,
+    chip8x: bool,
+    /// modeled execution time of the current frame when running with VIP timing
+    frame_micros: u32,
+
+    quirks: Quirks,
+
+    random_source: Box<dyn RandomSource>,
+
+    /// records subroutine calls when enabled
+    call_trace: Option<CallTrace>,
+    collision_callback: Option<CollisionCallback>,
+    instruction_hook: Option<InstructionHook>,
+    /// `(address, opcode)` of the last executed instructions, only recorded for crash dumps
+    recent_instructions: Option<VecDeque<(u16, u16)>>,
+    profile: Option<Profile>,
+    coverage: Option<Coverage>,
+    /// number of executed instructions since the start, for the rate meter
+    executed_instructions: u64,
+    /// number of 60 Hz timer frames processed since the start, for the rate meter
+    timer_frames: u64,
+    /// number of cycles in a row spent waiting for a key press, to stop runs without input that would wait forever
+    key_wait_cycles: u64,
+    /// why the executing instruction set VF as a flag
+    flag_cause: Option<FlagCause>,
+    compatibility_report: Option<CompatibilityReport>,
 }
 
 impl Cpu {
-    pub fn new(renderer: Renderer, keyboard: Keyboard) -> Cpu {
+    pub fn new(mut renderer: Renderer, keyboard: Keyboard, config: CpuConfig) -> Cpu {
+        if config.chip8x {
+            renderer.enable_color_zones();
+        }
         return Cpu {
             registers: Registers {
                 general_registers: [0; 16],
@@ -52,22 +343,447 @@ impl Cpu {
                 delay_timer: 0,
                 sound_timer: 0,
                 program_counter: ProgramCounter::new(),
-                stack_pointer: None,
             },
-            stack: [0; 16],
-            memory: Memory::new(),
+            stack: Vec::with_capacity(config.stack_size),
+            stack_size: config.stack_size,
+            memory: Memory::new(config.memory_size, config.font_base),
             renderer,
             keyboard,
+            key_wait: None,
             time_since_timer_update: None,
-            audio: Audio::new(),
+            vip_timing: config.vip_timing,
+            chip8x: config.chip8x,
+            frame_micros: 0,
+            audio: if config.audio {
+                Audio::new()
+            } else {
+                Audio::silent()
+            },
+            quirks: config.quirks,
+            random_source: Box::new(OsRandom),
+            call_trace: None,
+            collision_callback: None,
+            instruction_hook: None,
+            recent_instructions: None,
+            profile: None,
+            coverage: None,
+            executed_instructions: 0,
+            timer_frames: 0,
+            key_wait_cycles: 0,
+            flag_cause: None,
+            compatibility_report: None,
         };
     }
 
+    pub fn audio_mut(&mut self) -> &mut Audio {
+        return &mut self.audio;
+    }
+
+    pub fn set_random_source(&mut self, random_source: Box<dyn RandomSource>) {
+        self.random_source = random_source;
+    }
+
+    pub fn set_call_trace(&mut self, call_trace: CallTrace) {
+        self.call_trace = Some(call_trace);
+    }
+
+    pub fn set_profile(&mut self, profile: Profile) {
+        self.profile = Some(profile);
+    }
+
+    pub fn set_coverage(&mut self, coverage: Coverage) {
+        self.coverage = Some(coverage);
+    }
+
+    pub fn enable_compatibility_report(&mut self) {
+        self.compatibility_report = Some(CompatibilityReport::new());
+    }
+
+    pub fn compatibility_report(&self) -> Option<&CompatibilityReport> {
+        return self.compatibility_report.as_ref();
+    }
+
+    fn observe(&mut self, observation: Observation) {
+        if let Some(report) = self.compatibility_report.as_mut() {
+            report.record(observation);
+        }
+    }
+
+    pub fn set_collision_callback(&mut self, callback: CollisionCallback) {
+        self.collision_callback = Some(callback);
+    }
+
+    pub fn set_instruction_hook(&mut self, hook: InstructionHook) {
+        self.instruction_hook = Some(hook);
+    }
+
+    pub fn record_recent_instructions(&mut self) {
+        self.recent_instructions = Some(VecDeque::with_capacity(RECENT_INSTRUCTION_COUNT));
+    }
+
+    /// `(address, opcode)` of the last executed instructions, the most recent one is last
+    pub fn recent_instructions(&self) -> Vec<(u16, u16)> {
+        return self
+            .recent_instructions
+            .as_ref()
+            .map(|recent_instructions| recent_instructions.iter().cloned().collect())
+            .unwrap_or_default();
+    }
+
+    /// Writes the recorded call trace, if one was enabled
+    pub fn write_call_trace(&mut self) -> Result<()> {
+        if let Some(call_trace) = self.call_trace.as_mut() {
+            call_trace.write()?;
+        }
+        return Ok(());
+    }
+
+    /// Writes the opcode histogram, if profiling was enabled
+    pub fn write_profile(&self) -> Result<()> {
+        if let Some(profile) = self.profile.as_ref() {
+            profile.write()?;
+        }
+        return Ok(());
+    }
+
+    /// Writes the executed and not executed program ranges, if coverage was enabled
+    pub fn write_coverage(&self) -> Result<()> {
+        if let Some(coverage) = self.coverage.as_ref() {
+            coverage.write()?;
+        }
+        return Ok(());
+    }
+
     pub fn load_program_into_memory(&mut self, program: &[u8]) {
         self.memory.load_program(program)
     }
 
-    pub fn run_cycle(&mut self) {
+    /// Restarts the program like the reset switch of the COSMAC VIP: registers, stack, timers and display are cleared
+    /// and the program is loaded again, while the rest of the memory keeps its content, e.g. a high score a rom stored.
+    /// The configuration, the quirks and the debugging aids are kept.
+    pub fn reset(&mut self, program: &[u8]) {
+        self.registers = Registers {
+            general_registers: [0; 16],
+            i: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+            program_counter: ProgramCounter::new(),
+        };
+        self.stack.clear();
+        self.memory.load_program(program);
+        self.renderer.clear_display();
+        if self.chip8x {
+            self.renderer.enable_color_zones();
+        }
+        self.key_wait = None;
+        self.key_wait_cycles = 0;
+        self.frame_micros = 0;
+        self.audio.stop();
+        self.pause_timers();
+    }
+
+    /// Puts the cpu back into its power on state with the program loaded, e.g. to switch to another rom.
+    /// Unlike `reset`, the whole memory is cleared and the font is written again.
+    pub fn hard_reset(&mut self, program: &[u8]) {
+        self.memory.reset();
+        self.reset(program);
+    }
+
+    /// Fills the memory behind the program with a reproducible pattern, to test how a rom copes with garbage
+    pub fn fill_unused_memory_with_noise(&mut self, program_length: usize, seed: u64) {
+        self.memory.fill_with_noise(
+            PROGRAM_START_ADDRESS as usize + program_length,
+            &mut SeededRandom::new(seed),
+        );
+    }
+
+    /// Warns when an instruction is fetched from or Fx65 loads never written memory
+    pub fn track_uninitialized_reads(&mut self) {
+        self.memory.track_writes();
+    }
+
+    pub fn load_memory_image(&mut self, image: &[u8]) {
+        self.memory.load_image(image)
+    }
+
+    pub fn state(&self) -> CpuState {
+        // an instruction crossing the end of memory can not be fetched and is shown as zeros
+        let next_instruction = self.fetch().unwrap_or_default();
+        return CpuState {
+            general_registers: self.registers.general_registers,
+            i: self.registers.i,
+            program_counter: self.registers.program_counter.address(),
+            delay_timer: self.registers.delay_timer,
+            sound_timer: self.registers.sound_timer,
+            stack: self.call_stack(),
+            next_instruction,
+            display: self.display_rows_hex(),
+        };
+    }
+
+    /// The current display content, without waiting for the window to receive it
+    pub fn display(&self) -> &Display {
+        return self.renderer.display();
+    }
+
+    pub fn executed_instructions(&self) -> u64 {
+        return self.executed_instructions;
+    }
+
+    /// Whether an `Fx0A` is waiting for a key, the next cycle continues it instead of fetching a new instruction
+    pub fn is_waiting_for_key(&self) -> bool {
+        return self.key_wait.is_some();
+    }
+
+    /// Number of cycles in a row the cpu spent waiting for a key press so far
+    pub fn key_wait_cycles(&self) -> u64 {
+        return self.key_wait_cycles;
+    }
+
+    /// Counts the 60 Hz frames the timers were progressed by, whether or not they were running
+    pub fn timer_frames(&self) -> u64 {
+        return self.timer_frames;
+    }
+
+    pub fn font_table(&self) -> &[u8] {
+        return self.memory.font_table();
+    }
+
+    /// Whether the display can not be presented anymore, so running on is pointless
+    pub fn display_lost(&self) -> bool {
+        return self.renderer.display_lost();
+    }
+
+    /// Counts the clears and sprite draws
+    pub fn display_change_count(&self) -> u64 {
+        return self.renderer.change_count();
+    }
+
+    pub fn display_rows_hex(&self) -> Vec<String> {
+        return self
+            .display()
+            .rows()
+            .map(|row| {
+                row.chunks(4)
+                    .map(|pixels| {
+                        let nibble = pixels
+                            .iter()
+                            .enumerate()
+                            .fold(0u32, |nibble, (index, pixel)| {
+                                nibble | (*pixel as u32) << (3 - index)
+                            });
+                        char::from_digit(nibble, 16).expect("nibble is a hexadecimal digit")
+                    })
+                    .collect()
+            })
+            .collect();
+    }
+
+    /// Returns the start addresses of all occurrences of the byte pattern in memory
+    pub fn find_in_memory(&self, pattern: &[u8]) -> Vec<u16> {
+        return self.memory.find(pattern);
+    }
+
+    /// Hex dump of the memory region in which the addresses of the program counter and I are marked with `[PC]` and `[I]`
+    pub fn annotated_memory_dump(&self, start: u16, length: u16) -> String {
+        let markers = [
+            (self.registers.program_counter.address(), "PC"),
+            (self.registers.i, "I"),
+        ];
+        return self.memory.hex_dump(start, length, &markers);
+    }
+
+    #[cfg(test)]
+    pub fn state_snapshot(&self) -> StateSnapshot {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let mut memory_hasher = DefaultHasher::new();
+        self.memory.image().hash(&mut memory_hasher);
+        let mut display_hasher = DefaultHasher::new();
+        for row in self.display().rows() {
+            row.hash(&mut display_hasher);
+        }
+        return StateSnapshot {
+            registers: self.registers.general_registers,
+            i: self.registers.i,
+            program_counter: self.registers.program_counter.address(),
+            delay_timer: self.registers.delay_timer,
+            sound_timer: self.registers.sound_timer,
+            stack: self.call_stack(),
+            memory_hash: memory_hasher.finish(),
+            display_hash: display_hasher.finish(),
+        };
+    }
+
+    /// Return addresses of the active subroutine calls, the innermost call is last
+    pub fn call_stack(&self) -> Vec<u16> {
+        return self.stack.clone();
+    }
+
+    /// Value of the general register Vx
+    pub fn register(&self, x: usize) -> u8 {
+        return self.registers.reg(x);
+    }
+
+    pub fn i(&self) -> u16 {
+        return self.registers.i;
+    }
+
+    pub fn set_register(&mut self, x: usize, value: u8) -> Result<()> {
+        if x >= self.registers.general_registers.len() {
+            return Err(anyhow!("Register V{:X} does not exist", x));
+        }
+        self.registers.set_reg(x, value);
+        return Ok(());
+    }
+
+    pub fn set_i(&mut self, value: u16) -> Result<()> {
+        if value as usize >= self.memory.size() {
+            return Err(anyhow!(
+                "Address {:#05x} is outside of the {} bytes of memory",
+                value,
+                self.memory.size()
+            ));
+        }
+        self.registers.i = value;
+        return Ok(());
+    }
+
+    pub fn quirks(&self) -> &Quirks {
+        return &self.quirks;
+    }
+
+    /// Changes a quirk while the rom is running, it applies from the next instruction on
+    pub fn set_quirk(&mut self, name: &str, enabled: bool) -> Result<()> {
+        return self.quirks.set(name, enabled);
+    }
+
+    /// Continues execution at the address without reloading anything, e.g. to run a single subroutine.
+    /// The address has to lie in the program area, like the target of a jump.
+    pub fn set_program_counter(&mut self, address: u16) -> Result<()> {
+        if address < PROGRAM_START_ADDRESS || address as usize + 2 > self.memory.size() {
+            return Err(anyhow!(
+                "Address {:#05x} is outside of the program area from {:#05x} to {:#05x}",
+                address,
+                PROGRAM_START_ADDRESS,
+                self.memory.size() - 2
+            ));
+        }
+        // a pending key wait belongs to the instruction that is left
+        self.key_wait = None;
+        self.registers.program_counter.set_to_address(address);
+        return Ok(());
+    }
+
+    /// Writes the bytes to memory starting at the address, e.g. data that a rom expects outside of the program area
+    pub fn load_at(&mut self, address: u16, bytes: &[u8]) -> Result<()> {
+        if address as usize + bytes.len() > self.memory.size() {
+            return Err(anyhow!(
+                "{} bytes at {:#05x} do not fit into the {} bytes of memory",
+                bytes.len(),
+                address,
+                self.memory.size()
+            ));
+        }
+        self.memory.write_bytes(address, bytes);
+        return Ok(());
+    }
+
+    /// Writes a byte to memory. The bytes of the instruction that is executed next are only written when forced,
+    /// as changing them by accident is confusing.
+    pub fn poke_memory(&mut self, address: u16, value: u8, force: bool) -> Result<()> {
+        if address as usize >= self.memory.size() {
+            return Err(anyhow!(
+                "Address {:#05x} is outside of the {} bytes of memory",
+                address,
+                self.memory.size()
+            ));
+        }
+        let program_counter = self.registers.program_counter.address();
+        if !force && (address == program_counter || address == program_counter.wrapping_add(1)) {
+            return Err(anyhow!(
+                "Address {:#05x} belongs to the next instruction at {:#05x}, add 'force' to write it anyway",
+                address,
+                program_counter
+            ));
+        }
+        self.memory.write_byte(address, value);
+        return Ok(());
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        return self.registers.program_counter.address();
+    }
+
+    /// Reads the bytes of the instruction that is executed next without executing it.
+    /// Fails when the instruction would cross the end of memory.
+    pub fn fetch(&self) -> Result<[u8; 2], CpuError> {
+        let address = self.registers.program_counter.address();
+        if address as usize + 2 > self.memory.size() {
+            return Err(CpuError::FetchOutOfBounds {
+                address,
+                memory_size: self.memory.size(),
+            });
+        }
+        let bytes = self.memory.read_bytes(address, 2);
+        return Ok([bytes[0], bytes[1]]);
+    }
+
+    /// The opcode of the instruction that is executed next
+    pub fn peek_next_opcode(&self) -> Result<u16, CpuError> {
+        return Ok(u16::from_be_bytes(self.fetch()?));
+    }
+
+    pub fn snapshot_memory(&self) -> MemorySnapshot {
+        return MemorySnapshot {
+            memory: self.memory.image(),
+        };
+    }
+
+    /// Every byte that differs from the snapshot as `(address, old value, new value)`, in address order
+    pub fn diff_memory(&self, snapshot: &MemorySnapshot) -> Vec<(u16, u8, u8)> {
+        return snapshot
+            .memory
+            .iter()
+            .zip(self.memory.image())
+            .enumerate()
+            .filter(|(_, (old, new))| *old != new)
+            .map(|(address, (old, new))| (address as u16, *old, new))
+            .collect();
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        return Snapshot {
+            registers: self.registers.clone(),
+            stack: self.stack.clone(),
+            memory: self.memory.image(),
+            display: self.display().clone(),
+            key_wait: self.key_wait.clone(),
+            frame_micros: self.frame_micros,
+        };
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.registers = snapshot.registers.clone();
+        self.stack = snapshot.stack.clone();
+        self.memory.load_image(&snapshot.memory);
+        self.renderer.restore_display(snapshot.display.clone());
+        self.key_wait = snapshot.key_wait.clone();
+        self.frame_micros = snapshot.frame_micros;
+        // the timers continue from the restored values instead of catching up on the time in between
+        self.pause_timers();
+    }
+
+    /// Restarts the timer measurement, so the time spent while paused does not count down the timers
+    pub fn pause_timers(&mut self) {
+        self.time_since_timer_update = None;
+    }
+
+    pub fn run_cycle(&mut self) -> Result<(), CpuError> {
+        if self.vip_timing {
+            return self.run_cycle_with_vip_timing();
+        }
         if self.time_since_timer_update.is_none() {
             self.time_since_timer_update = Some(Instant::now());
         }
@@ -82,22 +798,168 @@ impl Cpu {
             self.time_since_timer_update = Some(Instant::now());
         }
 
-        let mut instruction = [0, 0];
-        instruction.clone_from_slice(
-            self.memory
-                .read_bytes(self.registers.program_counter.address(), 2),
-        );
-        self.evaluate_instructions(&instruction);
+        return self.execute_next_instruction();
+    }
+
+    /// Runs cycles until either `max_cycles` were executed or the deadline passed and returns the number of executed cycles.
+    /// Returning regularly lets the caller poll for input in between.
+    pub fn run_batch(&mut self, max_cycles: usize, deadline: Instant) -> Result<usize, CpuError> {
+        for executed in 0..max_cycles {
+            if Instant::now() >= deadline {
+                return Ok(executed);
+            }
+            self.run_cycle()?;
+        }
+        return Ok(max_cycles);
+    }
+
+    /// Adds up the modeled durations of the executed instructions and waits at the end of every frame,
+    /// so the emulation runs as fast as on the COSMAC VIP
+    fn run_cycle_with_vip_timing(&mut self) -> Result<(), CpuError> {
+        let frame_start = *self
+            .time_since_timer_update
+            .get_or_insert_with(Instant::now);
+
+        if let Some(opcode) = self
+            .peek_next_opcode()
+            .ok()
+            .and_then(|opcode| self.decode(opcode))
+        {
+            self.frame_micros += vip_instruction_micros(&opcode);
+        }
+        self.execute_next_instruction()?;
+
+        if self.frame_micros >= FRAME_MICROS {
+            // long instructions like large sprite draws can take longer than a frame
+            let elapsed_frames = self.frame_micros / FRAME_MICROS;
+            self.frame_micros %= FRAME_MICROS;
+            self.progress_timer_registers(elapsed_frames as u128);
+
+            let frame_end = frame_start + FRAME_DURATION * elapsed_frames;
+            if let Some(remaining) = frame_end.checked_duration_since(Instant::now()) {
+                thread::sleep(remaining);
+            }
+            self.time_since_timer_update = Some(frame_end);
+        }
+        return Ok(());
+    }
+
+    /// Runs the instructions of a single 60 Hz frame and then decrements the timers exactly once,
+    /// independent of the wall clock
+    pub fn run_frame(&mut self) -> Result<(), CpuError> {
+        for _ in 0..INSTRUCTIONS_PER_FRAME {
+            self.execute_next_instruction()?;
+        }
+        self.progress_timer_registers(1);
+        return Ok(());
+    }
+
+    /// Executes the next instruction without updating the timers
+    pub fn execute_next_instruction(&mut self) -> Result<(), CpuError> {
+        if let Some(key_wait) = self.key_wait.take() {
+            // a waiting Fx0A is not executed again, only the keyboard is checked while the timers keep running
+            self.key_wait_cycles += 1;
+            self.continue_key_wait(key_wait);
+            return Ok(());
+        }
+        // nothing changes when the instruction can not be fetched
+        let address = self.registers.program_counter.address();
+        let instruction = self.fetch()?;
+        self.key_wait_cycles = 0;
+        self.executed_instructions += 1;
+        if let Some(call_trace) = self.call_trace.as_mut() {
+            call_trace.record_instruction();
+        }
+        self.memory
+            .warn_uninitialized_reads(address, 2, "Instruction fetch");
+        let opcode = u16::from_be_bytes(instruction);
+        if let Some(recent_instructions) = self.recent_instructions.as_mut() {
+            if recent_instructions.len() == RECENT_INSTRUCTION_COUNT {
+                recent_instructions.pop_front();
+            }
+            recent_instructions.push_back((address, opcode));
+        }
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.record(address);
+        }
+        if let Some(profile) = self.profile.as_mut() {
+            // unknown instructions panic below
+            if let Some(decoded) = decode(opcode, self.chip8x) {
+                profile.record_instruction(decoded);
+            }
+        }
+        let vf_before = self.registers.reg(CARRY_REG_ADDRESS);
+        // only copied for the hook, so running without one costs nothing
+        let registers_before = self
+            .instruction_hook
+            .is_some()
+            .then(|| self.registers.clone());
+        self.evaluate_instructions(&instruction)?;
+        self.trace_vf_write(address, opcode, vf_before);
+        if let (Some(hook), Some(registers_before)) =
+            (self.instruction_hook.as_mut(), registers_before)
+        {
+            // unknown instructions panicked above
+            if let Some(decoded) = decode(opcode, self.chip8x) {
+                hook(&InstructionContext {
+                    address,
+                    opcode,
+                    decoded,
+                    next_address: self.registers.program_counter.address(),
+                    general_registers_before: registers_before.general_registers,
+                    general_registers: &self.registers.general_registers,
+                    i_before: registers_before.i,
+                    i: self.registers.i,
+                });
+            }
+        }
+        return Ok(());
+    }
+
+    /// Logs at the trace level when the instruction set VF as a flag or changed it as a general register
+    fn trace_vf_write(&mut self, address: u16, opcode: u16, vf_before: u8) {
+        let vf = self.registers.reg(CARRY_REG_ADDRESS);
+        let cause = self
+            .flag_cause
+            .take()
+            .or((vf != vf_before).then_some(FlagCause::RegisterWrite));
+        if let Some(cause) = cause {
+            trace!(
+                "VF = {:#04x} by {:04x} at {:#05x}: {}",
+                vf,
+                opcode,
+                address,
+                cause
+            );
+        }
+    }
+
+    /// Writes VF as a flag, every instruction that uses VF as a flag has to go through here
+    fn set_flag(&mut self, value: u8, cause: FlagCause) {
+        self.registers.set_reg(CARRY_REG_ADDRESS, value);
+        self.flag_cause = Some(cause);
     }
 
     fn progress_timer_registers(&mut self, elapsed_frames: u128) {
+        self.timer_frames += elapsed_frames as u64;
+        if let Some(profile) = self.profile.as_mut() {
+            profile.record_frames(elapsed_frames as u64);
+        }
+        self.renderer
+            .decay_collision_highlights(elapsed_frames.min(u8::MAX as u128) as u8);
         if self.registers.delay_timer > 0 {
             self.registers.delay_timer = self
                 .registers
                 .delay_timer
                 .saturating_sub(elapsed_frames as u8);
         }
-        if self.registers.sound_timer > 0 {
+        if self.audio.timer_gated() {
+            self.registers.sound_timer = self
+                .registers
+                .sound_timer
+                .saturating_sub(elapsed_frames as u8);
+            self.audio.set_gate(self.registers.sound_timer > 0);
+        } else if self.registers.sound_timer > 0 {
             self.audio.play(self.registers.sound_timer);
             self.registers.sound_timer = self
                 .registers
@@ -108,86 +970,141 @@ impl Cpu {
         }
     }
 
-    fn evaluate_instructions(&mut self, instruction_bytes: &[u8; 2]) {
+    fn evaluate_instructions(&mut self, instruction_bytes: &[u8; 2]) -> Result<(), CpuError> {
         let instruction = Instruction::new(instruction_bytes);
 
         debug!("Evaluating instruction: {}", instruction);
 
-        let nibbles = instruction.nibbles_lo();
-        match nibbles {
-            (0x0, 0x0, 0x0, 0x0) => self.ignore_instruction(),
-            (0x0, 0x0, 0xE, 0x0) => self.exec_clear_display(&instruction),
-            (0x0, 0x0, 0xE, 0xE) => self.exec_return_from_subroutine(&instruction),
+        let opcode = u16::from_be_bytes(*instruction_bytes);
+        match self.decode(opcode) {
+            Some(Opcode::Nop) => self.ignore_instruction(),
+            Some(Opcode::ClearDisplay) => self.exec_clear_display(&instruction),
+            Some(Opcode::Return) => self.exec_return_from_subroutine(&instruction),
 
-            (0x1, _, _, _) => self.exec_jump(&instruction),
+            Some(Opcode::Jump { .. }) => self.exec_jump(&instruction),
 
-            (0x2, _, _, _) => self.exec_call_subroutine(&instruction),
+            Some(Opcode::Call { .. }) => self.exec_call_subroutine(&instruction),
 
-            (0x3, _, _, _) => self.exec_skip_if_equal_kk(&instruction),
+            Some(Opcode::SkipIfEqualKk { .. }) => self.exec_skip_if_equal_kk(&instruction),
 
-            (0x4, _, _, _) => self.exec_skip_if_not_equal_kk(&instruction),
+            Some(Opcode::SkipIfNotEqualKk { .. }) => self.exec_skip_if_not_equal_kk(&instruction),
 
-            (0x5, _, _, _) => self.exec_skip_if_equal_register(&instruction),
+            Some(Opcode::SkipIfEqualRegister { .. }) => {
+                self.exec_skip_if_equal_register(&instruction)
+            }
 
-            (0x6, _, _, _) => self.exec_set_register(&instruction),
+            Some(Opcode::SetRegister { .. }) => self.exec_set_register(&instruction),
 
-            (0x7, _, _, _) => self.exec_add_kk(&instruction),
+            Some(Opcode::AddKk { .. }) => self.exec_add_kk(&instruction),
 
-            (0x8, _, _, 0x0) => self.exec_copy_register_value(&instruction),
-            (0x8, _, _, 0x2) => self.exec_and(&instruction),
-            (0x8, _, _, 0x1) => self.exec_or(&instruction),
-            (0x8, _, _, 0x3) => self.exec_xor(&instruction),
-            (0x8, _, _, 0x4) => self.exec_add(&instruction),
-            (0x8, _, _, 0x5) => self.exec_sub(&instruction),
-            (0x8, _, _, 0x6) => self.exec_shift_right(&instruction),
-            (0x8, _, _, 0x7) => self.exec_subn(&instruction),
-            (0x8, _, _, 0xE) => self.exec_shift_left(&instruction),
+            Some(Opcode::CopyRegister { .. }) => self.exec_copy_register_value(&instruction),
+            Some(Opcode::And { .. }) => self.exec_and(&instruction),
+            Some(Opcode::Or { .. }) => self.exec_or(&instruction),
+            Some(Opcode::Xor { .. }) => self.exec_xor(&instruction),
+            Some(Opcode::Add { .. }) => self.exec_add(&instruction),
+            Some(Opcode::Sub { .. }) => self.exec_sub(&instruction),
+            Some(Opcode::ShiftRight { .. }) => self.exec_shift_right(&instruction),
+            Some(Opcode::Subn { .. }) => self.exec_subn(&instruction),
+            Some(Opcode::ShiftLeft { .. }) => self.exec_shift_left(&instruction),
 
-            (0x9, _, _, _) => self.exec_skip_if_not_equal_register(&instruction),
+            Some(Opcode::SkipIfNotEqualRegister { .. }) => {
+                self.exec_skip_if_not_equal_register(&instruction)
+            }
 
-            (0xA, _, _, _) => self.exec_set_register_i_to_nnn(&instruction),
+            Some(Opcode::SetI { .. }) => self.exec_set_register_i_to_nnn(&instruction),
 
-            (0xB, _, _, _) => self.exec_move_program_counter(&instruction),
+            Some(Opcode::JumpV0 { .. }) => self.exec_move_program_counter(&instruction),
 
-            (0xC, _, _, _) => self.exec_generate_random_number(&instruction),
+            Some(Opcode::Random { .. }) => self.exec_generate_random_number(&instruction),
 
-            (0xD, _, _, 0x0) => self.ignore_instruction(),
-            (0xD, _, _, _) => self.exec_display_sprite_8xN(&instruction),
+            Some(Opcode::DrawSprite { n: 0, .. }) if self.quirks.large_sprite_dxy0 => {
+                self.observe(Observation::LargeSpriteDxy0);
+                self.exec_display_sprite_16x16(&instruction)?
+            }
+            Some(Opcode::DrawSprite { n: 0, .. }) => {
+                self.observe(Observation::EmptySpriteDxy0);
+                self.exec_display_sprite_8xN(&instruction)?
+            }
+            Some(Opcode::DrawSprite { .. }) => self.exec_display_sprite_8xN(&instruction)?,
 
-            (0xE, _, 0x9, 0xE) => self.exec_skip_if_key_pressed(&instruction),
-            (0xE, _, 0xA, 0x1) => self.exec_skip_if_key_not_pressed(&instruction),
+            Some(Opcode::SkipIfKeyPressed { .. }) => self.exec_skip_if_key_pressed(&instruction),
+            Some(Opcode::SkipIfKeyNotPressed { .. }) => {
+                self.exec_skip_if_key_not_pressed(&instruction)
+            }
 
-            (0xF, _, 0x0, 0x7) => self.exec_set_vx_to_delay_timer(&instruction),
-            (0xF, _, 0x0, 0xA) => self.exec_wait_until_key_press(&instruction),
-            (0xF, _, 0x1, 0x5) => self.exec_set_delay_timer(&instruction),
-            (0xF, _, 0x1, 0x8) => self.exec_set_sound_timer(&instruction),
-            (0xF, _, 0x1, 0xE) => self.exec_add_vx_to_i(&instruction),
+            Some(Opcode::LoadDelayTimer { .. }) => self.exec_set_vx_to_delay_timer(&instruction),
+            Some(Opcode::WaitForKey { .. }) => self.exec_wait_until_key_press(&instruction),
+            Some(Opcode::SetDelayTimer { .. }) => self.exec_set_delay_timer(&instruction),
+            Some(Opcode::SetSoundTimer { .. }) => self.exec_set_sound_timer(&instruction),
+            Some(Opcode::AddI { .. }) => self.exec_add_vx_to_i(&instruction),
 
-            (0xF, _, 0x2, _) => self.exec_set_i_to_sprite_address(&instruction),
-            (0xF, _, 0x3, _) => self.exec_store_vx_as_bsd_in_memory(&instruction),
-            (0xF, _, 0x5, 0x5) => self.exec_store_registers_in_memory(&instruction),
-            (0xF, _, 0x6, 0x5) => self.exec_load_registers_from_memory(&instruction),
-            _ => panic!("unexpected instruction"),
+            Some(Opcode::SetIToSprite { .. }) => self.exec_set_i_to_sprite_address(&instruction),
+            Some(Opcode::StoreBcd { .. }) => self.exec_store_vx_as_bsd_in_memory(&instruction)?,
+            Some(Opcode::StoreRegisters { .. }) => {
+                self.exec_store_registers_in_memory(&instruction)?
+            }
+            Some(Opcode::LoadRegisters { .. }) => {
+                self.exec_load_registers_from_memory(&instruction)?
+            }
+            Some(Opcode::CycleBackgroundColor) => self.exec_cycle_background_color(&instruction),
+            Some(Opcode::AddNibbles { .. }) => self.exec_add_nibbles(&instruction),
+            Some(Opcode::SetForegroundColor { .. }) => self.exec_set_foreground_color(&instruction),
+            None => {
+                self.observe(Observation::UnknownOpcode(
+                    self.registers.program_counter.address(),
+                    opcode,
+                ));
+                panic!("unexpected instruction {}", instruction)
+            }
         };
+        return Ok(());
     }
 
     fn exec_return_from_subroutine(&mut self, _instruction: &Instruction) {
-        let stack_pointer = self
-            .registers
-            .stack_pointer
-            .expect("stack should not be empty");
-        let return_address = self
-            .stack
-            .get(stack_pointer as usize)
-            .expect("Stack entry should exist");
-        self.registers.stack_pointer = if stack_pointer == 0 {
-            None
-        } else {
-            Some(stack_pointer - 1)
-        };
+        if self.stack.is_empty() {
+            self.observe(Observation::StackUnderflow);
+        }
+        let return_address = self.stack.pop().expect("stack should not be empty");
+        if let Some(call_trace) = self.call_trace.as_mut() {
+            call_trace.exit();
+        }
         self.registers
             .program_counter
-            .set_to_address(*return_address);
+            .set_to_address(return_address);
+    }
+
+    /// Decodes the instruction for the emulated interpreter
+    fn decode(&self, opcode: u16) -> Option<Opcode> {
+        return decode(opcode, self.chip8x);
+    }
+
+    /// CHIP-8X: the background color of the whole screen switches to the next one of blue, black, green and red.
+    fn exec_cycle_background_color(&mut self, _instruction: &Instruction) {
+        self.renderer.cycle_background_color();
+        self.registers.program_counter.increment();
+    }
+
+    /// CHIP-8X: the nibbles of Vy are added to the nibbles of Vx, each modulo 8 without carry into the next nibble.
+    fn exec_add_nibbles(&mut self, instruction: &Instruction) {
+        let x = instruction.x() as usize;
+        let y = instruction.y() as usize;
+        let sum = ((self.registers.reg(x) & 0x77) + (self.registers.reg(y) & 0x77)) & 0x77;
+        self.registers.set_reg(x, sum);
+        self.registers.program_counter.increment();
+    }
+
+    /// CHIP-8X: the zones described by Vx and Vx+1 get the foreground color Vy.
+    /// The low nibble of Vx is the first zone column and the high nibble the number of further columns,
+    /// Vx+1 describes the zone rows the same way.
+    fn exec_set_foreground_color(&mut self, instruction: &Instruction) {
+        let x = instruction.x() as usize;
+        let y = instruction.y() as usize;
+        let horizontal = self.registers.reg(x);
+        let vertical = self.registers.reg((x + 1) % 16);
+        let color = self.registers.reg(y) & 0x7;
+        self.renderer
+            .set_zone_color(zone_range(horizontal), zone_range(vertical), color);
+        self.registers.program_counter.increment();
     }
 
     fn exec_clear_display(&mut self, _instruction: &Instruction) {
@@ -198,52 +1115,87 @@ impl Cpu {
     /// The value of delay timer register is placed into Vx.
     fn exec_set_vx_to_delay_timer(&mut self, instruction: &Instruction) {
         let x = instruction.x() as usize;
-        self.registers.general_registers[x] = self.registers.delay_timer;
+        self.registers.set_reg(x, self.registers.delay_timer);
         self.registers.program_counter.increment();
     }
 
     fn exec_skip_if_key_not_pressed(&mut self, instruction: &Instruction) {
         let x = instruction.x() as usize;
-        let vx = self.registers.general_registers[x];
-        if !self
+        let vx = self.registers.reg(x);
+        let pressed = self
             .keyboard
-            .is_key_pressed_or_held(&U4x2::from(vx).right())
-        {
-            self.registers.program_counter.skip_instruction();
-        } else {
-            self.registers.program_counter.increment();
-        }
+            .is_key_pressed_or_held(&U4x2::from(vx).right());
+        self.skip_if(
+            !pressed,
+            format_args!("key V{:X}({:#04x}) not pressed", x, vx),
+        );
     }
 
     fn exec_skip_if_key_pressed(&mut self, instruction: &Instruction) {
         let x = instruction.x() as usize;
-        let vx = self.registers.general_registers[x];
-        if self
+        let vx = self.registers.reg(x);
+        let pressed = self
             .keyboard
-            .is_key_pressed_or_held(&U4x2::from(vx).right())
-        {
-            self.registers.program_counter.skip_instruction();
-        } else {
-            self.registers.program_counter.increment();
-        }
+            .is_key_pressed_or_held(&U4x2::from(vx).right());
+        self.skip_if(pressed, format_args!("key V{:X}({:#04x}) pressed", x, vx));
     }
 
     #[allow(non_snake_case)]
     /// The interpreter reads n bytes from memory, starting at the address stored in I.
     /// These bytes are then displayed as sprites on screen at coordinates (Vx, Vy)
-    fn exec_display_sprite_8xN(&mut self, instruction: &Instruction) {
+    fn exec_display_sprite_8xN(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
         let x = instruction.x() as usize;
         let y = instruction.y() as usize;
-        let n = instruction.fourth_nibble();
+        let n = instruction.n();
+        // without the large sprite quirk, n = 0 draws no rows at all and only resets VF
+        return self.draw_sprite_at_i(x, y, n as u16, 1);
+    }
 
-        let vx = self.registers.general_registers[x];
-        let vy = self.registers.general_registers[y];
+    /// Dxy0 with the large sprite quirk, like SCHIP: draws a 16x16 sprite stored as 2 bytes per row starting at I
+    fn exec_display_sprite_16x16(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let x = instruction.x() as usize;
+        let y = instruction.y() as usize;
+        return self.draw_sprite_at_i(x, y, 32, 2);
+    }
+
+    /// Fails when the sprite crosses the end of memory, unless sprite reads wrap around
+    fn draw_sprite_at_i(
+        &mut self,
+        x: usize,
+        y: usize,
+        length: u16,
+        bytes_per_row: usize,
+    ) -> Result<(), CpuError> {
+        let vx = self.registers.reg(x);
+        let vy = self.registers.reg(y);
         let i = self.registers.i;
-        let sprite = self.memory.read_bytes(i, n as u16);
+        let wrapped_sprite;
+        let sprite = if !self.memory.crosses_end(i, length) {
+            self.memory.read_bytes(i, length)
+        } else if self.quirks.sprite_read_wraps {
+            self.observe(Observation::SpriteReadWrapped);
+            wrapped_sprite = self.memory.read_bytes_wrapping(i, length);
+            &wrapped_sprite
+        } else {
+            return Err(CpuError::MemoryOutOfBounds {
+                instruction: "Dxyn",
+                address: self.registers.program_counter.address(),
+                last_address: i as usize + length as usize - 1,
+                memory_size: self.memory.size(),
+            });
+        };
 
-        let pixel_erased = self.renderer.draw_sprite(sprite, vx, vy);
-        self.registers.general_registers[CARRY_REG_ADDRESS] = if pixel_erased { 1 } else { 0 };
+        let pixel_erased = self
+            .renderer
+            .draw_sprite(sprite, bytes_per_row, vx, vy, &self.quirks);
+        self.set_flag(if pixel_erased { 1 } else { 0 }, FlagCause::Collision);
+        if pixel_erased {
+            if let Some(callback) = self.collision_callback.as_mut() {
+                callback(vx, vy, &self.registers.general_registers);
+            }
+        }
         self.registers.program_counter.increment();
+        return Ok(());
     }
 
     /// The interpreter generates a random number from 0 to 255,
@@ -252,15 +1204,15 @@ impl Cpu {
     fn exec_generate_random_number(&mut self, instruction: &Instruction) {
         let kk = instruction.kk();
         let x = instruction.x() as usize;
-        let random_num: u8 = rand::random();
-        self.registers.general_registers[x] = random_num & kk;
+        let random_num = self.random_source.next_u8();
+        self.registers.set_reg(x, random_num & kk);
         self.registers.program_counter.increment();
     }
 
     /// The program counter is set to nnn plus the value of V0.
     fn exec_move_program_counter(&mut self, instruction: &Instruction) {
         let nnn = instruction.nnn();
-        let v0 = self.registers.general_registers[0];
+        let v0 = self.registers.reg(0);
         self.registers
             .program_counter
             .set_to_address(nnn + v0 as u16);
@@ -276,76 +1228,95 @@ impl Cpu {
     fn exec_skip_if_not_equal_register(&mut self, instruction: &Instruction) {
         let x = instruction.x() as usize;
         let y = instruction.y() as usize;
-        let vx = self.registers.general_registers[x];
-        let vy = self.registers.general_registers[y];
-        if vx != vy {
-            self.registers.program_counter.skip_instruction();
-        } else {
-            self.registers.program_counter.increment();
-        }
+        let vx = self.registers.reg(x);
+        let vy = self.registers.reg(y);
+        self.skip_if(
+            vx != vy,
+            format_args!("V{:X}({:#04x}) != V{:X}({:#04x})", x, vx, y, vy),
+        );
     }
     fn exec_skip_if_equal_register(&mut self, instruction: &Instruction) {
         let x = instruction.x() as usize;
         let y = instruction.y() as usize;
-        let vx = self.registers.general_registers[x];
-        let vy = self.registers.general_registers[y];
-        if vx == vy {
-            self.registers.program_counter.skip_instruction();
-        } else {
-            self.registers.program_counter.increment();
-        }
+        let vx = self.registers.reg(x);
+        let vy = self.registers.reg(y);
+        self.skip_if(
+            vx == vy,
+            format_args!("V{:X}({:#04x}) == V{:X}({:#04x})", x, vx, y, vy),
+        );
     }
 
-    /// Add byte kk to the register x. No carry flag is set in case of an overflow
+    /// Add byte kk to the register x. No carry flag is set in case of an overflow,
+    /// VF keeps its value even when the addition wraps around, e.g. from 0xFF.
+    /// Some documentation claims otherwise, but roms rely on VF being untouched.
     fn exec_add_kk(&mut self, instruction: &Instruction) {
         let x = instruction.x() as usize;
         let kk = instruction.kk();
-        let (result, _overflow) = self.registers.general_registers[x].overflowing_add(kk);
-        self.registers.general_registers[x] = result;
+        let vf_before = self.registers.reg(CARRY_REG_ADDRESS);
+        let result = self.registers.reg(x).wrapping_add(kk);
+        self.registers.set_reg(x, result);
+        debug_assert!(
+            x == CARRY_REG_ADDRESS || self.registers.reg(CARRY_REG_ADDRESS) == vf_before,
+            "7xkk must not change VF"
+        );
         self.registers.program_counter.increment();
     }
 
     fn exec_set_register(&mut self, instruction: &Instruction) {
         let register_address = instruction.x() as usize;
         let byte = instruction.kk();
-        self.registers.general_registers[register_address] = byte;
+        self.registers.set_reg(register_address, byte);
         self.registers.program_counter.increment();
     }
 
     fn exec_skip_if_not_equal_kk(&mut self, instruction: &Instruction) {
         let x = instruction.x() as usize;
         let kk = instruction.kk();
-
-        if self.registers.general_registers[x] != kk {
-            self.registers.program_counter.skip_instruction();
-        } else {
-            self.registers.program_counter.increment();
-        }
+        let vx = self.registers.reg(x);
+        self.skip_if(
+            vx != kk,
+            format_args!("V{:X}({:#04x}) != {:#04x}", x, vx, kk),
+        );
     }
 
     fn exec_skip_if_equal_kk(&mut self, instruction: &Instruction) {
-        let x = instruction.x();
+        let x = instruction.x() as usize;
         let kk = instruction.kk();
+        let vx = self.registers.reg(x);
+        self.skip_if(
+            vx == kk,
+            format_args!("V{:X}({:#04x}) == {:#04x}", x, vx, kk),
+        );
+    }
 
-        if self.registers.general_registers[x as usize] == kk {
+    /// Skips the next instruction when the condition holds and traces the decision with the compared values
+    fn skip_if(&mut self, condition: bool, comparison: fmt::Arguments) {
+        if condition {
+            trace!("SKIP taken: {}", comparison);
             self.registers.program_counter.skip_instruction();
         } else {
+            trace!("SKIP not taken: {}", comparison);
             self.registers.program_counter.increment();
         }
     }
 
     fn exec_call_subroutine(&mut self, instruction: &Instruction) {
-        self.registers.stack_pointer = if self.registers.stack_pointer.is_none() {
-            Some(0)
-        } else {
-            self.registers.stack_pointer.map(|p| p + 1)
-        };
+        if self.stack.len() >= self.stack_size {
+            self.observe(Observation::StackOverflow);
+        }
+        assert!(
+            self.stack.len() < self.stack_size,
+            "stack overflow, more than {} nested subroutine calls",
+            self.stack_size
+        );
         let return_address = self.registers.program_counter.peek();
-        self.stack[self.registers.stack_pointer.expect("Stack pointer exists") as usize] =
-            return_address;
+        self.stack.push(return_address);
 
         let address = instruction.nnn();
         self.registers.program_counter.set_to_address(address);
+        if let Some(call_trace) = self.call_trace.as_mut() {
+            call_trace.enter(address);
+        }
     }
 
     fn exec_jump(&mut self, instruction: &Instruction) {
@@ -357,7 +1328,8 @@ impl Cpu {
     fn exec_copy_register_value(&mut self, instruction: &Instruction) {
         let x = instruction.x();
         let y = instruction.y();
-        self.registers.general_registers[x as usize] = self.registers.general_registers[y as usize];
+        let vy = self.registers.reg(y as usize);
+        self.registers.set_reg(x as usize, vy);
         self.registers.program_counter.increment();
     }
 
@@ -367,9 +1339,9 @@ impl Cpu {
     fn exec_or(&mut self, instruction: &Instruction) {
         let x = instruction.x();
         let y = instruction.y();
-        self.registers.general_registers[x as usize] |=
-            self.registers.general_registers[y as usize];
-        self.registers.general_registers[CARRY_REG_ADDRESS] = 0;
+        let result = self.registers.reg(x as usize) | self.registers.reg(y as usize);
+        self.registers.set_reg(x as usize, result);
+        self.set_flag(0, FlagCause::LogicReset);
         self.registers.program_counter.increment();
     }
 
@@ -379,99 +1351,126 @@ impl Cpu {
     fn exec_and(&mut self, instruction: &Instruction) {
         let x = instruction.x();
         let y = instruction.y();
-        self.registers.general_registers[x as usize] &=
-            self.registers.general_registers[y as usize];
-        self.registers.general_registers[CARRY_REG_ADDRESS] = 0;
+        let result = self.registers.reg(x as usize) & self.registers.reg(y as usize);
+        self.registers.set_reg(x as usize, result);
+        self.set_flag(0, FlagCause::LogicReset);
         self.registers.program_counter.increment();
     }
 
     fn exec_xor(&mut self, instruction: &Instruction) {
         let x = instruction.x();
         let y = instruction.y();
-        self.registers.general_registers[x as usize] ^=
-            self.registers.general_registers[y as usize];
-        self.registers.general_registers[CARRY_REG_ADDRESS] = 0;
+        let result = self.registers.reg(x as usize) ^ self.registers.reg(y as usize);
+        self.registers.set_reg(x as usize, result);
+        self.set_flag(0, FlagCause::LogicReset);
         self.registers.program_counter.increment();
     }
 
     /// The values of Vx and Vy are added together. If the result is greater than 8 bits (i.e., > 255,) VF is set to 1,
     /// otherwise 0. Only the lowest 8 bits of the result are kept, and stored in Vx.
+    /// VF is written after Vx, so for `x == 0xF` VF ends up holding the carry and not the sum.
     fn exec_add(&mut self, instruction: &Instruction) {
         let x = instruction.x() as usize;
         let y = instruction.y() as usize;
-        let (result, overflow) = (self.registers.general_registers[x])
-            .overflowing_add(self.registers.general_registers[y]);
+        let (result, overflow) = self.registers.reg(x).overflowing_add(self.registers.reg(y));
 
-        self.registers.general_registers[x] = result;
-        self.registers.general_registers[CARRY_REG_ADDRESS] = if overflow { 1 } else { 0 };
+        self.registers.set_reg(x, result);
+        self.set_flag(if overflow { 1 } else { 0 }, FlagCause::Carry);
         self.registers.program_counter.increment();
     }
 
+    /// Vy is subtracted from Vx and the result is stored in Vx. VF is set to 0 when the subtraction borrows, otherwise 1.
+    /// VF is written after Vx, so for `x == 0xF` VF ends up holding the flag and not the difference.
     fn exec_sub(&mut self, instruction: &Instruction) {
         let x = instruction.x() as usize;
         let y = instruction.y() as usize;
-        let vx = self.registers.general_registers[x];
-        let vy = self.registers.general_registers[y];
+        let vx = self.registers.reg(x);
+        let vy = self.registers.reg(y);
 
         let (result, underflow) = vx.overflowing_sub(vy);
-        self.registers.general_registers[x] = result;
-        self.registers.general_registers[CARRY_REG_ADDRESS] = if underflow { 0 } else { 1 };
+        self.registers.set_reg(x, result);
+        self.set_flag(if underflow { 0 } else { 1 }, FlagCause::Borrow);
         self.registers.program_counter.increment();
     }
 
+    /// Vy is shifted right by one bit and the result is stored in Vx, VF is set to the bit that was shifted out.
+    /// Vy is read before anything is written, so `y == 0xF` shifts the old VF,
+    /// and VF is written after Vx, so for `x == 0xF` VF ends up holding the flag and not the result.
     fn exec_shift_right(&mut self, instruction: &Instruction) {
         let x = instruction.x() as usize;
         let y = instruction.y() as usize;
-        let vy = self.registers.general_registers[y];
+        let vy = self.registers.reg(y);
 
-        self.registers.general_registers[x] = vy >> 1;
-        self.registers.general_registers[CARRY_REG_ADDRESS] = vy % 2;
+        self.registers.set_reg(x, vy >> 1);
+        self.set_flag(vy % 2, FlagCause::ShiftOut);
         self.registers.program_counter.increment();
     }
 
+    /// Vx is subtracted from Vy and the result is stored in Vx. VF is set to 0 when the subtraction borrows, otherwise 1.
+    /// VF is written after Vx, so for `x == 0xF` VF ends up holding the flag and not the difference.
     fn exec_subn(&mut self, instruction: &Instruction) {
         let x = instruction.x() as usize;
         let y = instruction.y() as usize;
-        let vx = self.registers.general_registers[x];
-        let vy = self.registers.general_registers[y];
+        let vx = self.registers.reg(x);
+        let vy = self.registers.reg(y);
 
         let (result, underflow) = vy.overflowing_sub(vx);
-        self.registers.general_registers[x] = result;
-        self.registers.general_registers[CARRY_REG_ADDRESS] = if underflow { 0 } else { 1 };
+        self.registers.set_reg(x, result);
+        self.set_flag(if underflow { 0 } else { 1 }, FlagCause::Borrow);
         self.registers.program_counter.increment();
     }
 
+    /// Vy is shifted left by one bit and the result is stored in Vx, VF is set to the bit that was shifted out.
+    /// Vy is read before anything is written, so `y == 0xF` shifts the old VF,
+    /// and VF is written after Vx, so for `x == 0xF` VF ends up holding the flag and not the result.
     fn exec_shift_left(&mut self, instruction: &Instruction) {
         let x = instruction.x() as usize;
         let y = instruction.y() as usize;
-        let vy = self.registers.general_registers[y];
+        let vy = self.registers.reg(y);
 
-        self.registers.general_registers[x] = vy << 1;
-        self.registers.general_registers[CARRY_REG_ADDRESS] = if vy >= 128 { 1 } else { 0 };
+        self.registers.set_reg(x, vy << 1);
+        self.set_flag(if vy >= 128 { 1 } else { 0 }, FlagCause::ShiftOut);
         self.registers.program_counter.increment();
     }
 
     /// All execution stops until a key is pressed, then the value of that key is stored in Vx.
+    /// The instruction completes once that same key is released again.
+    /// A key counts as pressed when it is held as the instruction starts or gets pressed while waiting,
+    /// even if it is released again before the next cycle. Of several pressed keys the most recently pressed one is stored.
+    ///
+    /// The instruction does not block, instead the program counter stays on it until the key is released.
+    /// Until then the following cycles only check the keyboard instead of executing it again,
+    /// so the timers keep running, e.g. for a rom whose delay timer times out the wait,
+    /// and the cpu loop can still react to a shutdown.
     fn exec_wait_until_key_press(&mut self, instruction: &Instruction) {
-        let mut key_pressed: Option<U4> = None;
-        loop {
-            if let Some(key) = key_pressed {
-                if !self.keyboard.is_key_pressed_or_held(&key) {
-                    break;
+        self.keyboard.reset_latched_presses();
+        self.continue_key_wait(KeyWait::Press(instruction.x() as usize));
+    }
+
+    fn continue_key_wait(&mut self, key_wait: KeyWait) {
+        self.key_wait = match key_wait {
+            KeyWait::Press(x) => match self.keyboard.take_latched_press() {
+                Some(key) => {
+                    self.registers.set_reg(x, key as u8);
+                    Some(KeyWait::Release(key))
+                }
+                None => Some(KeyWait::Press(x)),
+            },
+            KeyWait::Release(key) => {
+                if self.keyboard.is_key_pressed_or_held(&key) {
+                    Some(KeyWait::Release(key))
+                } else {
+                    self.registers.program_counter.increment();
+                    None
                 }
-            } else if let Some(pressed_key) = self.keyboard.get_pressed_key() {
-                key_pressed = Some(pressed_key);
-                let x = instruction.x() as usize;
-                self.registers.general_registers[x] = pressed_key as u8;
             }
-        }
-        self.registers.program_counter.increment();
+        };
     }
 
     /// Delay timer is set equal to the value of Vx.
     fn exec_set_delay_timer(&mut self, instruction: &Instruction) {
         let x = instruction.x() as usize;
-        let vx = self.registers.general_registers[x];
+        let vx = self.registers.reg(x);
         self.registers.delay_timer = vx;
         self.registers.program_counter.increment();
     }
@@ -479,39 +1478,52 @@ impl Cpu {
     /// Sound timer is set equal to the value of Vx.
     fn exec_set_sound_timer(&mut self, instruction: &Instruction) {
         let x = instruction.x() as usize;
-        let vx = self.registers.general_registers[x];
+        let vx = self.registers.reg(x);
         self.registers.sound_timer = vx;
+        if self.audio.timer_gated() {
+            self.audio.set_gate(vx > 0);
+        }
         self.registers.program_counter.increment();
     }
 
     /// The values of I and Vx are added, and the results are stored in I.
     fn exec_add_vx_to_i(&mut self, instruction: &Instruction) {
         let x = instruction.x() as usize;
-        let vx = self.registers.general_registers[x];
-        self.registers.i += vx as u16;
+        let vx = self.registers.reg(x);
+        self.registers.i = self.registers.i.wrapping_add(vx as u16);
         self.registers.program_counter.increment();
     }
 
     /// The value of I is set to the location for the hexadecimal sprite corresponding to the value of Vx.
     /// See section 2.4, Display, for more information on the Chip-8 hexadecimal font.
+    /// Only the lowest nibble of Vx is used, as there are only 16 characters.
     fn exec_set_i_to_sprite_address(&mut self, instruction: &Instruction) {
         let x = instruction.x() as usize;
-        let vx = self.registers.general_registers[x];
-        let sprite_address = (vx * 5) as u16; // a sprite is 5 bytes in size
+        let vx = self.registers.reg(x);
+        let sprite_address = self.memory.font_base() + (vx & 0xF) as u16 * FONT_CHARACTER_SIZE;
         self.registers.i = sprite_address;
         self.registers.program_counter.increment();
     }
 
     /// Takes the decimal value of Vx, and places the hundreds digit in memory at location in I,
-    /// the tens digit at location I+1, and the ones digit at location I+2
-    fn exec_store_vx_as_bsd_in_memory(&mut self, instruction: &Instruction) {
+    /// the tens digit at location I+1, and the ones digit at location I+2.
+    /// Like Fx55, digits beyond the end of memory are an error unless memory accesses wrap around.
+    fn exec_store_vx_as_bsd_in_memory(
+        &mut self,
+        instruction: &Instruction,
+    ) -> Result<(), CpuError> {
         let x = instruction.x() as usize;
-        let vx = self.registers.general_registers[x];
+        let vx = self.registers.reg(x);
+
+        self.check_memory_access_bounds(2, "Fx33")?;
 
         let bcd_representation = [(vx / 100) % 10, (vx / 10) % 10, vx % 10];
-        self.memory
-            .write_bytes(self.registers.i, &bcd_representation);
+        for (index, digit) in bcd_representation.iter().enumerate() {
+            self.memory
+                .write_byte(self.registers.i.wrapping_add(index as u16), *digit);
+        }
         self.registers.program_counter.increment();
+        return Ok(());
     }
 
     ///  The value of each variable register from V0 to VX inclusive (if X is 0, then only V0)
@@ -520,14 +1532,23 @@ impl Cpu {
     ///
     ///  Chip-8 quirk: Each time it stored or loaded one register, it incremented I.
     ///  After the instruction was finished, I would end up being set to the new value I + X + 1.
-    fn exec_store_registers_in_memory(&mut self, instruction: &Instruction) {
-        let x = instruction.x();
+    ///
+    ///  Addresses beyond the end of memory are an error, unless the quirk lets them wrap around to the start of memory.
+    fn exec_store_registers_in_memory(
+        &mut self,
+        instruction: &Instruction,
+    ) -> Result<(), CpuError> {
+        let x = instruction.x() as usize;
+        self.check_memory_access_bounds(x, "Fx55")?;
 
-        let registers = self.registers.general_registers;
-        self.memory
-            .write_bytes(self.registers.i, &registers[0..=x as usize]);
-        self.registers.i += x as u16 + 1;
+        for index in 0..=x {
+            let value = self.registers.reg(index);
+            self.memory
+                .write_byte(self.registers.i.wrapping_add(index as u16), value);
+        }
+        self.registers.i = self.registers.i.wrapping_add(x as u16 + 1);
         self.registers.program_counter.increment();
+        return Ok(());
     }
 
     ///  Values from V0 to VX inclusive (if X is 0, then only V0)
@@ -536,18 +1557,836 @@ impl Cpu {
     ///
     ///  Chip-8 quirk: Each time it loaded one register, it incremented I.
     ///  After the instruction was finished, I would end up being set to the new value I + X + 1.
-    fn exec_load_registers_from_memory(&mut self, instruction: &Instruction) {
+    ///
+    ///  Addresses beyond the end of memory are an error, unless the quirk lets them wrap around to the start of memory.
+    fn exec_load_registers_from_memory(
+        &mut self,
+        instruction: &Instruction,
+    ) -> Result<(), CpuError> {
         let x = instruction.x() as usize;
-        let read_data = self.memory.read_bytes(self.registers.i, 1 + x as u16);
+        self.check_memory_access_bounds(x, "Fx65")?;
 
-        for (index, value) in read_data.iter().enumerate() {
-            self.registers.general_registers[index] = *value;
-            self.registers.i += 1;
+        self.memory
+            .warn_uninitialized_reads(self.registers.i, x as u16 + 1, "Fx65");
+        for index in 0..=x {
+            let value = self.memory.read_byte(self.registers.i);
+            self.registers.set_reg(index, value);
+            self.registers.i = self.registers.i.wrapping_add(1);
         }
         self.registers.program_counter.increment();
+        return Ok(());
+    }
+
+    /// Fails when `[I, I + x]` does not fit into memory, unless memory accesses wrap around
+    fn check_memory_access_bounds(
+        &mut self,
+        x: usize,
+        access: &'static str,
+    ) -> Result<(), CpuError> {
+        let last_address = self.registers.i as usize + x;
+        if last_address < self.memory.size() {
+            return Ok(());
+        }
+        self.observe(Observation::MemoryAccessCrossedEnd(access));
+        if self.quirks.memory_access_wraps {
+            return Ok(());
+        }
+        return Err(CpuError::MemoryOutOfBounds {
+            instruction: access,
+            address: self.registers.program_counter.address(),
+            last_address,
+            memory_size: self.memory.size(),
+        });
     }
 
     fn ignore_instruction(&mut self) {
         self.registers.program_counter.increment();
     }
 }
+
+/// Decodes the instruction of a CHIP-8 or a CHIP-8X rom
+fn decode(opcode: u16, chip8x: bool) -> Option<Opcode> {
+    if chip8x {
+        return Opcode::decode_chip8x(opcode);
+    }
+    return Opcode::decode(opcode);
+}
+
+/// The zones of a CHIP-8X color instruction operand, with the first zone in the low nibble
+/// and the number of further zones in the high nibble
+fn zone_range(operand: u8) -> RangeInclusive<usize> {
+    let first = (operand & 0xF) as usize;
+    return first..=first + (operand >> 4) as usize;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{FONT, MAX_MEMORY_SIZE};
+    use crate::test_support::{
+        cpu_with_config, cpu_with_keys, cpu_with_program, press, release, silent_config,
+        FixedSequence, ProgramBuilder,
+    };
+
+    /// Executes a single instruction with the registers, and optionally I and the quirks, set
+    /// beforehand. Returns the state before the instruction as well.
+    fn execute(
+        opcode: u16,
+        registers: &[(usize, u8)],
+        i: Option<u16>,
+        quirks: Option<Quirks>,
+    ) -> (Cpu, StateSnapshot, Result<(), CpuError>) {
+        let mut cpu = cpu_with_program(&opcode.to_be_bytes());
+        if let Some(quirks) = quirks {
+            cpu.quirks = quirks;
+        }
+        for (x, value) in registers {
+            cpu.set_register(*x, *value).unwrap();
+        }
+        if let Some(i) = i {
+            cpu.set_i(i).unwrap();
+        }
+        let before = cpu.state_snapshot();
+        let result = cpu.execute_next_instruction();
+        return (cpu, before, result);
+    }
+
+    /// V0 to VF set to 1 to 16
+    fn counting_registers() -> Vec<(usize, u8)> {
+        return (0..16).map(|x| (x, x as u8 + 1)).collect();
+    }
+
+    /// Asserts that the instruction only changes Vx and VF and moves on to the next instruction
+    fn assert_result(opcode: u16, registers: &[(usize, u8)], vx: u8, vf: u8) {
+        let x = ((opcode >> 8) & 0xF) as usize;
+        let (cpu, before, result) = execute(opcode, registers, None, None);
+        result.unwrap();
+        let mut expected = before;
+        expected.registers[x] = vx;
+        expected.registers[0xF] = vf;
+        expected.program_counter += 2;
+        assert_eq!(cpu.state_snapshot(), expected, "after {:04x}", opcode);
+    }
+
+    #[test]
+    fn add_sets_carry() {
+        assert_result(0x8014, &[(0, 0xFF), (1, 0x01)], 0x00, 1);
+        assert_result(0x8014, &[(0, 0x80), (1, 0x80)], 0x00, 1);
+        assert_result(0x8014, &[(0, 0xFE), (1, 0x01)], 0xFF, 0);
+    }
+
+    #[test]
+    fn sub_clears_vf_on_borrow() {
+        assert_result(0x8015, &[(0, 0x00), (1, 0x01)], 0xFF, 0);
+        assert_result(0x8015, &[(0, 0x05), (1, 0x05)], 0x00, 1);
+        assert_result(0x8015, &[(0, 0x05), (1, 0x03)], 0x02, 1);
+    }
+
+    #[test]
+    fn subn_clears_vf_on_borrow() {
+        assert_result(0x8017, &[(0, 0x05), (1, 0x03)], 0xFE, 0);
+        assert_result(0x8017, &[(0, 0x03), (1, 0x05)], 0x02, 1);
+    }
+
+    #[test]
+    fn shifts_set_vf_to_the_shifted_out_bit() {
+        assert_result(0x8016, &[(1, 0x01)], 0x00, 1);
+        assert_result(0x8016, &[(1, 0x80)], 0x40, 0);
+        assert_result(0x801E, &[(1, 0x80)], 0x00, 1);
+        assert_result(0x801E, &[(1, 0x01)], 0x02, 0);
+    }
+
+    /// With VF as the target register, the flag is written last and wins over the result
+    #[test]
+    fn flag_wins_when_vf_is_the_target() {
+        assert_result(0x8F14, &[(0xF, 0x10), (1, 0x20)], 0, 0);
+        assert_result(0x8F14, &[(0xF, 0xFF), (1, 0x02)], 1, 1);
+        assert_result(0x8F15, &[(0xF, 0x30), (1, 0x10)], 1, 1);
+        assert_result(0x8F15, &[(0xF, 0x00), (1, 0x01)], 0, 0);
+        assert_result(0x8F16, &[(1, 0x04)], 0, 0);
+        assert_result(0x8F16, &[(1, 0x03)], 1, 1);
+        assert_result(0x8F17, &[(0xF, 0x10), (1, 0x30)], 1, 1);
+        assert_result(0x8F17, &[(0xF, 0x30), (1, 0x10)], 0, 0);
+        assert_result(0x8F1E, &[(1, 0x81)], 1, 1);
+        assert_result(0x8F1E, &[(1, 0x01)], 0, 0);
+    }
+
+    /// Vy is read before anything is written, so `y == 0xF` operates on the old VF
+    #[test]
+    fn vf_as_source_is_read_before_the_flag() {
+        assert_result(0x80F4, &[(0, 0x01), (0xF, 0xFF)], 0x00, 1);
+        assert_result(0x80F6, &[(0xF, 0x02)], 0x01, 0);
+    }
+
+    #[test]
+    fn register_store_and_load_fit_up_to_the_end_of_memory() {
+        let (cpu, _, result) = execute(0xFF55, &counting_registers(), Some(0xFF0), None);
+        assert!(result.is_ok());
+        assert_eq!(cpu.memory.read_byte(0xFFF), 16);
+        assert_eq!(cpu.i(), 0x1000);
+
+        let (cpu, _, result) = execute(0xFF65, &counting_registers(), Some(0xFF0), None);
+        assert!(result.is_ok());
+        assert_eq!(cpu.register(0xF), cpu.memory.read_byte(0xFFF));
+    }
+
+    #[test]
+    fn register_store_and_load_beyond_the_end_of_memory_fail() {
+        for opcode in [0xFF55, 0xFF65] {
+            let (cpu, before, result) = execute(opcode, &counting_registers(), Some(0xFF1), None);
+            assert!(
+                matches!(
+                    result,
+                    Err(CpuError::MemoryOutOfBounds {
+                        last_address: 0x1000,
+                        ..
+                    })
+                ),
+                "{:04x}",
+                opcode
+            );
+            // nothing was executed
+            assert_eq!(cpu.state_snapshot(), before, "{:04x}", opcode);
+        }
+    }
+
+    #[test]
+    fn register_store_wraps_with_the_quirk() {
+        let quirks = Quirks {
+            memory_access_wraps: true,
+            ..Quirks::default()
+        };
+        let (cpu, _, result) = execute(0xFF55, &counting_registers(), Some(0xFF8), Some(quirks));
+        assert!(result.is_ok());
+        assert_eq!(cpu.memory.read_byte(0xFFF), 8);
+        assert_eq!(cpu.memory.read_byte(0x000), 9);
+        assert_eq!(cpu.memory.read_byte(0x007), 16);
+    }
+
+    #[test]
+    fn bcd_store_fits_up_to_the_end_of_memory() {
+        let (mut cpu, _, result) = execute(0xF033, &[(0, 254)], Some(0xFFD), None);
+        result.unwrap();
+        assert_eq!(cpu.memory.read_bytes(0xFFD, 3), [2, 5, 4]);
+        cpu.set_i(0xFFE).unwrap();
+        cpu.set_program_counter(PROGRAM_START_ADDRESS).unwrap();
+        assert!(matches!(
+            cpu.execute_next_instruction(),
+            Err(CpuError::MemoryOutOfBounds {
+                instruction: "Fx33",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn bcd_store_wraps_with_the_quirk() {
+        let quirks = Quirks {
+            memory_access_wraps: true,
+            ..Quirks::default()
+        };
+        let (cpu, _, result) = execute(0xF033, &[(0, 137)], Some(0xFFE), Some(quirks));
+        result.unwrap();
+        assert_eq!(cpu.memory.read_bytes(0xFFE, 2), [1, 3]);
+        assert_eq!(cpu.memory.read_byte(0x000), 7);
+    }
+
+    #[test]
+    fn built_program_computes_a_digit_and_draws_it() {
+        let program = ProgramBuilder::new()
+            .ld(0, 5)
+            .add_kk(0, 3)
+            .font(0)
+            .ld(1, 2)
+            .draw(1, 1, 5)
+            .halt()
+            .build();
+        let mut cpu = cpu_with_program(&program);
+        for _ in 0..6 {
+            cpu.execute_next_instruction().unwrap();
+        }
+        assert_eq!(cpu.register(0), 8);
+        assert_eq!(cpu.program_counter(), PROGRAM_START_ADDRESS + 10);
+
+        let glyph = &FONT[8 * FONT_CHARACTER_SIZE as usize..9 * FONT_CHARACTER_SIZE as usize];
+        let display = cpu.display();
+        for y in 0..display.height() {
+            for x in 0..display.width() {
+                let in_glyph = (2..7).contains(&y) && (2..10).contains(&x);
+                let expected = in_glyph && glyph[y - 2] & (0x80 >> (x - 2)) != 0;
+                assert_eq!(display.pixel(x, y), expected, "pixel {},{}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn fetch_fails_for_an_instruction_crossing_the_end_of_memory() {
+        let mut cpu = cpu_with_program(&rom![Jump { nnn: 0xFFE }]);
+        // the last two bytes of memory still hold an instruction
+        cpu.load_at(0xFFE, &rom![Jump { nnn: 0xFFF }]).unwrap();
+        cpu.execute_next_instruction().unwrap();
+        assert_eq!(cpu.fetch().unwrap(), [0x1F, 0xFF]);
+        cpu.execute_next_instruction().unwrap();
+        assert_eq!(cpu.program_counter(), 0xFFF);
+
+        assert!(matches!(
+            cpu.peek_next_opcode(),
+            Err(CpuError::FetchOutOfBounds {
+                address: 0xFFF,
+                memory_size: 0x1000
+            })
+        ));
+        let before = cpu.state_snapshot();
+        assert!(matches!(
+            cpu.execute_next_instruction(),
+            Err(CpuError::FetchOutOfBounds { address: 0xFFF, .. })
+        ));
+        assert_eq!(cpu.state_snapshot(), before);
+        assert_eq!(cpu.executed_instructions(), 2);
+    }
+
+    /// Draws the 10 byte sprite at `I = 0xFFA`, of which the last 4 bytes cross the end of memory
+    fn draw_sprite_crossing_the_end_of_memory(
+        quirks: Quirks,
+    ) -> (Cpu, StateSnapshot, Result<(), CpuError>) {
+        let mut cpu = cpu_with_program(&rom![DrawSprite { x: 0, y: 0, n: 10 }]);
+        cpu.quirks = quirks;
+        cpu.load_at(0xFFA, &[0xFF; 6]).unwrap();
+        cpu.set_i(0xFFA).unwrap();
+        let before = cpu.state_snapshot();
+        let result = cpu.execute_next_instruction();
+        return (cpu, before, result);
+    }
+
+    #[test]
+    fn sprite_read_beyond_the_end_of_memory_fails() {
+        let (cpu, before, result) = draw_sprite_crossing_the_end_of_memory(Quirks::default());
+        assert!(matches!(
+            result,
+            Err(CpuError::MemoryOutOfBounds {
+                instruction: "Dxyn",
+                address: PROGRAM_START_ADDRESS,
+                last_address: 0x1003,
+                memory_size: 0x1000,
+            })
+        ));
+        assert_eq!(cpu.state_snapshot(), before);
+    }
+
+    #[test]
+    fn sprite_read_wraps_with_the_quirk() {
+        let quirks = Quirks {
+            sprite_read_wraps: true,
+            ..Quirks::default()
+        };
+        let (cpu, _, result) = draw_sprite_crossing_the_end_of_memory(quirks);
+        result.unwrap();
+        // 6 full rows from the end of memory, followed by the first 4 rows of the font character 0
+        let rows = cpu.display_rows_hex();
+        let expected = ["ff", "ff", "ff", "ff", "ff", "ff", "f0", "90", "90", "90"];
+        for (y, row) in expected.iter().enumerate() {
+            assert!(rows[y].starts_with(row), "row {}: {}", y, rows[y]);
+            assert!(
+                rows[y][2..].chars().all(|c| c == '0'),
+                "row {}: {}",
+                y,
+                rows[y]
+            );
+        }
+        assert!(rows[10..].iter().all(|row| row.chars().all(|c| c == '0')));
+    }
+
+    /// Calls a subroutine that decrements V0 and calls itself again until V0 is 0, starting with V0 = 20
+    const NESTING_20_DEEP: [u8; 14] = [
+        0x60, 0x14, // V0 := 20
+        0x22, 0x06, // call 0x206
+        0x12, 0x04, // halt
+        0x70, 0xFF, // V0 -= 1
+        0x30, 0x00, // skip if V0 == 0
+        0x22, 0x06, // call 0x206
+        0x00, 0xEE, // return
+    ];
+
+    /// Runs `NESTING_20_DEEP` until it halts and returns the deepest nesting
+    fn run_nesting_20_deep(stack_size: usize) -> usize {
+        let config = CpuConfig {
+            stack_size,
+            ..silent_config()
+        };
+        let mut cpu = cpu_with_config(&NESTING_20_DEEP, config);
+        let mut deepest = 0;
+        while cpu.program_counter() != 0x204 {
+            cpu.execute_next_instruction().unwrap();
+            deepest = deepest.max(cpu.call_stack().len());
+        }
+        assert!(cpu.call_stack().is_empty());
+        return deepest;
+    }
+
+    #[test]
+    fn configured_stack_size_allows_deeper_nesting() {
+        assert_eq!(run_nesting_20_deep(32), 20);
+    }
+
+    #[test]
+    #[should_panic(expected = "more than 16 nested subroutine calls")]
+    fn nesting_deeper_than_the_stack_size_fails() {
+        run_nesting_20_deep(DEFAULT_STACK_SIZE);
+    }
+
+    #[test]
+    fn poked_values_change_the_following_execution() {
+        let mut cpu = cpu_with_program(&rom![
+            SetRegister { x: 0, kk: 5 },
+            AddKk { x: 0, kk: 1 },
+            SkipIfEqualKk { x: 1, kk: 7 },
+            Jump { nnn: 0x300 },
+            Jump { nnn: 0x400 },
+        ]);
+        // the operand of the first instruction belongs to the next instruction
+        assert!(cpu.poke_memory(0x201, 0x0A, false).is_err());
+        cpu.poke_memory(0x201, 0x0A, true).unwrap();
+        cpu.poke_memory(0x203, 0x02, false).unwrap();
+        cpu.execute_next_instruction().unwrap();
+        cpu.execute_next_instruction().unwrap();
+        assert_eq!(cpu.register(0), 12);
+
+        cpu.set_register(1, 7).unwrap();
+        cpu.execute_next_instruction().unwrap();
+        cpu.execute_next_instruction().unwrap();
+        assert_eq!(cpu.program_counter(), 0x400);
+    }
+
+    #[test]
+    fn pokes_out_of_range_fail() {
+        let mut cpu = cpu_with_program(&[]);
+        let before = cpu.state_snapshot();
+        assert!(cpu.poke_memory(0x1000, 0xFF, true).is_err());
+        assert!(cpu.set_register(16, 1).is_err());
+        assert!(cpu.set_i(0x1000).is_err());
+        assert_eq!(cpu.state_snapshot(), before);
+        cpu.set_i(0xFFF).unwrap();
+        assert_eq!(cpu.i(), 0xFFF);
+    }
+
+    #[test]
+    fn add_to_i_wraps_around() {
+        let (cpu, _, result) = execute(0xF01E, &[(0, 0x02)], Some(0xFFF), None);
+        result.unwrap();
+        assert_eq!(cpu.i(), 0x1001);
+
+        let config = CpuConfig {
+            memory_size: MAX_MEMORY_SIZE,
+            ..silent_config()
+        };
+        let mut cpu = cpu_with_config(&rom![AddI { x: 0 }], config);
+        cpu.set_i(0xFFFF).unwrap();
+        cpu.set_register(0, 0x02).unwrap();
+        cpu.execute_next_instruction().unwrap();
+        assert_eq!(cpu.i(), 0x0001);
+    }
+    #[test]
+    fn large_memory_is_readable_and_writable_up_to_0xffff() {
+        let config = CpuConfig {
+            memory_size: MAX_MEMORY_SIZE,
+            ..silent_config()
+        };
+        let mut cpu = cpu_with_config(
+            &rom![StoreRegisters { x: 0xF }, LoadRegisters { x: 0xF }],
+            config,
+        );
+        for (x, value) in counting_registers() {
+            cpu.set_register(x, value).unwrap();
+        }
+        cpu.set_i(0xFFF0).unwrap();
+        cpu.execute_next_instruction().unwrap();
+        assert_eq!(
+            cpu.memory.read_bytes(0xFFF0, 16),
+            (1..=16).collect::<Vec<u8>>()
+        );
+
+        assert_eq!(cpu.i(), 0x0000);
+
+        cpu.set_i(0xFFF0).unwrap();
+        cpu.poke_memory(0xFFFF, 0xAB, false).unwrap();
+        for x in 0..16 {
+            cpu.set_register(x, 0).unwrap();
+        }
+        cpu.execute_next_instruction().unwrap();
+        assert_eq!(cpu.register(0), 1);
+        assert_eq!(cpu.register(0xE), 15);
+        assert_eq!(cpu.register(0xF), 0xAB);
+    }
+    #[test]
+    fn drawn_sprite_reads_back_from_the_display() {
+        let sprite = [0b1010_0101, 0b0101_1010];
+        let mut cpu = cpu_with_program(&rom![
+            SetI { nnn: 0x300 },
+            SetRegister { x: 0, kk: 3 },
+            SetRegister { x: 1, kk: 4 },
+            DrawSprite { x: 0, y: 1, n: 2 },
+        ]);
+        cpu.load_at(0x300, &sprite).unwrap();
+        for _ in 0..4 {
+            cpu.execute_next_instruction().unwrap();
+        }
+        let display = cpu.display();
+        for y in 0..display.height() {
+            for x in 0..display.width() {
+                let expected = (4..6).contains(&y)
+                    && (3..11).contains(&x)
+                    && sprite[y - 4] & (0x80 >> (x - 3)) != 0;
+                assert_eq!(display.pixel(x, y), expected, "pixel {},{}", x, y);
+            }
+        }
+    }
+    #[test]
+    fn peeked_opcode_is_the_next_instruction() {
+        let mut cpu = cpu_with_program(&rom![ClearDisplay, Jump { nnn: 0xFFE }]);
+        assert_eq!(cpu.peek_next_opcode().unwrap(), 0x00E0);
+        // peeking does not execute anything
+        assert_eq!(cpu.peek_next_opcode().unwrap(), 0x00E0);
+        assert_eq!(cpu.program_counter(), PROGRAM_START_ADDRESS);
+
+        cpu.execute_next_instruction().unwrap();
+        assert_eq!(cpu.peek_next_opcode().unwrap(), 0x1FFE);
+
+        cpu.load_at(0xFFE, &[0xD1, 0x23]).unwrap();
+        cpu.execute_next_instruction().unwrap();
+        assert_eq!(cpu.peek_next_opcode().unwrap(), 0xD123);
+    }
+    /// Draws the font character 0 at x = 70, beyond the right edge of the 64 pixel wide screen
+    fn draw_at_x_70(quirks: Quirks) -> Cpu {
+        let (cpu, _, result) = execute(0xD015, &[(0, 70), (1, 0)], Some(0), Some(quirks));
+        result.unwrap();
+        return cpu;
+    }
+
+    #[test]
+    fn sprite_start_wraps_by_default() {
+        let cpu = draw_at_x_70(Quirks::default());
+        let display = cpu.display();
+        // the top row of the character 0 is 0xF0, drawn from x = 70 % 64 = 6 on
+        let top_row: Vec<usize> = (0..64).filter(|x| display.pixel(*x, 0)).collect();
+        assert_eq!(top_row, [6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn sprite_start_is_clipped_with_the_quirk() {
+        let quirks = Quirks {
+            clip_sprite_start: true,
+            ..Quirks::default()
+        };
+        let cpu = draw_at_x_70(quirks);
+        assert!(cpu
+            .display()
+            .rows()
+            .all(|row| row.iter().all(|pixel| !pixel)));
+        assert_eq!(cpu.register(0xF), 0);
+    }
+    #[test]
+    fn state_survives_the_json_dump() {
+        let mut cpu = cpu_with_program(&rom![
+            SetRegister { x: 3, kk: 0x42 },
+            SetI { nnn: 0x000 },
+            DrawSprite { x: 0, y: 0, n: 5 },
+            Call { nnn: 0x208 },
+            SetDelayTimer { x: 3 },
+        ]);
+        for _ in 0..5 {
+            cpu.execute_next_instruction().unwrap();
+        }
+        let state = cpu.state();
+        let json = serde_json::to_string(&state).unwrap();
+        let parsed: CpuState = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, state);
+        assert_eq!(parsed.general_registers[3], 0x42);
+        assert_eq!(parsed.delay_timer, 0x42);
+        assert_eq!(parsed.stack, [0x208]);
+        assert_eq!(parsed.display[0], "f000000000000000");
+    }
+    #[test]
+    fn random_numbers_are_masked_with_kk() {
+        let mut cpu = cpu_with_program(&rom![
+            Random { x: 0, kk: 0x0F },
+            Random { x: 1, kk: 0xF0 },
+            Random { x: 2, kk: 0xFF },
+            Random { x: 3, kk: 0x00 },
+        ]);
+        cpu.set_random_source(Box::new(FixedSequence::new(&[0xA5, 0xA5, 0x3C, 0xFF])));
+        for _ in 0..4 {
+            cpu.execute_next_instruction().unwrap();
+        }
+        assert_eq!(
+            cpu.state_snapshot().registers[..4],
+            [0x05, 0xA0, 0x3C, 0x00]
+        );
+    }
+    #[test]
+    fn memory_dump_marks_pc_and_i() {
+        let mut cpu = cpu_with_program(&rom![SetRegister { x: 0, kk: 1 }, AddKk { x: 0, kk: 2 }]);
+        cpu.set_i(0x205).unwrap();
+        cpu.execute_next_instruction().unwrap();
+        assert_eq!(
+            cpu.annotated_memory_dump(0x200, 8),
+            "0x200: 60 01 [PC]70 02 00 [I]00 00 00"
+        );
+        assert_eq!(
+            cpu.annotated_memory_dump(0x1F0, 24).lines().nth(1),
+            Some("0x200: 60 01 [PC]70 02 00 [I]00 00 00")
+        );
+    }
+    #[test]
+    fn call_stack_lists_the_nested_calls() {
+        let mut cpu = cpu_with_program(&rom![
+            Call { nnn: 0x204 },
+            Jump { nnn: 0x202 },
+            Call { nnn: 0x208 },
+            Return,
+            Return,
+        ]);
+        assert!(cpu.call_stack().is_empty());
+        cpu.execute_next_instruction().unwrap();
+        assert_eq!(cpu.call_stack(), [0x202]);
+        cpu.execute_next_instruction().unwrap();
+        // the innermost call is last
+        assert_eq!(cpu.call_stack(), [0x202, 0x206]);
+        cpu.execute_next_instruction().unwrap();
+        assert_eq!(cpu.program_counter(), 0x206);
+        assert_eq!(cpu.call_stack(), [0x202]);
+        cpu.execute_next_instruction().unwrap();
+        assert_eq!(cpu.program_counter(), 0x202);
+        assert!(cpu.call_stack().is_empty());
+    }
+    #[test]
+    fn key_wait_stores_the_pressed_key_and_completes_on_release() {
+        let (mut cpu, keys) =
+            cpu_with_keys(&rom![WaitForKey { x: 0 }, ClearDisplay], silent_config());
+        for _ in 0..3 {
+            cpu.execute_next_instruction().unwrap();
+        }
+        assert!(cpu.is_waiting_for_key());
+
+        press(&keys, 0x5);
+        cpu.execute_next_instruction().unwrap();
+        assert_eq!(cpu.register(0), 0x5);
+        // the instruction only completes once the key is released
+        assert!(cpu.is_waiting_for_key());
+        assert_eq!(cpu.program_counter(), PROGRAM_START_ADDRESS);
+
+        release(&keys, 0x5);
+        cpu.execute_next_instruction().unwrap();
+        assert!(!cpu.is_waiting_for_key());
+        assert_eq!(cpu.program_counter(), PROGRAM_START_ADDRESS + 2);
+    }
+
+    #[test]
+    fn key_held_across_frames_keeps_waiting() {
+        let (mut cpu, keys) =
+            cpu_with_keys(&rom![WaitForKey { x: 0 }, ClearDisplay], silent_config());
+        cpu.execute_next_instruction().unwrap();
+        press(&keys, 0xA);
+        for _ in 0..5 {
+            cpu.run_frame().unwrap();
+            assert_eq!(cpu.register(0), 0xA);
+            assert_eq!(cpu.program_counter(), PROGRAM_START_ADDRESS);
+        }
+        // pressing another key while the first one is held does not change the stored key
+        press(&keys, 0x3);
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.register(0), 0xA);
+
+        release(&keys, 0xA);
+        cpu.execute_next_instruction().unwrap();
+        assert_eq!(cpu.program_counter(), PROGRAM_START_ADDRESS + 2);
+    }
+    #[test]
+    fn frame_step_decrements_the_timers_by_one() {
+        let program = ProgramBuilder::new()
+            .ld(0, 10)
+            .opcode(Opcode::SetDelayTimer { x: 0 })
+            .opcode(Opcode::SetSoundTimer { x: 0 })
+            .halt()
+            .build();
+        let mut cpu = cpu_with_program(&program);
+        for remaining in [9, 8, 7] {
+            cpu.run_frame().unwrap();
+            let state = cpu.state_snapshot();
+            assert_eq!(
+                (state.delay_timer, state.sound_timer),
+                (remaining, remaining)
+            );
+        }
+        assert_eq!(cpu.timer_frames(), 3);
+    }
+    #[test]
+    fn vip_timing_accumulates_the_instruction_costs() {
+        let config = CpuConfig {
+            vip_timing: true,
+            ..silent_config()
+        };
+        let program = rom![
+            SetRegister { x: 0, kk: 123 },
+            SetI { nnn: 0x300 },
+            DrawSprite { x: 0, y: 0, n: 5 },
+            StoreBcd { x: 0 },
+            StoreRegisters { x: 2 },
+            DrawSprite { x: 0, y: 0, n: 5 },
+        ];
+        let mut cpu = cpu_with_config(&program, config);
+        for _ in 0..5 {
+            cpu.run_cycle().unwrap();
+        }
+        // 27 + 55 + (3812 + 5 * 1264) + 927 + (605 + 2 * 64)
+        assert_eq!(cpu.frame_micros, 11_874);
+        assert_eq!(cpu.timer_frames(), 0);
+
+        // the second draw crosses the end of the frame, the rest counts towards the next frame
+        cpu.run_cycle().unwrap();
+        assert_eq!(cpu.timer_frames(), 1);
+        assert_eq!(cpu.frame_micros, 11_874 + 10_132 - FRAME_MICROS);
+    }
+    #[test]
+    fn delay_timer_runs_out_during_a_key_wait() {
+        let program = ProgramBuilder::new()
+            .ld(0, 5)
+            .opcode(Opcode::SetDelayTimer { x: 0 })
+            .opcode(Opcode::WaitForKey { x: 1 })
+            .halt()
+            .build();
+        let (mut cpu, _keys) = cpu_with_keys(&program, silent_config());
+        cpu.run_frame().unwrap();
+        assert!(cpu.is_waiting_for_key());
+        assert_eq!(cpu.state_snapshot().delay_timer, 4);
+        for _ in 0..10 {
+            cpu.run_frame().unwrap();
+        }
+        let state = cpu.state_snapshot();
+        assert_eq!(state.delay_timer, 0);
+        assert_eq!(state.program_counter, 0x204);
+        assert!(cpu.is_waiting_for_key());
+        assert_eq!(cpu.executed_instructions(), 3);
+    }
+    #[test]
+    fn memory_diff_lists_the_bytes_written_by_fx55() {
+        let mut cpu = cpu_with_program(&rom![StoreRegisters { x: 3 }]);
+        for (x, value) in [(0, 0x11), (1, 0x00), (2, 0x22), (3, 0x33)] {
+            cpu.set_register(x, value).unwrap();
+        }
+        cpu.load_at(0x300, &[0xFF; 8]).unwrap();
+        cpu.set_i(0x300).unwrap();
+        let snapshot = cpu.snapshot_memory();
+        cpu.execute_next_instruction().unwrap();
+        // all 4 bytes were overwritten, the untouched 0x304 is not listed
+        assert_eq!(
+            cpu.diff_memory(&snapshot),
+            [
+                (0x300, 0xFF, 0x11),
+                (0x301, 0xFF, 0x00),
+                (0x302, 0xFF, 0x22),
+                (0x303, 0xFF, 0x33),
+            ]
+        );
+
+        // writing the same values again changes nothing
+        let snapshot = cpu.snapshot_memory();
+        cpu.set_i(0x300).unwrap();
+        cpu.set_program_counter(PROGRAM_START_ADDRESS).unwrap();
+        cpu.execute_next_instruction().unwrap();
+        assert!(cpu.diff_memory(&snapshot).is_empty());
+    }
+    #[test]
+    fn key_wait_captures_the_most_recently_pressed_key() {
+        let program = rom![WaitForKey { x: 0 }, WaitForKey { x: 1 }];
+        let (mut cpu, keys) = cpu_with_keys(&program, silent_config());
+        // keys that are already held when Fx0A starts count in the order they were pressed
+        press(&keys, 0x2);
+        press(&keys, 0x9);
+        cpu.execute_next_instruction().unwrap();
+        assert_eq!(cpu.register(0), 0x9);
+        release(&keys, 0x2);
+        release(&keys, 0x9);
+        cpu.execute_next_instruction().unwrap();
+
+        // two presses between two checks of the keyboard
+        cpu.execute_next_instruction().unwrap();
+        press(&keys, 0xC);
+        press(&keys, 0x4);
+        cpu.execute_next_instruction().unwrap();
+        assert_eq!(cpu.register(1), 0x4);
+    }
+    #[test]
+    fn routine_runs_from_an_arbitrary_address() {
+        let mut cpu = cpu_with_program(&rom![Jump { nnn: 0x200 }]);
+        let routine = rom![
+            SetRegister { x: 0, kk: 7 },
+            AddKk { x: 0, kk: 3 },
+            SetI { nnn: 0x400 },
+            StoreBcd { x: 0 },
+            Jump { nnn: 0x308 },
+        ];
+        cpu.load_at(0x300, &routine).unwrap();
+        assert!(cpu.set_program_counter(0x1FE).is_err());
+        assert!(cpu.set_program_counter(0xFFF).is_err());
+        cpu.set_program_counter(0x300).unwrap();
+        for _ in 0..6 {
+            cpu.execute_next_instruction().unwrap();
+        }
+        assert_eq!(cpu.register(0), 10);
+        assert_eq!(cpu.memory.read_bytes(0x400, 3), [0, 1, 0]);
+        assert_eq!(cpu.program_counter(), 0x308);
+    }
+    #[test]
+    fn instruction_hook_sees_every_executed_instruction() {
+        use std::sync::{Arc, Mutex};
+
+        let program = ProgramBuilder::new().ld(0, 1).add_kk(0, 2).halt().build();
+        let mut cpu = cpu_with_program(&program);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let hook_seen = Arc::clone(&seen);
+        cpu.set_instruction_hook(Box::new(move |context| {
+            hook_seen.lock().unwrap().push((
+                context.address,
+                context.opcode,
+                context.general_registers_before[0],
+                context.general_registers[0],
+            ));
+        }));
+        for _ in 0..10 {
+            cpu.execute_next_instruction().unwrap();
+        }
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 10);
+        assert_eq!(seen[0], (0x200, 0x6001, 0, 1));
+        assert_eq!(seen[1], (0x202, 0x7002, 1, 3));
+        assert!(seen[2..].iter().all(|call| *call == (0x204, 0x1204, 3, 3)));
+    }
+    #[test]
+    fn coverage_marks_only_the_taken_branch() {
+        let program = rom![
+            SetRegister { x: 0, kk: 5 },
+            SkipIfEqualKk { x: 0, kk: 5 },
+            SetRegister { x: 1, kk: 1 },
+            SkipIfNotEqualKk { x: 0, kk: 5 },
+            SetRegister { x: 2, kk: 2 },
+            Jump { nnn: 0x20A },
+        ];
+        let mut cpu = cpu_with_program(&program);
+        cpu.set_coverage(Coverage::new(
+            String::new(),
+            DEFAULT_MEMORY_SIZE,
+            program.len(),
+        ));
+        for _ in 0..6 {
+            cpu.execute_next_instruction().unwrap();
+        }
+        let coverage = cpu.coverage.as_ref().unwrap();
+        assert!(!coverage.is_executed(0x204));
+        assert!(coverage.is_executed(0x208));
+        assert_eq!(
+            coverage.listing(),
+            "0x200-0x202 executed\n0x204-0x204 not executed\n0x206-0x20a executed\n"
+        );
+        assert_eq!(format!("{:.1}", coverage.percentage()), "83.3");
+    }
+}
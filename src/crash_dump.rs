@@ -0,0 +1,67 @@
+use std::any::Any;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+
+use anyhow::Result;
+use serde::Serialize;
+use tracing::error;
+
+use crate::cpu::{Cpu, CpuState};
+
+/// Written when the cpu panics, to be attached to bug reports
+#[derive(Serialize)]
+struct CrashDump {
+    error: String,
+    state: CpuState,
+    /// `(address, opcode)` of the last executed instructions, the most recent one is last
+    recent_instructions: Vec<(u16, u16)>,
+}
+
+/// Runs the cpu and writes a crash dump to the path if it panics or fails. The panic or error is passed on afterwards.
+pub fn run_with_crash_dump<T>(
+    cpu: &mut Cpu,
+    path: Option<&str>,
+    run: impl FnOnce(&mut Cpu) -> Result<T>,
+) -> Result<T> {
+    let Some(path) = path else {
+        return run(cpu);
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| run(cpu))) {
+        Ok(Ok(result)) => return Ok(result),
+        Ok(Err(e)) => {
+            write_crash_dump(cpu, path, e.to_string());
+            return Err(e);
+        }
+        Err(payload) => {
+            write_crash_dump(cpu, path, panic_message(payload.as_ref()));
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+fn write_crash_dump(cpu: &Cpu, path: &str, error: String) {
+    let dump = CrashDump {
+        error,
+        state: cpu.state(),
+        recent_instructions: cpu.recent_instructions(),
+    };
+    match serde_json::to_string_pretty(&dump) {
+        Ok(json) => {
+            if let Err(e) = fs::write(path, json) {
+                error!("Failed to write crash dump to '{}': {}", path, e);
+            }
+        }
+        Err(e) => error!("Failed to serialize crash dump: {}", e),
+    }
+}
+
+/// The message of a caught panic
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return message.to_string();
+    }
+    if let Some(message) = payload.downcast_ref::<String>() {
+        return message.clone();
+    }
+    return "unknown error".to_string();
+}
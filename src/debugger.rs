@@ -0,0 +1,563 @@
+use std::collections::{HashSet, VecDeque};
+use std::{io, thread};
+
+use anyhow::{anyhow, Result};
+
+use crate::cli::{parse_address, parse_number};
+use crate::control::{ControlCommand, ControlSender};
+use crate::cpu::{Cpu, MemorySnapshot, Snapshot};
+
+/// Commands that can be entered in the debugger console
+#[derive(Clone)]
+pub enum DebugCommand {
+    /// Searches the memory for a byte pattern
+    Find(Vec<u8>),
+    /// Shows a hex dump of the memory region `(start, length)` with the program counter and I marked
+    Memory(u16, u16),
+    /// Lists the return addresses of the active subroutine calls
+    Stack,
+    /// Adds a breakpoint that halts execution once the condition becomes true
+    BreakIf(Condition),
+    /// Sets a general register or I to the value
+    Set(Operand, u16),
+    /// Stores a snapshot of the cpu under the label, replacing an older one with the same label
+    Mark(String),
+    /// Restores the snapshot stored under the label
+    JumpTo(String),
+    /// Lists the labels of the stored snapshots
+    Marks,
+    /// Writes the byte `(address, value, force)` to memory, the next instruction is only overwritten when forced
+    Poke(u16, u8, bool),
+    /// Enables or disables the quirk `(name, enabled)` of the running cpu
+    SetQuirk(String, bool),
+    /// Lists the quirks and whether they are enabled
+    Quirks,
+    /// Stores a copy of the memory to compare with later
+    SnapshotMemory,
+    /// Lists the bytes that changed since the memory snapshot
+    DiffMemory,
+}
+
+const DEFAULT_MEMORY_DUMP_LENGTH: u16 = 64;
+/// Number of labeled snapshots kept, the oldest one is dropped when another is marked
+const MAX_MARKS: usize = 32;
+
+/// Halts execution when an instruction matches the pattern, i.e. `(opcode & mask) == value`
+struct OpcodeBreakpoint {
+    mask: u16,
+    value: u16,
+}
+
+/// Value of the cpu a condition looks at
+#[derive(Clone, Copy)]
+pub enum Operand {
+    /// general register Vx
+    Register(usize),
+    I,
+    ProgramCounter,
+}
+
+#[derive(Clone, Copy)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// A comparison like `V3 == 0x10` or `I >= 0x300`
+#[derive(Clone, Copy)]
+pub struct Condition {
+    operand: Operand,
+    comparison: Comparison,
+    value: u16,
+}
+
+impl Condition {
+    fn is_true(&self, cpu: &Cpu) -> bool {
+        let actual = match self.operand {
+            Operand::Register(x) => cpu.register(x) as u16,
+            Operand::I => cpu.i(),
+            Operand::ProgramCounter => cpu.program_counter(),
+        };
+        return match self.comparison {
+            Comparison::Equal => actual == self.value,
+            Comparison::NotEqual => actual != self.value,
+            Comparison::Less => actual < self.value,
+            Comparison::LessOrEqual => actual <= self.value,
+            Comparison::Greater => actual > self.value,
+            Comparison::GreaterOrEqual => actual >= self.value,
+        };
+    }
+}
+
+/// Halts execution when the condition changes from false to true,
+/// so a condition that stays true does not halt on every instruction
+struct ConditionBreakpoint {
+    condition: Condition,
+    was_true: bool,
+}
+
+pub struct Debugger {
+    address_breakpoints: HashSet<u16>,
+    opcode_breakpoints: Vec<OpcodeBreakpoint>,
+    condition_breakpoints: Vec<ConditionBreakpoint>,
+    /// labeled snapshots in the order they were marked
+    marks: VecDeque<(String, Snapshot)>,
+    memory_snapshot: Option<MemorySnapshot>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        return Self {
+            address_breakpoints: HashSet::new(),
+            opcode_breakpoints: Vec::new(),
+            condition_breakpoints: Vec::new(),
+            marks: VecDeque::new(),
+            memory_snapshot: None,
+        };
+    }
+
+    /// Stores the current state of the cpu under the label
+    pub fn mark(&mut self, cpu: &Cpu, label: &str) {
+        self.marks.retain(|(marked_label, _)| marked_label != label);
+        if self.marks.len() == MAX_MARKS {
+            self.marks.pop_front();
+        }
+        self.marks.push_back((label.to_string(), cpu.snapshot()));
+    }
+
+    /// Restores the state stored under the label
+    pub fn jump_to(&self, cpu: &mut Cpu, label: &str) -> Result<()> {
+        let (_, snapshot) = self
+            .marks
+            .iter()
+            .find(|(marked_label, _)| marked_label == label)
+            .ok_or_else(|| anyhow!("No mark '{}'", label))?;
+        cpu.restore(snapshot);
+        return Ok(());
+    }
+
+    /// One line per mark with its label and program counter, the oldest mark first
+    pub fn list_marks(&self) -> String {
+        if self.marks.is_empty() {
+            return "No marks".to_string();
+        }
+        return self
+            .marks
+            .iter()
+            .map(|(label, snapshot)| format!("{}: PC={:#05x}", label, snapshot.program_counter()))
+            .collect::<Vec<String>>()
+            .join("\n");
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.address_breakpoints.insert(address);
+    }
+
+    pub fn add_opcode_breakpoint(&mut self, mask: u16, value: u16) {
+        self.opcode_breakpoints
+            .push(OpcodeBreakpoint { mask, value });
+    }
+
+    pub fn add_condition_breakpoint(&mut self, condition: Condition) {
+        self.condition_breakpoints.push(ConditionBreakpoint {
+            condition,
+            was_true: false,
+        });
+    }
+
+    pub fn has_breakpoints(&self) -> bool {
+        return !self.address_breakpoints.is_empty()
+            || !self.opcode_breakpoints.is_empty()
+            || !self.condition_breakpoints.is_empty();
+    }
+
+    /// Checks whether the instruction that is executed next hits any breakpoint
+    pub fn should_break(&mut self, cpu: &Cpu) -> bool {
+        // every condition is evaluated, so it notices when it becomes false again
+        let mut condition_became_true = false;
+        for breakpoint in self.condition_breakpoints.iter_mut() {
+            let is_true = breakpoint.condition.is_true(cpu);
+            condition_became_true |= is_true && !breakpoint.was_true;
+            breakpoint.was_true = is_true;
+        }
+        if condition_became_true || self.address_breakpoints.contains(&cpu.program_counter()) {
+            return true;
+        }
+        if self.opcode_breakpoints.is_empty() {
+            return false;
+        }
+        // the fetch error is reported when the instruction is executed
+        let Ok(opcode) = cpu.peek_next_opcode() else {
+            return false;
+        };
+        return self
+            .opcode_breakpoints
+            .iter()
+            .any(|breakpoint| opcode & breakpoint.mask == breakpoint.value);
+    }
+
+    /// Executes a console command and returns the response that is shown to the user
+    pub fn execute(&mut self, cpu: &mut Cpu, command: DebugCommand) -> String {
+        match command {
+            DebugCommand::Find(pattern) => {
+                let addresses = cpu.find_in_memory(&pattern);
+                if addresses.is_empty() {
+                    return "Pattern not found".to_string();
+                }
+                return addresses
+                    .iter()
+                    .map(|address| format!("{:#05x}", address))
+                    .collect::<Vec<String>>()
+                    .join(" ");
+            }
+            DebugCommand::Stack => {
+                let call_stack = cpu.call_stack();
+                if call_stack.is_empty() {
+                    return "Stack is empty".to_string();
+                }
+                return call_stack
+                    .iter()
+                    .enumerate()
+                    .map(|(depth, address)| format!("{}: {:#05x}", depth, address))
+                    .collect::<Vec<String>>()
+                    .join("\n");
+            }
+            DebugCommand::Mark(label) => {
+                self.mark(cpu, &label);
+                return format!("Marked '{}'", label);
+            }
+            DebugCommand::JumpTo(label) => {
+                return match self.jump_to(cpu, &label) {
+                    Ok(()) => format!("Jumped to '{}'", label),
+                    Err(e) => e.to_string(),
+                };
+            }
+            DebugCommand::Marks => return self.list_marks(),
+            DebugCommand::Memory(start, length) => {
+                return cpu.annotated_memory_dump(start, length);
+            }
+            DebugCommand::BreakIf(condition) => {
+                self.add_condition_breakpoint(condition);
+                return "Breakpoint added".to_string();
+            }
+            DebugCommand::Set(operand, value) => {
+                let result = match operand {
+                    Operand::Register(x) => u8::try_from(value)
+                        .map_err(|_| anyhow!("Value {:#x} does not fit into a register", value))
+                        .and_then(|value| cpu.set_register(x, value)),
+                    Operand::I => cpu.set_i(value),
+                    Operand::ProgramCounter => cpu.set_program_counter(value),
+                };
+                return match result {
+                    Ok(()) => "Value set".to_string(),
+                    Err(e) => e.to_string(),
+                };
+            }
+            DebugCommand::SetQuirk(name, enabled) => {
+                return match cpu.set_quirk(&name, enabled) {
+                    Ok(()) => format!(
+                        "Quirk {} {}",
+                        name,
+                        if enabled { "enabled" } else { "disabled" }
+                    ),
+                    Err(e) => e.to_string(),
+                };
+            }
+            DebugCommand::Quirks => {
+                return cpu
+                    .quirks()
+                    .named()
+                    .iter()
+                    .map(|(name, enabled)| {
+                        format!("{}: {}", name, if *enabled { "on" } else { "off" })
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n");
+            }
+            DebugCommand::SnapshotMemory => {
+                self.memory_snapshot = Some(cpu.snapshot_memory());
+                return "Memory snapshot taken".to_string();
+            }
+            DebugCommand::DiffMemory => {
+                let Some(snapshot) = &self.memory_snapshot else {
+                    return "No memory snapshot, take one with 'memsnap'".to_string();
+                };
+                let changes = cpu.diff_memory(snapshot);
+                if changes.is_empty() {
+                    return "Memory unchanged".to_string();
+                }
+                return changes
+                    .iter()
+                    .map(|(address, old, new)| {
+                        format!("{:#05x}: {:02x} -> {:02x}", address, old, new)
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n");
+            }
+            DebugCommand::Poke(address, value, force) => {
+                return match cpu.poke_memory(address, value, force) {
+                    Ok(()) => "Memory written".to_string(),
+                    Err(e) => e.to_string(),
+                };
+            }
+        }
+    }
+}
+
+/// Reads debugger commands from stdin and forwards them to the cpu thread, invalid commands are reported unless quiet
+pub fn spawn_repl(control_sender: ControlSender, quiet: bool) {
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            let Ok(line) = line else {
+                return;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_command(&line) {
+                Ok(command) => {
+                    if control_sender.send(ControlCommand::Debug(command)).is_err() {
+                        return;
+                    }
+                }
+                Err(e) if !quiet => println!("{}", e),
+                Err(_) => {}
+            }
+        }
+    });
+}
+
+fn parse_command(line: &str) -> Result<DebugCommand> {
+    let mut words = line.split_whitespace();
+    match words.next() {
+        Some("find") => {
+            return Ok(DebugCommand::Find(parse_hex_bytes(
+                &words.collect::<String>(),
+            )?))
+        }
+        Some("mem") => {
+            let start = parse_address(
+                words
+                    .next()
+                    .ok_or_else(|| anyhow!("Usage: mem <start> [length]"))?,
+            )?;
+            let length = match words.next() {
+                Some(length) => parse_address(length)?,
+                None => DEFAULT_MEMORY_DUMP_LENGTH,
+            };
+            return Ok(DebugCommand::Memory(start, length));
+        }
+        Some("stack") => return Ok(DebugCommand::Stack),
+        Some("marks") => return Ok(DebugCommand::Marks),
+        Some("memsnap") => return Ok(DebugCommand::SnapshotMemory),
+        Some("quirks") => return Ok(DebugCommand::Quirks),
+        Some("quirk") => {
+            let (Some(name), Some(state), None) = (words.next(), words.next(), words.next()) else {
+                return Err(anyhow!("Usage: quirk <name> on|off"));
+            };
+            let enabled = match state {
+                "on" => true,
+                "off" => false,
+                _ => return Err(anyhow!("Usage: quirk <name> on|off")),
+            };
+            return Ok(DebugCommand::SetQuirk(name.to_string(), enabled));
+        }
+        Some("memdiff") => return Ok(DebugCommand::DiffMemory),
+        Some(command @ ("mark" | "jump")) => {
+            // labels may contain spaces, e.g. `mark before boss spawn`
+            let label = words.collect::<Vec<&str>>().join(" ");
+            if label.is_empty() {
+                return Err(anyhow!("Usage: {} <label>", command));
+            }
+            if command == "mark" {
+                return Ok(DebugCommand::Mark(label));
+            }
+            return Ok(DebugCommand::JumpTo(label));
+        }
+        Some("set") => {
+            let (Some(operand), Some(value), None) = (words.next(), words.next(), words.next())
+            else {
+                return Err(anyhow!("Usage: set <V0-VF|I|PC> <value>"));
+            };
+            return Ok(DebugCommand::Set(
+                parse_operand(operand)?,
+                parse_address(value)?,
+            ));
+        }
+        Some("poke") => {
+            let (Some(address), Some(value)) = (words.next(), words.next()) else {
+                return Err(anyhow!("Usage: poke <address> <byte> [force]"));
+            };
+            let force = match (words.next(), words.next()) {
+                (None, None) => false,
+                (Some("force"), None) => true,
+                _ => return Err(anyhow!("Usage: poke <address> <byte> [force]")),
+            };
+            let value = u8::try_from(parse_number(value)?)
+                .map_err(|_| anyhow!("Value '{}' does not fit into a byte", value))?;
+            return Ok(DebugCommand::Poke(parse_address(address)?, value, force));
+        }
+        Some("break") => {
+            return Ok(DebugCommand::BreakIf(parse_condition(
+                &words.collect::<Vec<&str>>().join(" "),
+            )?))
+        }
+        Some(other) => return Err(anyhow!("Unknown command '{}'", other)),
+        None => return Err(anyhow!("Empty command")),
+    }
+}
+
+/// Parses hexadecimal bytes like `00e0` or `00 e0`
+fn parse_hex_bytes(hex: &str) -> Result<Vec<u8>> {
+    let hex = hex.strip_prefix("0x").unwrap_or(hex);
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return Err(anyhow!(
+            "Expected an even number of hexadecimal digits, got '{}'",
+            hex
+        ));
+    }
+    return (0..hex.len())
+        .step_by(2)
+        .map(|index| {
+            hex.get(index..index + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| anyhow!("Invalid hexadecimal bytes '{}'", hex))
+        })
+        .collect();
+}
+
+/// Parses a condition like `V3 == 0x10` or `I >= 0x300`, the parts have to be separated by spaces
+pub fn parse_condition(condition: &str) -> Result<Condition> {
+    let parts: Vec<&str> = condition.split_whitespace().collect();
+    let [operand, comparison, value] = parts[..] else {
+        return Err(anyhow!(
+            "Condition '{}' should look like 'V3 == 0x10' or 'I >= 0x300'",
+            condition
+        ));
+    };
+
+    let operand = parse_operand(operand)?;
+    let comparison = match comparison {
+        "==" => Comparison::Equal,
+        "!=" => Comparison::NotEqual,
+        "<" => Comparison::Less,
+        "<=" => Comparison::LessOrEqual,
+        ">" => Comparison::Greater,
+        ">=" => Comparison::GreaterOrEqual,
+        _ => return Err(anyhow!("Unknown comparison '{}'", comparison)),
+    };
+    return Ok(Condition {
+        operand,
+        comparison,
+        value: parse_address(value)?,
+    });
+}
+
+/// Parses an operand of a condition: a register name `V0` to `VF`, `I` or the program counter `PC`
+fn parse_operand(operand: &str) -> Result<Operand> {
+    if operand.eq_ignore_ascii_case("I") {
+        return Ok(Operand::I);
+    }
+    if operand.eq_ignore_ascii_case("PC") {
+        return Ok(Operand::ProgramCounter);
+    }
+    let register = operand
+        .strip_prefix(['V', 'v'])
+        .filter(|digit| digit.len() == 1)
+        .and_then(|digit| usize::from_str_radix(digit, 16).ok())
+        .ok_or_else(|| anyhow!("Unknown register '{}', expected V0 to VF, I or PC", operand))?;
+    return Ok(Operand::Register(register));
+}
+
+/// Parses an opcode pattern like `Dxyn` or `Fx0A` into a mask and value.
+/// Hexadecimal digits have to match exactly, any other character matches every nibble.
+pub fn parse_opcode_pattern(pattern: &str) -> Result<(u16, u16)> {
+    if pattern.chars().count() != 4 {
+        return Err(anyhow!(
+            "Opcode pattern '{}' should have exactly 4 characters",
+            pattern
+        ));
+    }
+    let mut mask = 0;
+    let mut value = 0;
+    for character in pattern.chars() {
+        mask <<= 4;
+        value <<= 4;
+        if let Some(digit) = character.to_digit(16) {
+            mask |= 0xF;
+            value |= digit as u16;
+        }
+    }
+    return Ok((mask, value));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcode::Opcode;
+    use crate::test_support::{cpu_with_program, ProgramBuilder};
+
+    /// Executes instructions until a breakpoint hits, returns the program counter it stopped at
+    fn run_until_break(debugger: &mut Debugger, cpu: &mut Cpu, max_cycles: usize) -> Option<u16> {
+        for _ in 0..max_cycles {
+            if debugger.should_break(cpu) {
+                return Some(cpu.program_counter());
+            }
+            cpu.execute_next_instruction().unwrap();
+        }
+        return None;
+    }
+
+    #[test]
+    fn opcode_breakpoint_stops_at_the_first_draw() {
+        let program = ProgramBuilder::new()
+            .ld(0, 1)
+            .font(0)
+            .draw(0, 0, 5)
+            .draw(0, 0, 5)
+            .halt()
+            .build();
+        let mut cpu = cpu_with_program(&program);
+        let mut debugger = Debugger::new();
+        let (mask, value) = parse_opcode_pattern("Dxyn").unwrap();
+        assert_eq!((mask, value), (0xF000, 0xD000));
+        debugger.add_opcode_breakpoint(mask, value);
+
+        assert_eq!(run_until_break(&mut debugger, &mut cpu, 10), Some(0x204));
+        // the draw has not been executed yet
+        assert!(cpu
+            .display_rows_hex()
+            .iter()
+            .all(|row| row.chars().all(|c| c == '0')));
+    }
+    #[test]
+    fn condition_breakpoint_stops_when_the_condition_becomes_true() {
+        let program = ProgramBuilder::new()
+            .add_kk(3, 4)
+            .opcode(Opcode::Jump { nnn: 0x200 })
+            .build();
+        let mut cpu = cpu_with_program(&program);
+        let mut debugger = Debugger::new();
+        debugger.add_condition_breakpoint(parse_condition("V3 == 0x10").unwrap());
+
+        assert_eq!(run_until_break(&mut debugger, &mut cpu, 100), Some(0x202));
+        assert_eq!(cpu.register(3), 0x10);
+        // 4 additions and the 3 jumps in between
+        assert_eq!(cpu.executed_instructions(), 7);
+
+        // the condition stays true until the next addition, which does not stop again
+        cpu.execute_next_instruction().unwrap();
+        assert!(!debugger.should_break(&cpu));
+        cpu.execute_next_instruction().unwrap();
+        assert_eq!(cpu.register(3), 0x14);
+
+        // V3 wraps around and becomes 0x10 again after 63 more additions, each after a jump
+        assert_eq!(run_until_break(&mut debugger, &mut cpu, 1000), Some(0x202));
+        assert_eq!(cpu.register(3), 0x10);
+        assert_eq!(cpu.executed_instructions(), 9 + 63 * 2);
+    }
+}
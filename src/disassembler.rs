@@ -0,0 +1,149 @@
+use core::fmt;
+
+use crate::opcode::Opcode;
+
+pub struct DisassembledInstruction {
+    pub address: u16,
+    pub opcode: u16,
+    /// `None` when the opcode is not a known instruction
+    pub decoded: Option<Opcode>,
+}
+
+/// Decodes every 2-byte word of the program in a linear sweep.
+/// Data embedded in the program is decoded as well, so it may show up as unknown instructions.
+pub fn disassemble(program: &[u8], start_address: u16) -> Vec<DisassembledInstruction> {
+    return program
+        .chunks_exact(2)
+        .enumerate()
+        .map(|(index, bytes)| {
+            let opcode = u16::from_be_bytes([bytes[0], bytes[1]]);
+            DisassembledInstruction {
+                address: start_address.wrapping_add(2 * index as u16),
+                opcode,
+                decoded: Opcode::decode(opcode),
+            }
+        })
+        .collect();
+}
+
+/// A line of a listing that separates code from data
+pub enum ListingLine {
+    Instruction {
+        address: u16,
+        opcode: u16,
+        decoded: Opcode,
+    },
+    /// Bytes that are never executed, `referenced` is set when `I` is pointed at the first byte, e.g. for a sprite
+    Data {
+        address: u16,
+        bytes: Vec<u8>,
+        referenced: bool,
+    },
+}
+
+/// Number of bytes per `db` line of the listing
+const DATA_BYTES_PER_LINE: usize = 8;
+
+/// Follows the control flow from the start address to find the bytes that are executed, everything else is data.
+/// Targets of `Bnnn` depend on V0 at runtime and can not be followed, code only reached through them is listed as data.
+pub fn disassemble_reachable(program: &[u8], start_address: u16) -> Vec<ListingLine> {
+    let end_address = start_address as usize + program.len();
+    let offset = |address: u16| address as usize - start_address as usize;
+    let mut is_code = vec![false; program.len()];
+    let mut is_referenced = vec![false; program.len()];
+    let mut pending = vec![start_address];
+    while let Some(address) = pending.pop() {
+        if (address as usize) < start_address as usize
+            || address as usize + 2 > end_address
+            || is_code[offset(address)]
+        {
+            continue;
+        }
+        let opcode = u16::from_be_bytes([program[offset(address)], program[offset(address) + 1]]);
+        let Some(decoded) = Opcode::decode(opcode) else {
+            // most likely data that is reached by a skip over it
+            continue;
+        };
+        is_code[offset(address)] = true;
+        is_code[offset(address) + 1] = true;
+
+        let next = address.wrapping_add(2);
+        match decoded {
+            Opcode::Jump { nnn } => pending.push(nnn),
+            Opcode::Call { nnn } => pending.extend([nnn, next]),
+            Opcode::Return | Opcode::JumpV0 { .. } => {}
+            Opcode::SkipIfEqualKk { .. }
+            | Opcode::SkipIfNotEqualKk { .. }
+            | Opcode::SkipIfEqualRegister { .. }
+            | Opcode::SkipIfNotEqualRegister { .. }
+            | Opcode::SkipIfKeyPressed { .. }
+            | Opcode::SkipIfKeyNotPressed { .. } => pending.extend([next, next.wrapping_add(2)]),
+            Opcode::SetI { nnn } => {
+                if (start_address as usize..end_address).contains(&(nnn as usize)) {
+                    is_referenced[offset(nnn)] = true;
+                }
+                pending.push(next);
+            }
+            _ => pending.push(next),
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut index = 0;
+    while index < program.len() {
+        let address = start_address.wrapping_add(index as u16);
+        if is_code[index] {
+            let opcode = u16::from_be_bytes([program[index], program[index + 1]]);
+            if let Some(decoded) = Opcode::decode(opcode) {
+                lines.push(ListingLine::Instruction {
+                    address,
+                    opcode,
+                    decoded,
+                });
+            }
+            index += 2;
+            continue;
+        }
+        // a data line ends before code and before the next byte that I points to
+        let mut length = 1;
+        while index + length < program.len()
+            && length < DATA_BYTES_PER_LINE
+            && !is_code[index + length]
+            && !is_referenced[index + length]
+        {
+            length += 1;
+        }
+        lines.push(ListingLine::Data {
+            address,
+            bytes: program[index..index + length].to_vec(),
+            referenced: is_referenced[index],
+        });
+        index += length;
+    }
+    return lines;
+}
+
+impl fmt::Display for ListingLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListingLine::Instruction {
+                address,
+                opcode,
+                decoded,
+            } => write!(f, "{:#05x}  {:04x}  {}", address, opcode, decoded),
+            ListingLine::Data {
+                address,
+                bytes,
+                referenced,
+            } => {
+                let bytes: Vec<String> =
+                    bytes.iter().map(|byte| format!("{:#04x}", byte)).collect();
+                write!(f, "{:#05x}        db {}", address, bytes.join(", "))?;
+                if *referenced {
+                    write!(f, "  ; loaded into I")?;
+                }
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::ops::RangeInclusive;
+
+#[cfg(test)]
+use anyhow::{anyhow, Result};
+
+/// Width of a CHIP-8X color zone in pixels
+pub const COLOR_ZONE_WIDTH: usize = 8;
+/// Height of a CHIP-8X color zone in pixels
+pub const COLOR_ZONE_HEIGHT: usize = 4;
+
+/// Foreground color code of the zones after power on, red
+const DEFAULT_FOREGROUND_COLOR: u8 = 1;
+
+/// Color attributes of the CHIP-8X color board: one background color for the whole screen
+/// and a foreground color per zone of 8x4 pixels
+#[derive(Clone, PartialEq)]
+pub struct ColorZones {
+    /// index into the background colors, cycled by `02A0`
+    background: u8,
+    /// number of zones per row
+    columns: usize,
+    rows: usize,
+    /// foreground color code of each zone, row by row
+    foreground: Vec<u8>,
+}
+
+impl ColorZones {
+    pub fn new(width: usize, height: usize) -> Self {
+        let columns = width.div_ceil(COLOR_ZONE_WIDTH);
+        let rows = height.div_ceil(COLOR_ZONE_HEIGHT);
+        return Self {
+            background: 0,
+            columns,
+            rows,
+            foreground: vec![DEFAULT_FOREGROUND_COLOR; columns * rows],
+        };
+    }
+
+    pub fn background(&self) -> u8 {
+        return self.background;
+    }
+
+    pub fn cycle_background(&mut self) {
+        self.background = (self.background + 1) % 4;
+    }
+
+    /// Foreground color code of the zone containing the pixel
+    pub fn foreground(&self, x: usize, y: usize) -> u8 {
+        return self.foreground[(y / COLOR_ZONE_HEIGHT) * self.columns + x / COLOR_ZONE_WIDTH];
+    }
+
+    /// Sets the foreground color code of the zones, zones beyond the screen are ignored
+    pub fn set_foreground(
+        &mut self,
+        columns: RangeInclusive<usize>,
+        rows: RangeInclusive<usize>,
+        color: u8,
+    ) {
+        for row in rows.filter(|row| *row < self.rows) {
+            for column in columns.clone().filter(|column| *column < self.columns) {
+                self.foreground[row * self.columns + column] = color;
+            }
+        }
+    }
+}
+
+/// Pixels of the emulated monochrome display, stored row by row
+#[derive(Clone, PartialEq)]
+pub struct Display {
+    width: usize,
+    height: usize,
+    pixels: Vec<bool>,
+    /// remaining 60 Hz frames each pixel is highlighted for after a sprite collision
+    highlights: Vec<u8>,
+    /// number of sprite draws that erased each pixel since the start
+    collision_counts: Vec<u32>,
+    /// only present when emulating CHIP-8X
+    color_zones: Option<ColorZones>,
+}
+
+impl Display {
+    pub fn new(width: usize, height: usize) -> Self {
+        return Self {
+            width,
+            height,
+            pixels: vec![false; width * height],
+            highlights: vec![0; width * height],
+            collision_counts: vec![0; width * height],
+            color_zones: None,
+        };
+    }
+
+    pub fn width(&self) -> usize {
+        return self.width;
+    }
+
+    pub fn height(&self) -> usize {
+        return self.height;
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        return self.pixels[y * self.width + x];
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, value: bool) {
+        self.pixels[y * self.width + x] = value;
+    }
+
+    pub fn color_zones(&self) -> Option<&ColorZones> {
+        return self.color_zones.as_ref();
+    }
+
+    pub fn color_zones_mut(&mut self) -> Option<&mut ColorZones> {
+        return self.color_zones.as_mut();
+    }
+
+    /// Adds CHIP-8X color zones in their power on colors, replacing the previous ones
+    pub fn enable_color_zones(&mut self) {
+        self.color_zones = Some(ColorZones::new(self.width, self.height));
+    }
+
+    pub fn is_highlighted(&self, x: usize, y: usize) -> bool {
+        return self.highlights[y * self.width + x] > 0;
+    }
+
+    pub fn highlight(&mut self, x: usize, y: usize, frames: u8) {
+        self.highlights[y * self.width + x] = frames;
+    }
+
+    pub fn collision_count(&self, x: usize, y: usize) -> u32 {
+        return self.collision_counts[y * self.width + x];
+    }
+
+    pub fn max_collision_count(&self) -> u32 {
+        return self.collision_counts.iter().copied().max().unwrap_or(0);
+    }
+
+    pub fn count_collision(&mut self, x: usize, y: usize) {
+        let count = &mut self.collision_counts[y * self.width + x];
+        *count = count.saturating_add(1);
+    }
+
+    /// Shortens the highlights by the elapsed frames, returns whether any highlight changed
+    pub fn decay_highlights(&mut self, elapsed_frames: u8) -> bool {
+        let mut changed = false;
+        for highlight in self
+            .highlights
+            .iter_mut()
+            .filter(|highlight| **highlight > 0)
+        {
+            *highlight = highlight.saturating_sub(elapsed_frames);
+            changed = true;
+        }
+        return changed;
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.fill(false);
+    }
+
+    /// The pixels row by row with 8 pixels per byte, the most significant bit first.
+    /// The last byte is padded with zeros when the pixel count is not a multiple of 8.
+    pub fn packed(&self) -> Vec<u8> {
+        return self
+            .pixels
+            .chunks(8)
+            .map(|pixels| {
+                pixels.iter().enumerate().fold(0u8, |byte, (index, pixel)| {
+                    byte | (*pixel as u8) << (7 - index)
+                })
+            })
+            .collect();
+    }
+
+    /// Restores the pixels of a display of the given size from the output of `packed`.
+    /// Only the tests decode displays, clients of `--serve` do the same on their side.
+    #[cfg(test)]
+    pub fn from_packed(width: usize, height: usize, packed: &[u8]) -> Result<Self> {
+        let pixel_count = width * height;
+        if packed.len() != pixel_count.div_ceil(8) {
+            return Err(anyhow!(
+                "{} packed bytes do not match a {}x{} display",
+                packed.len(),
+                width,
+                height
+            ));
+        }
+        let mut display = Display::new(width, height);
+        for (index, pixel) in display.pixels.iter_mut().enumerate() {
+            *pixel = packed[index / 8] & (0x80 >> (index % 8)) != 0;
+        }
+        return Ok(display);
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[bool]> {
+        return self.pixels.chunks(self.width);
+    }
+}
+
+/// One line per sprite row with `#` for set and `.` for unset pixels, the most significant bit is the left most pixel
+pub fn sprite_ascii_rows(sprite: &[u8]) -> Vec<String> {
+    return sprite
+        .iter()
+        .map(|row| {
+            (0..8)
+                .map(|bit| if row & (0x80 >> bit) != 0 { '#' } else { '.' })
+                .collect()
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packed_pixels_survive_unpacking() {
+        // 10x5 has 50 pixels, so the last byte is padded
+        let mut display = Display::new(10, 5);
+        for (x, y) in [(0, 0), (9, 0), (3, 2), (7, 3), (8, 4), (9, 4)] {
+            display.set_pixel(x, y, true);
+        }
+        let packed = display.packed();
+        assert_eq!(packed.len(), 7);
+        assert_eq!(packed[0], 0b1000_0000);
+        assert!(Display::from_packed(10, 5, &packed).unwrap() == display);
+        assert!(Display::from_packed(10, 6, &packed).is_err());
+    }
+}
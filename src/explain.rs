@@ -0,0 +1,232 @@
+use crate::cpu::InstructionContext;
+use crate::opcode::Opcode;
+
+/// Describes in plain English what the executed instruction did, for people learning how Chip-8 works,
+/// e.g. `Set register V2 to 0x0a`
+pub fn explain(context: &InstructionContext) -> String {
+    let before = &context.general_registers_before;
+    let after = context.general_registers;
+    let skipped = context.next_address == context.address.wrapping_add(4);
+    // the reason a skip was taken and the reason it was not taken
+    let skip = |taken: String, not_taken: String| {
+        if skipped {
+            format!("Skipped the next instruction because {}", taken)
+        } else {
+            format!("Did not skip the next instruction because {}", not_taken)
+        }
+    };
+    let compare = |left: String, operator: &str, opposite: &str, right: String| {
+        skip(
+            format!("{} {} {}", left, operator, right),
+            format!("{} {} {}", left, opposite, right),
+        )
+    };
+    let register = |x: u8| format!("V{:X} ({:#04x})", x, before[x as usize]);
+    let key = |x: u8| format!("key {:X} (from V{:X})", before[x as usize] & 0xF, x);
+    let flag = after[0xF];
+
+    return match context.decoded {
+        Opcode::Nop => "Did nothing".to_string(),
+        Opcode::ClearDisplay => "Cleared the display".to_string(),
+        Opcode::Return => format!(
+            "Returned from the subroutine to {:#05x}",
+            context.next_address
+        ),
+        Opcode::Jump { nnn } => format!("Jumped to {:#05x}", nnn),
+        Opcode::Call { nnn } => format!("Called the subroutine at {:#05x}", nnn),
+        Opcode::SkipIfEqualKk { x, kk } => {
+            compare(register(x), "==", "!=", format!("{:#04x}", kk))
+        }
+        Opcode::SkipIfNotEqualKk { x, kk } => {
+            compare(register(x), "!=", "==", format!("{:#04x}", kk))
+        }
+        Opcode::SkipIfEqualRegister { x, y } => compare(register(x), "==", "!=", register(y)),
+        Opcode::SkipIfNotEqualRegister { x, y } => compare(register(x), "!=", "==", register(y)),
+        Opcode::SetRegister { x, kk } => format!("Set register V{:X} to {:#04x}", x, kk),
+        Opcode::AddKk { x, kk } => format!(
+            "Added {:#04x} to V{:X}, which is now {:#04x} (VF is not changed)",
+            kk, x, after[x as usize]
+        ),
+        Opcode::CopyRegister { x, y } => format!(
+            "Copied V{:X} into V{:X}, which is now {:#04x}",
+            y, x, after[x as usize]
+        ),
+        Opcode::Or { x, y } => format!(
+            "Set V{:X} to V{:X} OR V{:X} = {:#04x}",
+            x, x, y, after[x as usize]
+        ),
+        Opcode::And { x, y } => format!(
+            "Set V{:X} to V{:X} AND V{:X} = {:#04x}",
+            x, x, y, after[x as usize]
+        ),
+        Opcode::Xor { x, y } => format!(
+            "Set V{:X} to V{:X} XOR V{:X} = {:#04x}",
+            x, x, y, after[x as usize]
+        ),
+        Opcode::Add { x, y } => format!(
+            "Added V{:X} ({:#04x}) to V{:X} ({:#04x}), VF = {} because the sum {}",
+            y,
+            before[y as usize],
+            x,
+            before[x as usize],
+            flag,
+            if flag == 1 { "overflowed" } else { "fit into 8 bits" }
+        ),
+        Opcode::Sub { x, y } => format!(
+            "Subtracted V{:X} ({:#04x}) from V{:X} ({:#04x}), VF = {} because the subtraction {}",
+            y,
+            before[y as usize],
+            x,
+            before[x as usize],
+            flag,
+            if flag == 1 { "did not borrow" } else { "borrowed" }
+        ),
+        Opcode::Subn { x, y } => format!(
+            "Set V{:X} to V{:X} ({:#04x}) minus V{:X} ({:#04x}), VF = {} because the subtraction {}",
+            x,
+            y,
+            before[y as usize],
+            x,
+            before[x as usize],
+            flag,
+            if flag == 1 { "did not borrow" } else { "borrowed" }
+        ),
+        Opcode::ShiftRight { x, y } => format!(
+            "Shifted V{:X} ({:#04x}) right by one bit into V{:X}, VF = {} is the bit shifted out",
+            y, before[y as usize], x, flag
+        ),
+        Opcode::ShiftLeft { x, y } => format!(
+            "Shifted V{:X} ({:#04x}) left by one bit into V{:X}, VF = {} is the bit shifted out",
+            y, before[y as usize], x, flag
+        ),
+        Opcode::SetI { nnn } => format!("Set I to {:#05x}", nnn),
+        Opcode::JumpV0 { nnn } => format!(
+            "Jumped to {:#05x} + V0 ({:#04x}) = {:#05x}",
+            nnn, before[0], context.next_address
+        ),
+        Opcode::Random { x, kk } => format!(
+            "Set V{:X} to a random number AND {:#04x} = {:#04x}",
+            x, kk, after[x as usize]
+        ),
+        Opcode::DrawSprite { x, y, n } => format!(
+            "Drew the {} byte sprite at I = {:#05x} at ({}, {}), VF = {} because {}",
+            n,
+            context.i,
+            before[x as usize],
+            before[y as usize],
+            flag,
+            if flag == 1 {
+                "a pixel was erased"
+            } else {
+                "no pixel was erased"
+            }
+        ),
+        Opcode::SkipIfKeyPressed { x } => skip(
+            format!("{} is pressed", key(x)),
+            format!("{} is not pressed", key(x)),
+        ),
+        Opcode::SkipIfKeyNotPressed { x } => skip(
+            format!("{} is not pressed", key(x)),
+            format!("{} is pressed", key(x)),
+        ),
+        Opcode::LoadDelayTimer { x } => format!(
+            "Set V{:X} to the delay timer, {:#04x}",
+            x, after[x as usize]
+        ),
+        Opcode::WaitForKey { x } => format!(
+            "Started waiting until a key is pressed and released, the key will be stored in V{:X}",
+            x
+        ),
+        Opcode::SetDelayTimer { x } => format!(
+            "Set the delay timer to V{:X} ({:#04x}), it counts down 60 times per second",
+            x, before[x as usize]
+        ),
+        Opcode::SetSoundTimer { x } => format!(
+            "Set the sound timer to V{:X} ({:#04x}), a beep plays until it counted down to 0",
+            x, before[x as usize]
+        ),
+        Opcode::AddI { x } => format!(
+            "Added V{:X} ({:#04x}) to I, which is now {:#05x}",
+            x, before[x as usize], context.i
+        ),
+        Opcode::SetIToSprite { x } => format!(
+            "Set I to the font sprite of the digit {:X} (from V{:X}) at {:#05x}",
+            before[x as usize] & 0xF,
+            x,
+            context.i
+        ),
+        Opcode::StoreBcd { x } => format!(
+            "Stored the decimal digits of V{:X} ({}) at I = {:#05x} and the 2 bytes behind it",
+            x, before[x as usize], context.i
+        ),
+        Opcode::StoreRegisters { x } => format!(
+            "Stored V0 to V{:X} in memory starting at I = {:#05x}, I is now {:#05x}",
+            x, context.i_before, context.i
+        ),
+        Opcode::LoadRegisters { x } => format!(
+            "Loaded V0 to V{:X} from memory starting at I = {:#05x}, I is now {:#05x}",
+            x, context.i_before, context.i
+        ),
+        Opcode::CycleBackgroundColor => "Switched to the next CHIP-8X background color".to_string(),
+        Opcode::AddNibbles { x, y } => format!(
+            "Added the nibbles of V{:X} ({:#04x}) to the nibbles of V{:X} ({:#04x}) without carry, V{:X} is now {:#04x}",
+            y,
+            before[y as usize],
+            x,
+            before[x as usize],
+            x,
+            after[x as usize]
+        ),
+        Opcode::SetForegroundColor { x, y } => format!(
+            "Set the CHIP-8X foreground color of the zones described by V{:X} and V{:X} to {} (from V{:X})",
+            x,
+            (x + 1) % 16,
+            before[y as usize] & 0x7,
+            y
+        ),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::test_support::cpu_with_program;
+
+    /// Runs the program and explains every executed instruction
+    fn explain_program(program: &[u8], instructions: usize) -> Vec<String> {
+        let mut cpu = cpu_with_program(program);
+        let explanations = Arc::new(Mutex::new(Vec::new()));
+        let hook_explanations = Arc::clone(&explanations);
+        cpu.set_instruction_hook(Box::new(move |context| {
+            hook_explanations.lock().unwrap().push(explain(context));
+        }));
+        for _ in 0..instructions {
+            cpu.execute_next_instruction().unwrap();
+        }
+        return explanations.lock().unwrap().clone();
+    }
+
+    #[test]
+    fn explains_registers_skips_and_carries() {
+        let program = rom![
+            SetRegister { x: 2, kk: 0x0A },
+            SkipIfEqualRegister { x: 3, y: 4 },
+            ClearDisplay,
+            SetRegister { x: 1, kk: 0xFF },
+            Add { x: 1, y: 2 },
+            SkipIfEqualKk { x: 1, kk: 0x00 },
+        ];
+        assert_eq!(
+            explain_program(&program, 5),
+            [
+                "Set register V2 to 0x0a",
+                "Skipped the next instruction because V3 (0x00) == V4 (0x00)",
+                "Set register V1 to 0xff",
+                "Added V2 (0x0a) to V1 (0xff), VF = 1 because the sum overflowed",
+                "Did not skip the next instruction because V1 (0x09) != 0x00",
+            ]
+        );
+    }
+}
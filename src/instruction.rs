@@ -16,19 +16,6 @@ impl Instruction {
         };
     }
 
-    pub fn nibbles_lo(&self) -> (u8, u8, u8, u8) {
-        return (
-            self.first_nibble() as u8,
-            self.second_nibble() as u8,
-            self.third_nibble() as u8,
-            self.fourth_nibble() as u8,
-        );
-    }
-
-    pub fn first_nibble(&self) -> U4 {
-        return self.bytes[0].left();
-    }
-
     pub fn second_nibble(&self) -> U4 {
         return self.bytes[0].right();
     }
@@ -47,6 +34,11 @@ impl Instruction {
         return self.third_nibble();
     }
 
+    /// Number of sprite rows of Dxyn, 0 to 15
+    pub fn n(&self) -> u8 {
+        return self.fourth_nibble() as u8;
+    }
+
     pub fn kk(&self) -> u8 {
         return self.bytes[1].packed;
     }
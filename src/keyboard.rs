@@ -1,9 +1,14 @@
-use std::collections::HashSet;
+use std::time::Duration;
 
 use minifb::Key;
-use tracing::{debug, info};
+use tracing::{debug, info, trace};
 use u4::{U4x2, U4};
 
+/// Defaults of the window library for repeating held keys
+pub const DEFAULT_KEY_REPEAT_DELAY: Duration = Duration::from_millis(250);
+pub const DEFAULT_KEY_REPEAT_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Clone)]
 pub struct KeysChange {
     pub pressed: Vec<Key>,
     pub released: Vec<Key>,
@@ -12,14 +17,18 @@ pub struct KeysChange {
 type KeysPressedReceiver = std::sync::mpsc::Receiver<KeysChange>;
 
 pub struct Keyboard {
-    pressed_keys: HashSet<u4::U4>,
+    /// held keys in the order they were pressed, the most recent press last
+    pressed_keys: Vec<U4>,
+    /// keys pressed since the latch was last reset, including keys that were released again in the meantime
+    latched_presses: Vec<U4>,
     key_receiver: KeysPressedReceiver,
 }
 
 impl Keyboard {
     pub fn new(key_receiver: KeysPressedReceiver) -> Self {
         return Self {
-            pressed_keys: HashSet::new(),
+            pressed_keys: Vec::new(),
+            latched_presses: Vec::new(),
             key_receiver,
         };
     }
@@ -29,9 +38,23 @@ impl Keyboard {
         return self.pressed_keys.contains(chip_8_key);
     }
 
-    pub fn get_pressed_key(&mut self) -> Option<U4> {
+    /// Resets the latched key presses to the keys that are currently held
+    pub fn reset_latched_presses(&mut self) {
+        self.update_pressed_keys();
+        self.latched_presses = self.pressed_keys.clone();
+    }
+
+    /// Returns the most recently pressed key since the latch was last reset and resets the latch.
+    /// Keys that were already held at the reset count as pressed in the order they were pressed.
+    /// Short presses are not lost, even when the key is already released again.
+    pub fn take_latched_press(&mut self) -> Option<U4> {
         self.update_pressed_keys();
-        return self.pressed_keys.iter().next().cloned();
+        let key = self.latched_presses.last().cloned();
+        if let Some(key) = key {
+            trace!("Fx0A captured key {:X}", key as u8);
+        }
+        self.latched_presses.clear();
+        return key;
     }
 
     fn update_pressed_keys(&mut self) {
@@ -39,19 +62,53 @@ impl Keyboard {
             for pressed in changed_keys.pressed.iter() {
                 if let Some(pressed_chip_8_key) = to_chip_8_key(*pressed) {
                     debug!("keyboard insert: {:?}", pressed_chip_8_key);
-                    self.pressed_keys.insert(pressed_chip_8_key);
+                    trace!(
+                        "Chip-8 key {:X} pressed with {:?}",
+                        pressed_chip_8_key as u8,
+                        pressed
+                    );
+                    // a repeated press moves the key to the end, as it is the most recent press now
+                    self.pressed_keys.retain(|key| *key != pressed_chip_8_key);
+                    self.pressed_keys.push(pressed_chip_8_key);
+                    self.latched_presses
+                        .retain(|key| *key != pressed_chip_8_key);
+                    self.latched_presses.push(pressed_chip_8_key);
                 }
             }
             for released in changed_keys.released.iter() {
                 if let Some(released_chip_8_key) = to_chip_8_key(*released) {
                     debug!("keyboard remove: {:?}", released_chip_8_key);
-                    self.pressed_keys.remove(&released_chip_8_key);
+                    self.pressed_keys.retain(|key| *key != released_chip_8_key);
                 }
             }
         }
     }
 }
 
+/// Window keys of the Chip-8 keys `0` to `F`
+const WINDOW_KEYS: [Key; 16] = [
+    Key::Key0,
+    Key::Key1,
+    Key::Key2,
+    Key::Key3,
+    Key::Key4,
+    Key::Key5,
+    Key::Key6,
+    Key::Key7,
+    Key::Key8,
+    Key::Key9,
+    Key::A,
+    Key::B,
+    Key::C,
+    Key::D,
+    Key::E,
+    Key::F,
+];
+
+pub fn to_window_key(chip_8_key: U4) -> Key {
+    return WINDOW_KEYS[chip_8_key as usize];
+}
+
 fn to_chip_8_key(key: Key) -> Option<U4> {
     if is_valid_key_code(key) {
         return Some(U4x2::from(key as u8).right());
@@ -61,6 +118,16 @@ fn to_chip_8_key(key: Key) -> Option<U4> {
     }
 }
 
-fn is_valid_key_code(key: Key) -> bool {
+/// Which of the Chip-8 keys are among the held window keys, indexed by the Chip-8 key
+pub fn held_chip_8_keys(keys: &[Key]) -> [bool; 16] {
+    let mut held_keys = [false; 16];
+    for key in keys.iter().filter(|key| is_valid_key_code(**key)) {
+        held_keys[*key as usize] = true;
+    }
+    return held_keys;
+}
+
+/// Whether the key is mapped to one of the Chip-8 keys
+pub fn is_valid_key_code(key: Key) -> bool {
     return key as u8 <= Key::F as u8;
 }
@@ -1,8 +1,10 @@
 use tracing::Level;
 
-pub fn setup_logging() {
+pub fn setup_logging(max_level: Level) {
     let subscriber = tracing_subscriber::FmtSubscriber::builder()
-        .with_max_level(Level::WARN)
+        .with_max_level(max_level)
+        // keeps stdout free for the output of the rom and of options like --dump-state-json
+        .with_writer(std::io::stderr)
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 }
@@ -1,109 +1,1238 @@
 use anyhow::{anyhow, Result};
 use logging::setup_logging;
-use minifb::{Key, KeyRepeat, Scale, ScaleMode, Window, WindowOptions};
+use minifb::{Key, KeyRepeat, ScaleMode, Window, WindowOptions};
 use std::{
     env::{self},
-    fs, thread,
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    sync::mpsc::{self, RecvTimeoutError, TryRecvError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
-use cpu::Cpu;
+use autofire::{Autofire, AUTOFIRE_MODIFIER_KEY};
+use call_trace::CallTrace;
+use cli::Options;
+use control::{ControlCommand, ControlReceiver, ControlSender, CpuEvent, CpuEventSender};
+use coverage::Coverage;
+use cpu::{Cpu, CpuState, INSTRUCTIONS_PER_FRAME};
+use debugger::Debugger;
 use keyboard::Keyboard;
-use renderer::{Renderer, SCREEN_HEIGHT, SCREEN_WIDTH};
+use memory::{FONT_CHARACTER_SIZE, PROGRAM_START_ADDRESS};
+use opcode::Opcode;
+use playlist::Playlist;
+use presenter::Presenter;
+use profile::Profile;
+use random::{OsRandom, RandomSource, SeededRandom};
+use renderer::{DisplayReceiver, DisplaySender, Renderer};
 
+#[cfg(test)]
+#[macro_use]
+mod test_support;
+
+mod archive;
 mod audio;
+mod autofire;
+mod call_trace;
+mod cli;
+mod compatibility;
+mod config;
+mod control;
+mod coverage;
 mod cpu;
+mod crash_dump;
+mod debugger;
+mod disassembler;
+mod display;
+mod explain;
 mod instruction;
 mod keyboard;
 mod logging;
 mod memory;
+mod octo;
+mod opcode;
+mod palette;
+mod playlist;
+mod presenter;
+mod profile;
 mod program_counter;
+mod quirks;
+mod random;
 mod renderer;
+mod server;
+mod splash;
+mod timing;
 
-#[allow(clippy::eq_op, clippy::identity_op)]
-const BACKGROUND_COLOR_RGB: u32 = 0x00 << 16 | 0x00 << 8 | 0x00;
-#[allow(clippy::eq_op, clippy::identity_op)]
-const FOREGROUND_COLOR_RGB: u32 = 0x00 << 16 | 0x99 << 8 | 0x00;
+const WINDOW_TITLE: &str = "Chip-8 Emulator";
+const PAUSE_KEY: Key = Key::P;
+const STEP_KEY: Key = Key::N;
+const FRAME_STEP_KEY: Key = Key::Period;
+const STEP_OVER_KEY: Key = Key::O;
+const STEP_OUT_KEY: Key = Key::U;
+const RUN_UNTIL_DISPLAY_CHANGE_KEY: Key = Key::R;
+const VOLUME_UP_KEY: Key = Key::Equal;
+const VOLUME_DOWN_KEY: Key = Key::Minus;
+const MUTE_KEY: Key = Key::M;
+const GRID_KEY: Key = Key::G;
+const CHANGES_KEY: Key = Key::V;
+const COLLISION_MAP_KEY: Key = Key::H;
+const NEXT_ROM_KEY: Key = Key::Tab;
+/// restarts the rom, together with shift the memory is cleared as well
+const RESET_KEY: Key = Key::F1;
+const KEYPAD_KEY: Key = Key::K;
+/// Limits of the instructions run between polling for commands
+const MAX_BATCH_CYCLES: usize = 1000;
+const BATCH_DURATION: Duration = Duration::from_millis(1);
+/// Limit of the instructions run while waiting for a display change, for roms that never draw again
+const MAX_DISPLAY_CHANGE_CYCLES: u64 = 1_000_000;
+/// Instructions each rom runs in batch mode when no `--cycles` are given
+const DEFAULT_BATCH_CYCLES: u64 = 10_000;
+/// How often the instruction and timer rates are measured
+const RATE_METER_INTERVAL: Duration = Duration::from_secs(1);
+/// Keys that control the emulator instead of the Chip-8
+const HOTKEYS: [Key; 19] = [
+    Key::Escape,
+    PAUSE_KEY,
+    STEP_KEY,
+    FRAME_STEP_KEY,
+    STEP_OVER_KEY,
+    STEP_OUT_KEY,
+    RUN_UNTIL_DISPLAY_CHANGE_KEY,
+    VOLUME_UP_KEY,
+    VOLUME_DOWN_KEY,
+    MUTE_KEY,
+    GRID_KEY,
+    CHANGES_KEY,
+    COLLISION_MAP_KEY,
+    NEXT_ROM_KEY,
+    RESET_KEY,
+    KEYPAD_KEY,
+    AUTOFIRE_MODIFIER_KEY,
+    // shift is needed for `+` on most layouts and for the hard reset
+    Key::LeftShift,
+    Key::RightShift,
+];
+const VOLUME_STEP: f32 = 0.1;
 
 fn main() -> Result<()> {
-    setup_logging();
-
     let args: Vec<String> = env::args().collect();
+    let options = Options::parse(&args)?;
+    setup_logging(options.log_level);
+
+    if let Some(directory) = &options.batch_directory {
+        return run_batch(&options, directory);
+    }
 
-    let rom: Vec<u8> = if args.len() > 1 {
-        load_rom(&args[1])?
+    let mut playlist = match &options.playlist_directory {
+        Some(directory) => Some(Playlist::new(directory)?),
+        None => None,
+    };
+    let rom: Vec<u8> = if let Some(playlist) = playlist.as_mut() {
+        playlist.load_first(|rom| check_rom(&options, rom))?
     } else {
-        info!("No rom provided, using default rom");
-        load_rom("./roms/test/1-chip8-logo.ch8")?
+        let rom = if let Some(source_path) = &options.assemble_path {
+            let source = fs::read_to_string(source_path)
+                .map_err(|e| anyhow!("Failed to read '{}': {}", source_path, e))?;
+            octo::assemble(&source)
+                .map_err(|e| anyhow!("Failed to assemble '{}': {}", source_path, e))?
+        } else if let Some(rom_path) = &options.rom_path {
+            load_rom(rom_path, options.zip_entry.as_deref())?
+        } else {
+            info!("No rom provided, using default rom");
+            load_rom("./roms/test/1-chip8-logo.ch8", None)?
+        };
+        check_rom(&options, &rom)?;
+        rom
+    };
+
+    let memory_image = match &options.memory_image_path {
+        Some(path) => Some(load_memory_image(path, options.cpu_config.memory_size)?),
+        None => None,
     };
 
+    let overlays = options
+        .overlays
+        .iter()
+        .map(|(address, path)| {
+            let bytes =
+                fs::read(path).map_err(|e| anyhow!("Failed to read overlay '{}': {}", path, e))?;
+            return Ok((*address, bytes));
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if options.show_font {
+        return show_font(&options, &rom, memory_image.as_deref(), &overlays);
+    }
+
+    if options.validate {
+        return validate_rom(&rom, options.quiet);
+    }
+
+    if options.disassemble {
+        for line in disassembler::disassemble_reachable(&rom, PROGRAM_START_ADDRESS) {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+
+    if options.headless {
+        return run_headless(&options, &rom, memory_image.as_deref(), &overlays);
+    }
+
+    if let Some(address) = &options.serve_address {
+        return run_server(&options, address, rom, memory_image, overlays);
+    }
+
+    let display_count = if options.compare_quirks { 2 } else { 1 };
+    let mut presenter = Presenter::new(
+        options.palette,
+        options.screen_width,
+        options.screen_height,
+        display_count,
+    );
     let mut window = Window::new(
-        "Chip-8 Emulator",
-        SCREEN_WIDTH,
-        SCREEN_HEIGHT,
+        WINDOW_TITLE,
+        presenter.width(),
+        presenter.height(),
         WindowOptions {
             resize: true,
-            scale: Scale::X16,
             scale_mode: ScaleMode::AspectRatioStretch,
             ..WindowOptions::default()
         },
     )?;
 
+    // repeated presses of a held key are sent to the cpu as new presses
+    let key_repeat = if options.key_repeat {
+        window.set_key_repeat_delay(options.key_repeat_delay.as_secs_f32());
+        window.set_key_repeat_rate(options.key_repeat_interval.as_secs_f32());
+        KeyRepeat::Yes
+    } else {
+        KeyRepeat::No
+    };
+
     let (mut display_receiver, display_sender) = single_value_channel::channel();
-    let (pressed_keys_sender, keyboard_receiver) = std::sync::mpsc::channel();
+    let (pressed_keys_sender, keyboard_receiver) = mpsc::channel();
+    let (control_sender, control_receiver) = mpsc::channel();
+    let (event_sender, event_receiver) = mpsc::channel();
+
+    if options.repl {
+        debugger::spawn_repl(control_sender.clone(), options.quiet);
+    }
 
-    let renderer = Renderer::new(display_sender);
-    let keyboard = Keyboard::new(keyboard_receiver);
+    let mut debugger = Debugger::new();
+    for address in options.breakpoints.iter() {
+        debugger.add_breakpoint(*address);
+    }
+    for (mask, value) in options.opcode_breakpoints.iter() {
+        debugger.add_opcode_breakpoint(*mask, *value);
+    }
+    for condition in options.condition_breakpoints.iter() {
+        debugger.add_condition_breakpoint(*condition);
+    }
 
-    let mut frame_buffer: [u32; SCREEN_WIDTH * SCREEN_HEIGHT] = [0; SCREEN_WIDTH * SCREEN_HEIGHT];
+    // the playing rom, loaded again by the reset key
+    let mut current_rom = rom.clone();
+    let mut cpu_threads = vec![spawn_cpu(
+        options.clone(),
+        rom.clone(),
+        memory_image.clone(),
+        overlays.clone(),
+        display_sender,
+        keyboard_receiver,
+        debugger,
+        control_receiver,
+        event_sender,
+    )];
 
-    thread::spawn(move || {
-        let mut cpu = Cpu::new(renderer, keyboard);
-        cpu.load_program_into_memory(&rom);
-        loop {
-            cpu.run_cycle();
+    // the second cpu mirrors the keyboard and the controls, but has no breakpoints, reports no events and plays no sound.
+    // It is paused whenever the first one pauses, e.g. at a breakpoint, so both runs stay comparable.
+    let mut comparison: Option<Comparison> = None;
+    let mut comparison_control_sender: Option<ControlSender> = None;
+    if options.compare_quirks {
+        let mut comparison_options = options.clone();
+        comparison_options.cpu_config.quirks = options.cpu_config.quirks.toggled();
+        comparison_options.cpu_config.audio = false;
+        comparison_options.call_trace_path = None;
+        comparison_options.profile_path = None;
+        comparison_options.coverage_path = None;
+        comparison_options.crash_dump_path = None;
+        let (display_receiver, display_sender) = single_value_channel::channel();
+        let (pressed_keys_sender, keyboard_receiver) = mpsc::channel();
+        let (control_sender, control_receiver) = mpsc::channel();
+        let (event_sender, event_receiver) = mpsc::channel();
+        cpu_threads.push(spawn_cpu(
+            comparison_options,
+            rom,
+            memory_image,
+            overlays,
+            display_sender,
+            keyboard_receiver,
+            Debugger::new(),
+            control_receiver,
+            event_sender,
+        ));
+        comparison = Some(Comparison {
+            display_receiver,
+            pressed_keys_sender,
+            _event_receiver: event_receiver,
+        });
+        comparison_control_sender = Some(control_sender);
+    }
+    let send_control = |command: ControlCommand| -> Result<()> {
+        if let Some(comparison_control_sender) = &comparison_control_sender {
+            comparison_control_sender.send(command.clone())?;
+        }
+        control_sender.send(command)?;
+        return Ok(());
+    };
+
+    let mut autofire = options
+        .autofire_key
+        .map(|key| Autofire::new(keyboard::to_window_key(key), options.autofire_rate));
+
+    // display updates that were replaced by a newer one before the window presented them
+    let mut dropped_display_updates: u64 = 0;
+    let mut presented_sequence: u64 = 0;
+
+    let mut paused_state: Option<CpuState> = None;
+    // the last pressed key that neither is a Chip-8 key nor a hotkey and the number of such presses
+    let mut unmapped_keys: Option<(Key, u64)> = None;
+    // instructions and timer frames per second of the last measurement
+    let mut rates: Option<(f64, f64)> = None;
+
+    // the file name of the playing rom is shown in the title when playing a playlist
+    let mut rom_name = playlist
+        .as_ref()
+        .map(|playlist| file_name(playlist.current_path()));
+    if rom_name.is_some() {
+        window.set_title(&window_title(
+            rom_name.as_deref(),
+            paused_state.as_ref(),
+            unmapped_keys,
+            rates,
+        ));
+    }
+
+    // a cpu thread stops on its own when the watchdog expired or it panicked
+    while window.is_open()
+        && !window.is_key_down(Key::Escape)
+        && !cpu_threads
+            .iter()
+            .any(|cpu_thread| cpu_thread.is_finished())
+    {
+        if window.is_key_pressed(PAUSE_KEY, KeyRepeat::No) {
+            send_control(ControlCommand::TogglePause)?;
+        }
+        if window.is_key_pressed(STEP_KEY, KeyRepeat::Yes) {
+            send_control(ControlCommand::Step)?;
+        }
+        if window.is_key_pressed(STEP_OVER_KEY, KeyRepeat::Yes) {
+            send_control(ControlCommand::StepOver)?;
+        }
+        if window.is_key_pressed(STEP_OUT_KEY, KeyRepeat::No) {
+            send_control(ControlCommand::StepOut)?;
+        }
+        if window.is_key_pressed(RUN_UNTIL_DISPLAY_CHANGE_KEY, KeyRepeat::No) {
+            send_control(ControlCommand::RunUntilDisplayChange)?;
+        }
+        if window.is_key_pressed(FRAME_STEP_KEY, KeyRepeat::Yes) {
+            send_control(ControlCommand::StepFrame)?;
+        }
+        if window.is_key_pressed(VOLUME_UP_KEY, KeyRepeat::Yes) {
+            send_control(ControlCommand::ChangeVolume(VOLUME_STEP))?;
+        }
+        if window.is_key_pressed(VOLUME_DOWN_KEY, KeyRepeat::Yes) {
+            send_control(ControlCommand::ChangeVolume(-VOLUME_STEP))?;
+        }
+        if window.is_key_pressed(MUTE_KEY, KeyRepeat::No) {
+            send_control(ControlCommand::ToggleMute)?;
+        }
+        if window.is_key_pressed(RESET_KEY, KeyRepeat::No) {
+            send_control(ControlCommand::Reset {
+                program: current_rom.clone(),
+                hard: window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift),
+            })?;
+        }
+        if window.is_key_pressed(GRID_KEY, KeyRepeat::No) {
+            presenter.show_grid = !presenter.show_grid;
+        }
+        if window.is_key_pressed(CHANGES_KEY, KeyRepeat::No) {
+            presenter.show_changes = !presenter.show_changes;
+        }
+        if window.is_key_pressed(COLLISION_MAP_KEY, KeyRepeat::No) {
+            presenter.show_collision_map = !presenter.show_collision_map;
+        }
+        if window.is_key_pressed(KEYPAD_KEY, KeyRepeat::No) {
+            presenter.show_keypad = !presenter.show_keypad;
+        }
+        let mut title_changed = false;
+        for event in event_receiver.try_iter() {
+            match event {
+                CpuEvent::Paused(state) => {
+                    if let Some(comparison_control_sender) = &comparison_control_sender {
+                        comparison_control_sender.send(ControlCommand::Pause)?;
+                    }
+                    paused_state = Some(state);
+                }
+                CpuEvent::Resumed => paused_state = None,
+                CpuEvent::Rates {
+                    instructions_per_second,
+                    timer_frames_per_second,
+                } => {
+                    if !options.show_rates {
+                        continue;
+                    }
+                    rates = Some((instructions_per_second, timer_frames_per_second));
+                }
+            }
+            title_changed = true;
+        }
+        if let Some(playlist) = playlist.as_mut() {
+            if window.is_key_pressed(NEXT_ROM_KEY, KeyRepeat::No) {
+                current_rom = playlist.load_next(|rom| check_rom(&options, rom))?;
+                send_control(ControlCommand::Reset {
+                    program: current_rom.clone(),
+                    hard: true,
+                })?;
+                rom_name = Some(file_name(playlist.current_path()));
+                title_changed = true;
+            }
         }
-    });
 
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        let change = keyboard::KeysChange {
-            pressed: window.get_keys_pressed(KeyRepeat::No),
+        let mut change = keyboard::KeysChange {
+            pressed: window.get_keys_pressed(key_repeat),
             released: window.get_keys_released(),
         };
+        if options.show_unmapped_keys {
+            for key in change.pressed.iter() {
+                if !keyboard::is_valid_key_code(*key) && !HOTKEYS.contains(key) {
+                    let count = unmapped_keys.map_or(0, |(_, count)| count);
+                    unmapped_keys = Some((*key, count + 1));
+                    title_changed = true;
+                }
+            }
+        }
+        if title_changed {
+            window.set_title(&window_title(
+                rom_name.as_deref(),
+                paused_state.as_ref(),
+                unmapped_keys,
+                rates,
+            ));
+        }
+        if let Some(autofire) = autofire.as_mut() {
+            autofire.apply(
+                window.is_key_down(AUTOFIRE_MODIFIER_KEY),
+                window.is_key_down(autofire.key()),
+                &mut change,
+            );
+        }
         if !change.released.is_empty() || !change.pressed.is_empty() {
             debug!("pressed: {:?}", change.pressed);
             debug!("released: {:?}", change.released);
+            if let Some(comparison) = &comparison {
+                comparison.pressed_keys_sender.send(change.clone())?;
+            }
             pressed_keys_sender.send(change)?;
         }
 
         if let Some(latest) = display_receiver.latest() {
-            update_pixels(&mut frame_buffer, latest)
+            if latest.sequence > presented_sequence {
+                dropped_display_updates += latest.sequence - presented_sequence - 1;
+                presented_sequence = latest.sequence;
+            }
+            match comparison.as_mut() {
+                Some(comparison) => {
+                    // nothing is shown until both cpus have drawn
+                    if let Some(compared) = comparison.display_receiver.latest() {
+                        presenter.render(&[&latest.display, &compared.display]);
+                    }
+                }
+                None => presenter.render(&[&latest.display]),
+            }
         }
+        if presenter.show_keypad {
+            presenter.draw_keypad(&keyboard::held_chip_8_keys(&window.get_keys()));
+        }
+
+        let (window_width, window_height) = window.get_size();
+        // a minimized window has no size to scale to
+        if options.letterbox && window_width > 0 && window_height > 0 {
+            window.update_with_buffer(
+                presenter.letterbox(window_width, window_height),
+                window_width,
+                window_height,
+            )?;
+        } else {
+            window.update_with_buffer(
+                presenter.frame_buffer(),
+                presenter.width(),
+                presenter.height(),
+            )?;
+        }
+    }
 
-        window.update_with_buffer(&frame_buffer, SCREEN_WIDTH, SCREEN_HEIGHT)?;
+    // the cpu threads might already be gone, which is reported when joining them
+    let _ = send_control(ControlCommand::Shutdown);
+    for cpu_thread in cpu_threads {
+        cpu_thread
+            .join()
+            .map_err(|_| anyhow!("Cpu thread panicked"))??;
+    }
+
+    if options.frame_stats {
+        println!(
+            "{} of {} display updates were replaced before they were presented",
+            dropped_display_updates, presented_sequence
+        );
     }
 
     return Ok(());
 }
 
-fn load_rom(file_path: &str) -> Result<Vec<u8>> {
-    if fs::exists(file_path).unwrap_or(false) {
-        return fs::read(file_path).map_err(|e| anyhow!(e));
+/// Display and keyboard channels of the second cpu of `--compare-quirks`
+struct Comparison {
+    display_receiver: DisplayReceiver,
+    pressed_keys_sender: mpsc::Sender<keyboard::KeysChange>,
+    /// kept open, as the cpu stops once nobody listens to its events anymore
+    _event_receiver: mpsc::Receiver<CpuEvent>,
+}
+
+/// Runs the rom on a cpu thread that exits once it was shut down, the watchdog expired or the window closed
+#[allow(clippy::too_many_arguments)]
+fn spawn_cpu(
+    options: Options,
+    rom: Vec<u8>,
+    memory_image: Option<Vec<u8>>,
+    overlays: Vec<(u16, Vec<u8>)>,
+    display_sender: DisplaySender,
+    keyboard_receiver: mpsc::Receiver<keyboard::KeysChange>,
+    mut debugger: Debugger,
+    control_receiver: ControlReceiver,
+    event_sender: CpuEventSender,
+) -> JoinHandle<Result<()>> {
+    return thread::spawn(move || {
+        let mut renderer =
+            Renderer::new(display_sender, options.screen_width, options.screen_height);
+        renderer.set_show_collisions(options.show_collisions);
+        splash::show_splash(&mut renderer, options.splash_duration);
+        let mut cpu = create_cpu(
+            &options,
+            renderer,
+            Keyboard::new(keyboard_receiver),
+            &rom,
+            memory_image.as_deref(),
+            &overlays,
+        )?;
+        let watchdog = Watchdog {
+            deadline: options.exit_after.map(|duration| Instant::now() + duration),
+            remaining_cycles: options.exit_after_cycles,
+        };
+        let result =
+            crash_dump::run_with_crash_dump(&mut cpu, options.crash_dump_path.as_deref(), |cpu| {
+                compatibility::run_with_report(cpu, |cpu| {
+                    run_cpu(
+                        cpu,
+                        &mut debugger,
+                        watchdog,
+                        control_receiver,
+                        event_sender,
+                        options.quiet,
+                    )
+                })
+            });
+        // the traces help to find out what led to an error
+        cpu.write_call_trace()?;
+        cpu.write_coverage()?;
+        cpu.write_profile()?;
+        return result;
+    });
+}
+
+/// Prints the font characters as they are in memory once the rom, the memory image and the overlays were loaded,
+/// 8 characters side by side
+fn show_font(
+    options: &Options,
+    rom: &[u8],
+    memory_image: Option<&[u8]>,
+    overlays: &[(u16, Vec<u8>)],
+) -> Result<()> {
+    let (_, display_sender) = single_value_channel::channel();
+    let (_, keyboard_receiver) = mpsc::channel();
+    let cpu = create_cpu(
+        options,
+        Renderer::new(display_sender, options.screen_width, options.screen_height),
+        Keyboard::new(keyboard_receiver),
+        rom,
+        memory_image,
+        overlays,
+    )?;
+    let characters: Vec<Vec<String>> = cpu
+        .font_table()
+        .chunks(FONT_CHARACTER_SIZE as usize)
+        .map(display::sprite_ascii_rows)
+        .collect();
+    for (line, characters) in characters.chunks(8).enumerate() {
+        let labels: Vec<String> = (0..characters.len())
+            .map(|index| format!("{:<8}", format!("{:X}", line * 8 + index)))
+            .collect();
+        println!("{}", labels.join(" ").trim_end());
+        for row in 0..FONT_CHARACTER_SIZE as usize {
+            let rows: Vec<&str> = characters
+                .iter()
+                .map(|character| character[row].as_str())
+                .collect();
+            println!("{}", rows.join(" "));
+        }
+        println!();
+    }
+    return Ok(());
+}
+
+/// Reports every word of the rom that does not decode to a known instruction.
+/// When quiet, only the result tells whether the rom is valid.
+fn validate_rom(rom: &[u8], quiet: bool) -> Result<()> {
+    let invalid_instructions: Vec<_> = disassembler::disassemble(rom, PROGRAM_START_ADDRESS)
+        .into_iter()
+        .filter(|instruction| instruction.decoded.is_none())
+        .collect();
+    for instruction in invalid_instructions.iter().filter(|_| !quiet) {
+        println!(
+            "{:#05x}: unknown instruction {:04x}",
+            instruction.address, instruction.opcode
+        );
+    }
+    if !invalid_instructions.is_empty() {
+        return Err(anyhow!(
+            "Found {} unknown instructions",
+            invalid_instructions.len()
+        ));
+    }
+    if quiet {
+        return Ok(());
+    }
+    println!("No unknown instructions found");
+    // e.g. `5xy3` runs as `5xy0` here, but other interpreters may reject it
+    for instruction in disassembler::disassemble(rom, PROGRAM_START_ADDRESS) {
+        if let Some(decoded) = instruction.decoded {
+            let canonical = u16::from_be_bytes(decoded.encode());
+            if canonical != instruction.opcode {
+                println!(
+                    "{:#05x}: {:04x} runs as {:04x} ({}), other interpreters may not accept it",
+                    instruction.address, instruction.opcode, canonical, decoded
+                );
+            }
+        }
     }
-    return Err(anyhow!("Rom file '{}' does not exist", file_path));
+    return Ok(());
 }
 
-fn update_pixels(frame_buffer: &mut [u32], display_content: &[[bool; 64]; 32]) {
-    for (i, frame_rgb) in frame_buffer.iter_mut().enumerate() {
-        let x = i % SCREEN_WIDTH;
-        let y = i / SCREEN_WIDTH;
+/// The file name of the path for display, or the whole path if it has none
+fn file_name(path: &Path) -> String {
+    return path.file_name().map_or_else(
+        || path.display().to_string(),
+        |name| name.to_string_lossy().to_string(),
+    );
+}
 
-        let rgb: u32 = if display_content[y][x] {
-            FOREGROUND_COLOR_RGB
+/// Extends the title with the last unmapped key and with the state of a paused cpu
+fn window_title(
+    rom_name: Option<&str>,
+    paused_state: Option<&CpuState>,
+    unmapped_keys: Option<(Key, u64)>,
+    rates: Option<(f64, f64)>,
+) -> String {
+    let mut title = WINDOW_TITLE.to_string();
+    if let Some(rom_name) = rom_name {
+        title += &format!(" - {}", rom_name);
+    }
+    if let Some((instructions_per_second, timer_frames_per_second)) = rates {
+        title += &format!(
+            " [{:.0} instructions/s, {:.1} timer frames/s]",
+            instructions_per_second, timer_frames_per_second
+        );
+    }
+    if let Some((key, count)) = unmapped_keys {
+        title += &format!(" [unmapped key {:?}, {} presses]", key, count);
+    }
+    if let Some(state) = paused_state {
+        title += &format!(" [paused] {}", state);
+    }
+    return title;
+}
+
+/// The memory image replaces the whole memory after the rom was loaded, the overlays are written on top of both
+fn create_cpu(
+    options: &Options,
+    renderer: Renderer,
+    keyboard: Keyboard,
+    rom: &[u8],
+    memory_image: Option<&[u8]>,
+    overlays: &[(u16, Vec<u8>)],
+) -> Result<Cpu> {
+    let mut cpu = Cpu::new(renderer, keyboard, options.cpu_config);
+    let random_source: Box<dyn RandomSource> = match options.seed {
+        Some(seed) => Box::new(SeededRandom::new(seed)),
+        None => Box::new(OsRandom),
+    };
+    cpu.set_random_source(random_source);
+    cpu.audio_mut().set_volume(options.volume);
+    cpu.audio_mut().set_timer_gated(options.timer_gated_audio);
+    if let Some(path) = &options.call_trace_path {
+        cpu.set_call_trace(CallTrace::new(path.clone()));
+    }
+    if let Some(path) = &options.profile_path {
+        cpu.set_profile(Profile::new(path.clone()));
+    }
+    if let Some(path) = &options.coverage_path {
+        cpu.set_coverage(Coverage::new(
+            path.clone(),
+            options.cpu_config.memory_size,
+            rom.len(),
+        ));
+    }
+    if options.log_collisions {
+        cpu.set_collision_callback(Box::new(|x, y, registers| {
+            println!("Sprite collision at ({}, {}) V={:02x?}", x, y, registers);
+        }));
+    }
+    if options.log_instructions || options.explain {
+        let log_instructions = options.log_instructions;
+        let explain = options.explain;
+        cpu.set_instruction_hook(Box::new(move |context| {
+            if log_instructions {
+                println!(
+                    "{:#05x}: {:04x} I={:#05x} V={:02x?}",
+                    context.address, context.opcode, context.i, context.general_registers
+                );
+            }
+            if explain {
+                println!(
+                    "{:#05x}: {:04x} {}",
+                    context.address,
+                    context.opcode,
+                    explain::explain(context)
+                );
+            }
+        }));
+    }
+    if options.strict {
+        cpu.track_uninitialized_reads();
+    }
+    if options.crash_dump_path.is_some() {
+        cpu.record_recent_instructions();
+    }
+    if options.compatibility_report {
+        cpu.enable_compatibility_report();
+    }
+    cpu.load_program_into_memory(rom);
+    if let Some(seed) = options.noise_seed {
+        cpu.fill_unused_memory_with_noise(rom.len(), seed);
+    }
+    if let Some(image) = memory_image {
+        cpu.load_memory_image(image);
+    }
+    for (address, bytes) in overlays.iter() {
+        cpu.load_at(*address, bytes)?;
+    }
+    if let Some(address) = options.entry_address {
+        cpu.set_program_counter(address)?;
+    }
+    return Ok(cpu);
+}
+
+/// Runs the rom for the configured number of cycles without a window or keyboard input
+fn run_headless(
+    options: &Options,
+    rom: &[u8],
+    memory_image: Option<&[u8]>,
+    overlays: &[(u16, Vec<u8>)],
+) -> Result<()> {
+    let (_, display_sender) = single_value_channel::channel();
+    let (_, keyboard_receiver) = mpsc::channel();
+    let mut cpu = create_cpu(
+        options,
+        Renderer::new(display_sender, options.screen_width, options.screen_height),
+        Keyboard::new(keyboard_receiver),
+        rom,
+        memory_image,
+        overlays,
+    )?;
+
+    let frame_display =
+        crash_dump::run_with_crash_dump(&mut cpu, options.crash_dump_path.as_deref(), |cpu| {
+            compatibility::run_with_report(cpu, |cpu| {
+                run_cycles(cpu, options.cycles.unwrap_or(0), options.max_idle_cycles)
+            })
+        });
+    cpu.write_call_trace()?;
+    cpu.write_coverage()?;
+    cpu.write_profile()?;
+    let frame_display = frame_display?;
+
+    if options.dump_state_json {
+        let mut state = cpu.state();
+        state.display = frame_display;
+        println!("{}", serde_json::to_string(&state)?);
+    }
+    return Ok(());
+}
+
+/// Runs the instructions and returns the display at the last frame boundary.
+/// The timers are driven by the instruction count instead of the wall clock, so runs are reproducible.
+/// The run stops early once the cpu waited `max_idle_cycles` cycles in a row for a key press, which never comes without input.
+fn run_cycles(cpu: &mut Cpu, cycles: u64, max_idle_cycles: Option<u64>) -> Result<Vec<String>> {
+    let idle = |cpu: &Cpu| {
+        let idle =
+            max_idle_cycles.is_some_and(|max_idle_cycles| cpu.key_wait_cycles() >= max_idle_cycles);
+        if idle {
+            warn!(
+                "Stopped after {} instructions, the rom waited {} cycles for a key press",
+                cpu.executed_instructions(),
+                cpu.key_wait_cycles()
+            );
+        }
+        return idle;
+    };
+    for _ in 0..cycles / INSTRUCTIONS_PER_FRAME as u64 {
+        if idle(cpu) {
+            return Ok(cpu.display_rows_hex());
+        }
+        cpu.run_frame()?;
+    }
+    // roms draw a frame with several sprites, so the display is sampled at the last frame boundary
+    // to not capture a half drawn screen
+    let frame_display = cpu.display_rows_hex();
+    for _ in 0..cycles % INSTRUCTIONS_PER_FRAME as u64 {
+        if idle(cpu) {
+            break;
+        }
+        cpu.execute_next_instruction()?;
+    }
+    return Ok(frame_display);
+}
+
+/// Runs every `.ch8` rom of the directory headless. A rom that fails to load or panics is reported
+/// and the remaining roms still run, each with a cpu of its own.
+fn run_batch(options: &Options, directory: &str) -> Result<()> {
+    let rom_paths = playlist::find_roms(directory)?;
+
+    let cycles = options.cycles.unwrap_or(DEFAULT_BATCH_CYCLES);
+    let mut failed_count = 0;
+    for rom_path in rom_paths.iter() {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let rom = load_rom(&rom_path.to_string_lossy(), None)?;
+            check_rom(options, &rom)?;
+            let (_, display_sender) = single_value_channel::channel();
+            let (_, keyboard_receiver) = mpsc::channel();
+            let mut cpu = create_cpu(
+                options,
+                Renderer::new(display_sender, options.screen_width, options.screen_height),
+                Keyboard::new(keyboard_receiver),
+                &rom,
+                None,
+                &[],
+            )?;
+            run_cycles(&mut cpu, cycles, options.max_idle_cycles)?;
+            return Ok(());
+        }))
+        .unwrap_or_else(|payload| {
+            Err(anyhow!(
+                "panicked: {}",
+                crash_dump::panic_message(payload.as_ref())
+            ))
+        });
+        match result {
+            Ok(()) => println!("ok      {}", rom_path.display()),
+            Err(e) => {
+                failed_count += 1;
+                println!("FAILED  {}: {}", rom_path.display(), e);
+            }
+        }
+    }
+    if failed_count > 0 {
+        return Err(anyhow!(
+            "{} of {} roms failed",
+            failed_count,
+            rom_paths.len()
+        ));
+    }
+    return Ok(());
+}
+
+/// Budget after which the cpu stops on its own, so a hanging rom can not block automated runs
+struct Watchdog {
+    deadline: Option<Instant>,
+    remaining_cycles: Option<u64>,
+}
+
+impl Watchdog {
+    fn expired(&self) -> bool {
+        return self.remaining_cycles == Some(0)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline);
+    }
+
+    /// Limits the number of cycles that may be run next
+    fn allowed_cycles(&self, max_cycles: usize) -> usize {
+        return match self.remaining_cycles {
+            Some(remaining_cycles) => max_cycles.min(remaining_cycles as usize),
+            None => max_cycles,
+        };
+    }
+
+    fn count_cycles(&mut self, cycles: usize) {
+        if let Some(remaining_cycles) = self.remaining_cycles.as_mut() {
+            *remaining_cycles = remaining_cycles.saturating_sub(cycles as u64);
+        }
+    }
+}
+
+/// Measures how many instructions and timer frames the cpu processes per second
+struct RateMeter {
+    start: Instant,
+    executed_instructions: u64,
+    timer_frames: u64,
+}
+
+impl RateMeter {
+    fn new(cpu: &Cpu) -> Self {
+        return Self {
+            start: Instant::now(),
+            executed_instructions: cpu.executed_instructions(),
+            timer_frames: cpu.timer_frames(),
+        };
+    }
+
+    /// Returns the rates once a second passed since the last measurement
+    fn measure(&mut self, cpu: &Cpu) -> Option<CpuEvent> {
+        let elapsed = self.start.elapsed();
+        if elapsed < RATE_METER_INTERVAL {
+            return None;
+        }
+        let seconds = elapsed.as_secs_f64();
+        let event = CpuEvent::Rates {
+            instructions_per_second: (cpu.executed_instructions() - self.executed_instructions)
+                as f64
+                / seconds,
+            timer_frames_per_second: (cpu.timer_frames() - self.timer_frames) as f64 / seconds,
+        };
+        *self = Self::new(cpu);
+        return Some(event);
+    }
+}
+
+/// Runs the cpu until it is shut down, the control channel is closed or the watchdog expired.
+/// Breakpoints are checked before each instruction. While paused, instructions are only executed on a step command
+/// and the resulting state is sent back for inspection. When quiet, the answers of debugger commands are not printed.
+fn run_cpu(
+    cpu: &mut Cpu,
+    debugger: &mut Debugger,
+    mut watchdog: Watchdog,
+    control_receiver: ControlReceiver,
+    event_sender: CpuEventSender,
+    quiet: bool,
+) -> Result<()> {
+    let mut paused = false;
+    // the instruction a breakpoint stopped at must not stop the cpu again once it continues
+    let mut skip_breakpoint = false;
+    // stack depth at which a step over or step out pauses the cpu again
+    let mut step_target_depth: Option<usize> = None;
+    // display change count at which running until a display change started, and the instructions left to find one
+    let mut display_change_target: Option<(u64, u64)> = None;
+    let mut rate_meter = RateMeter::new(cpu);
+    loop {
+        if watchdog.expired() {
+            return Ok(());
+        }
+        if cpu.display_lost() {
+            warn!("The display updates keep failing, stopping the cpu");
+            return Ok(());
+        }
+        let command = if paused {
+            let received = match watchdog.deadline {
+                Some(deadline) => control_receiver
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                None => control_receiver
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(command) => Some(command),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        } else {
+            match control_receiver.try_recv() {
+                Ok(command) => Some(command),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        };
+
+        let event = match command {
+            Some(ControlCommand::Pause) if !paused => {
+                paused = true;
+                step_target_depth = None;
+                display_change_target = None;
+                cpu.pause_timers();
+                Some(CpuEvent::Paused(cpu.state()))
+            }
+            Some(ControlCommand::TogglePause) => {
+                paused = !paused;
+                skip_breakpoint = true;
+                step_target_depth = None;
+                display_change_target = None;
+                cpu.pause_timers();
+                if paused {
+                    Some(CpuEvent::Paused(cpu.state()))
+                } else {
+                    Some(CpuEvent::Resumed)
+                }
+            }
+            Some(ControlCommand::Step) if paused => {
+                cpu.run_cycle()?;
+                cpu.pause_timers();
+                Some(CpuEvent::Paused(cpu.state()))
+            }
+            Some(ControlCommand::StepFrame) if paused => {
+                cpu.run_frame()?;
+                Some(CpuEvent::Paused(cpu.state()))
+            }
+            Some(ControlCommand::StepOver) if paused => {
+                if let Some(Opcode::Call { .. }) =
+                    cpu.peek_next_opcode().ok().and_then(Opcode::decode)
+                {
+                    // run until the call returned to the current depth
+                    step_target_depth = Some(cpu.call_stack().len());
+                    cpu.run_cycle()?;
+                    paused = false;
+                    Some(CpuEvent::Resumed)
+                } else {
+                    cpu.run_cycle()?;
+                    cpu.pause_timers();
+                    Some(CpuEvent::Paused(cpu.state()))
+                }
+            }
+            Some(ControlCommand::StepOut) if paused => match cpu.call_stack().len() {
+                0 => {
+                    warn!("Not inside of a subroutine");
+                    None
+                }
+                depth => {
+                    step_target_depth = Some(depth - 1);
+                    skip_breakpoint = true;
+                    paused = false;
+                    Some(CpuEvent::Resumed)
+                }
+            },
+            Some(ControlCommand::RunUntilDisplayChange) if paused => {
+                display_change_target =
+                    Some((cpu.display_change_count(), MAX_DISPLAY_CHANGE_CYCLES));
+                skip_breakpoint = true;
+                paused = false;
+                Some(CpuEvent::Resumed)
+            }
+            // steps only apply while paused and pausing again changes nothing
+            Some(ControlCommand::Pause)
+            | Some(ControlCommand::Step)
+            | Some(ControlCommand::StepFrame)
+            | Some(ControlCommand::StepOver)
+            | Some(ControlCommand::StepOut)
+            | Some(ControlCommand::RunUntilDisplayChange)
+            | None => None,
+            Some(ControlCommand::ChangeVolume(change)) => {
+                let audio = cpu.audio_mut();
+                audio.set_volume(audio.volume() + change);
+                None
+            }
+            Some(ControlCommand::ToggleMute) => {
+                cpu.audio_mut().toggle_mute();
+                None
+            }
+            Some(ControlCommand::Reset { program, hard }) => {
+                if hard {
+                    cpu.hard_reset(&program);
+                } else {
+                    cpu.reset(&program);
+                }
+                step_target_depth = None;
+                display_change_target = None;
+                if paused {
+                    Some(CpuEvent::Paused(cpu.state()))
+                } else {
+                    None
+                }
+            }
+            Some(ControlCommand::Debug(command)) => {
+                let output = debugger.execute(cpu, command);
+                if !quiet {
+                    println!("{}", output);
+                }
+                // commands can change the state, so the inspector is refreshed
+                if paused {
+                    Some(CpuEvent::Paused(cpu.state()))
+                } else {
+                    None
+                }
+            }
+            Some(ControlCommand::Shutdown) => return Ok(()),
+        };
+
+        let display_change_finished = match display_change_target {
+            Some((_, 0)) => {
+                warn!(
+                    "The display did not change within {} instructions",
+                    MAX_DISPLAY_CHANGE_CYCLES
+                );
+                true
+            }
+            Some((start_count, _)) => cpu.display_change_count() != start_count,
+            None => false,
+        };
+        let step_finished = display_change_finished
+            || step_target_depth.is_some_and(|depth| cpu.call_stack().len() <= depth);
+        // breakpoints are only checked before a new instruction, a waiting Fx0A would hit its breakpoint again every cycle
+        let event = if !paused
+            && (step_finished
+                || !skip_breakpoint && !cpu.is_waiting_for_key() && debugger.should_break(cpu))
+        {
+            paused = true;
+            step_target_depth = None;
+            display_change_target = None;
+            cpu.pause_timers();
+            Some(CpuEvent::Paused(cpu.state()))
+        } else {
+            event
+        };
+
+        // the time spent paused is not measured
+        let rates = if paused {
+            rate_meter = RateMeter::new(cpu);
+            None
         } else {
-            BACKGROUND_COLOR_RGB
+            rate_meter.measure(cpu)
         };
+        for event in [event, rates].into_iter().flatten() {
+            if event_sender.send(event).is_err() {
+                return Ok(());
+            }
+        }
+
+        if !paused {
+            skip_breakpoint = false;
+            if debugger.has_breakpoints()
+                || step_target_depth.is_some()
+                || display_change_target.is_some()
+            {
+                cpu.run_cycle()?;
+                watchdog.count_cycles(1);
+                if let Some((_, remaining_cycles)) = display_change_target.as_mut() {
+                    *remaining_cycles -= 1;
+                }
+            } else {
+                // without breakpoints to check, the commands only have to be polled often enough to stay responsive
+                let executed = cpu.run_batch(
+                    watchdog.allowed_cycles(MAX_BATCH_CYCLES),
+                    Instant::now() + BATCH_DURATION,
+                )?;
+                watchdog.count_cycles(executed);
+            }
+        }
+    }
+}
+
+/// Runs the rom without a window and streams the display to network clients until the cpu stops
+fn run_server(
+    options: &Options,
+    address: &str,
+    rom: Vec<u8>,
+    memory_image: Option<Vec<u8>>,
+    overlays: Vec<(u16, Vec<u8>)>,
+) -> Result<()> {
+    let (display_receiver, display_sender) = single_value_channel::channel();
+    let (pressed_keys_sender, keyboard_receiver) = mpsc::channel();
+    let (control_sender, control_receiver) = mpsc::channel();
+    // nothing pauses the cpu, but it stops once its events can not be sent anymore
+    let (event_sender, _event_receiver) = mpsc::channel();
+    let cpu_thread = spawn_cpu(
+        options.clone(),
+        rom,
+        memory_image,
+        overlays,
+        display_sender,
+        keyboard_receiver,
+        Debugger::new(),
+        control_receiver,
+        event_sender,
+    );
+
+    let result = server::serve(address, display_receiver, pressed_keys_sender, &cpu_thread);
+    // the cpu thread might already be gone, which is reported when joining it
+    let _ = control_sender.send(ControlCommand::Shutdown);
+    cpu_thread
+        .join()
+        .map_err(|_| anyhow!("Cpu thread panicked"))??;
+    return result;
+}
+
+/// Rejects roms that do not fit into memory and warns about files that are probably no Chip-8 rom,
+/// which are rejected as well in strict mode
+fn check_rom(options: &Options, rom: &[u8]) -> Result<()> {
+    let available_memory = options
+        .cpu_config
+        .memory_size
+        .saturating_sub(PROGRAM_START_ADDRESS as usize);
+    if rom.len() > available_memory {
+        return Err(anyhow!(
+            "The rom has {} bytes, but only {} bytes of memory are available for programs",
+            rom.len(),
+            available_memory
+        ));
+    }
+    if let Some(problem) = find_rom_problem(rom) {
+        if options.strict {
+            return Err(anyhow!("{}, it is probably not a Chip-8 rom", problem));
+        }
+        warn!("{}, it is probably not a Chip-8 rom", problem);
+    }
+    return Ok(());
+}
 
-        *frame_rgb = rgb;
+/// Heuristics for files that are most likely not a Chip-8 rom, returns a description of the problem
+fn find_rom_problem(rom: &[u8]) -> Option<&'static str> {
+    if rom.len() < 2 {
+        return Some("The rom is shorter than a single instruction");
+    }
+    if rom.iter().all(|byte| *byte == 0) {
+        return Some("The rom only contains zeros");
+    }
+    return None;
+}
+
+fn load_memory_image(file_path: &str, memory_size: usize) -> Result<Vec<u8>> {
+    let image = fs::read(file_path)
+        .map_err(|e| anyhow!("Failed to read memory image '{}': {}", file_path, e))?;
+    if image.len() != memory_size {
+        return Err(anyhow!(
+            "Memory image has {} bytes, but the memory has {} bytes",
+            image.len(),
+            memory_size
+        ));
+    }
+    return Ok(image);
+}
+
+/// Reads a rom file, or the rom selected by `zip_entry` from a zip archive
+fn load_rom(file_path: &str, zip_entry: Option<&str>) -> Result<Vec<u8>> {
+    if !fs::exists(file_path).unwrap_or(false) {
+        return Err(anyhow!("Rom file '{}' does not exist", file_path));
+    }
+    if archive::is_zip_archive(file_path) {
+        return archive::load_rom(file_path, zip_entry);
     }
+    return fs::read(file_path).map_err(|e| anyhow!(e));
 }
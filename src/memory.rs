@@ -1,57 +1,233 @@
-const MEMORY_SIZE: usize = 4096;
+use tracing::warn;
+
+use crate::random::RandomSource;
+
+pub const DEFAULT_MEMORY_SIZE: usize = 4096;
+/// addresses are 16 bits wide, so anything beyond 64KB could never be reached
+pub const MAX_MEMORY_SIZE: usize = 0x10000;
+
+pub const PROGRAM_START_ADDRESS: u16 = 0x200;
+pub const FONT_SIZE: usize = 80;
+/// bytes of a single font character
+pub const FONT_CHARACTER_SIZE: u16 = 5;
+pub const DEFAULT_FONT_BASE: u16 = 0x0;
+
+/// Sprites of the hexadecimal digits 0 to F, each 4 pixels wide in the upper nibble
+pub const FONT: [u8; FONT_SIZE] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
 
 pub struct Memory {
-    data: [u8; MEMORY_SIZE],
+    data: Box<[u8]>,
+    /// address of the first font character
+    font_base: u16,
+    /// cells that were written, only tracked when reads of uninitialized memory should be reported
+    written: Option<Vec<bool>>,
 }
 
 impl Memory {
-    pub fn new() -> Self {
+    pub fn new(size: usize, font_base: u16) -> Self {
+        assert!(
+            size >= PROGRAM_START_ADDRESS as usize + FONT_SIZE,
+            "memory size should leave room for the font and a program"
+        );
+        assert!(
+            size <= MAX_MEMORY_SIZE,
+            "memory size should not exceed the 16-bit address space"
+        );
+        assert!(
+            font_base as usize + FONT_SIZE <= PROGRAM_START_ADDRESS as usize,
+            "font should end before the program start"
+        );
         let mut new_memory = Self {
-            data: [0; MEMORY_SIZE],
+            data: vec![0; size].into_boxed_slice(),
+            font_base,
+            written: None,
         };
         new_memory.initialize_sprites();
         return new_memory;
     }
 
     pub fn read_bytes(&self, start: u16, count: u16) -> &[u8] {
-        let start_address = start as usize;
+        let start_address = self.mask_address(start);
         let end_address = start_address + count as usize;
         return self.data[start_address..end_address].as_ref();
     }
 
+    /// Like `read_bytes`, but continues at the start of memory when the end is reached
+    pub fn read_bytes_wrapping(&self, start: u16, count: u16) -> Vec<u8> {
+        let start_address = self.mask_address(start);
+        return (0..count as usize)
+            .map(|offset| self.data[(start_address + offset) % self.data.len()])
+            .collect();
+    }
+
+    /// Whether reading `count` bytes from `start` would cross the end of memory
+    pub fn crosses_end(&self, start: u16, count: u16) -> bool {
+        return self.mask_address(start) + count as usize > self.data.len();
+    }
+
     pub fn write_bytes(&mut self, start: u16, replacement: &[u8]) {
-        let end_address = start as usize + replacement.len();
+        let start_address = self.mask_address(start);
+        let end_address = start_address + replacement.len();
         if end_address <= self.data.len() {
-            self.data[(start as usize)..end_address].copy_from_slice(replacement);
+            self.data[start_address..end_address].copy_from_slice(replacement);
+            self.mark_written(start_address, end_address);
         } else {
             panic!("Replacement would exceed destination slice bounds")
         }
     }
 
+    pub fn read_byte(&self, address: u16) -> u8 {
+        return self.data[self.mask_address(address)];
+    }
+
+    pub fn write_byte(&mut self, address: u16, value: u8) {
+        let address = self.mask_address(address);
+        self.data[address] = value;
+        self.mark_written(address, address + 1);
+    }
+
+    /// Starts to track which cells are written. The font counts as written.
+    pub fn track_writes(&mut self) {
+        let mut written = vec![false; self.data.len()];
+        let font_start = self.font_base as usize;
+        written[font_start..font_start + FONT_SIZE].fill(true);
+        self.written = Some(written);
+    }
+
+    /// Warns about every address in the range that was never written while writes are tracked.
+    /// Each address is only reported once, so code that keeps reading it does not flood the log.
+    pub fn warn_uninitialized_reads(&mut self, start: u16, count: u16, access: &str) {
+        let size = self.data.len();
+        let Some(written) = self.written.as_mut() else {
+            return;
+        };
+        for offset in 0..count {
+            let address = start.wrapping_add(offset) as usize % size;
+            if !written[address] {
+                warn!("{} reads uninitialized memory at {:#05x}", access, address);
+                written[address] = true;
+            }
+        }
+    }
+
+    fn mark_written(&mut self, start: usize, end: usize) {
+        if let Some(written) = self.written.as_mut() {
+            written[start..end].fill(true);
+        }
+    }
+
+    /// Formats `length` bytes starting at `start` as hexadecimal, 16 bytes per line.
+    /// Each marker is shown as `[label]` in front of the byte at its address.
+    pub fn hex_dump(&self, start: u16, length: u16, markers: &[(u16, &str)]) -> String {
+        let mut dump = String::new();
+        for offset in 0..length {
+            let address = start.wrapping_add(offset);
+            if offset.is_multiple_of(16) {
+                if offset > 0 {
+                    dump.push('\n');
+                }
+                dump.push_str(&format!("{:#05x}:", address));
+            }
+            dump.push(' ');
+            for (_, label) in markers
+                .iter()
+                .filter(|(marker_address, _)| *marker_address == address)
+            {
+                dump.push_str(&format!("[{}]", label));
+            }
+            dump.push_str(&format!("{:02x}", self.read_byte(address)));
+        }
+        return dump;
+    }
+
+    /// Returns the start addresses of all occurrences of the pattern
+    pub fn find(&self, pattern: &[u8]) -> Vec<u16> {
+        if pattern.is_empty() {
+            return Vec::new();
+        }
+        return self
+            .data
+            .windows(pattern.len())
+            .enumerate()
+            .filter(|(_, window)| *window == pattern)
+            .map(|(address, _)| address as u16)
+            .collect();
+    }
+
+    pub fn font_base(&self) -> u16 {
+        return self.font_base;
+    }
+
+    /// The 16 font characters, as currently stored in memory
+    pub fn font_table(&self) -> &[u8] {
+        let font_start = self.font_base as usize;
+        return &self.data[font_start..font_start + FONT_SIZE];
+    }
+
+    pub fn size(&self) -> usize {
+        return self.data.len();
+    }
+
+    /// Maps an address into the configured memory size, so addresses beyond the end wrap around
+    fn mask_address(&self, address: u16) -> usize {
+        return address as usize % self.data.len();
+    }
+
     fn initialize_sprites(&mut self) {
-        let sprites: [u8; 80] = [
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-        ];
-
-        self.write_bytes(0x0, &sprites);
+        self.write_bytes(self.font_base, &FONT);
+    }
+
+    /// A copy of the whole memory, which `load_image` accepts
+    pub fn image(&self) -> Vec<u8> {
+        return self.data.to_vec();
+    }
+
+    /// Replaces the whole memory, including the font, with the image
+    pub fn load_image(&mut self, image: &[u8]) {
+        assert_eq!(
+            image.len(),
+            self.data.len(),
+            "memory image should have the size of the memory"
+        );
+        self.data.copy_from_slice(image);
+        let size = self.data.len();
+        self.mark_written(0, size);
+    }
+
+    /// Clears the memory to its initial state with only the font
+    pub fn reset(&mut self) {
+        self.data.fill(0);
+        if self.written.is_some() {
+            self.track_writes();
+        }
+        self.initialize_sprites();
     }
 
     pub fn load_program(&mut self, program: &[u8]) {
-        self.write_bytes(0x200, program);
+        self.write_bytes(PROGRAM_START_ADDRESS, program);
+    }
+
+    /// Fills the memory from `start` to the end with random bytes, which still count as never written
+    pub fn fill_with_noise(&mut self, start: usize, random_source: &mut dyn RandomSource) {
+        for byte in self.data[start..].iter_mut() {
+            *byte = random_source.next_u8();
+        }
     }
 }
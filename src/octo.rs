@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+
+use crate::memory::PROGRAM_START_ADDRESS;
+use crate::opcode::Opcode;
+
+/// A word of the source together with its line number, for error messages
+type Token<'a> = (usize, &'a str);
+
+/// An address operand whose label may only be defined further down in the source
+struct Fixup<'a> {
+    /// offset of the instruction in the rom
+    offset: usize,
+    label: &'a str,
+    line: usize,
+}
+
+/// Assembles a program written in a subset of the Octo assembly language into a rom loaded at `0x200`.
+/// See the readme for the supported statements.
+pub fn assemble(source: &str) -> Result<Vec<u8>> {
+    let tokens = source
+        .lines()
+        .enumerate()
+        .flat_map(|(index, line)| {
+            let code = line.split('#').next().unwrap_or_default();
+            return code.split_whitespace().map(move |word| (index + 1, word));
+        })
+        .collect();
+    let mut assembler = Assembler {
+        tokens,
+        position: 0,
+        rom: Vec::new(),
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        fixups: Vec::new(),
+        loops: Vec::new(),
+    };
+    while assembler.position < assembler.tokens.len() {
+        assembler.statement()?;
+    }
+    return assembler.finish();
+}
+
+struct Assembler<'a> {
+    tokens: Vec<Token<'a>>,
+    /// index of the next token
+    position: usize,
+    rom: Vec<u8>,
+    labels: HashMap<&'a str, u16>,
+    /// values defined with `:const`
+    constants: HashMap<&'a str, u16>,
+    /// register names defined with `:alias`
+    aliases: HashMap<&'a str, u8>,
+    fixups: Vec<Fixup<'a>>,
+    /// start addresses of the open `loop`s, the innermost last
+    loops: Vec<u16>,
+}
+
+impl<'a> Assembler<'a> {
+    fn next(&mut self) -> Result<Token<'a>> {
+        let token = self.tokens.get(self.position).copied().ok_or_else(|| {
+            let line = self.tokens.last().map_or(1, |(line, _)| *line);
+            anyhow!("Line {}: unexpected end of the source", line)
+        })?;
+        self.position += 1;
+        return Ok(token);
+    }
+
+    /// Consumes the next token, which has to be the keyword
+    fn expect(&mut self, keyword: &str) -> Result<()> {
+        let (line, word) = self.next()?;
+        if word != keyword {
+            return Err(anyhow!(
+                "Line {}: expected '{}' but found '{}'",
+                line,
+                keyword,
+                word
+            ));
+        }
+        return Ok(());
+    }
+
+    fn address(&self) -> u16 {
+        return PROGRAM_START_ADDRESS + self.rom.len() as u16;
+    }
+
+    fn emit(&mut self, opcode: Opcode) {
+        self.rom.extend(opcode.encode());
+    }
+
+    /// Emits an instruction whose `nnn` operand is the value of the next token, resolved once all labels are known
+    fn emit_with_address(&mut self, opcode: Opcode) -> Result<()> {
+        let (line, word) = self.next()?;
+        match self.value(word) {
+            Some(value) if value <= 0xFFF => self.emit(with_address(opcode, value)),
+            Some(value) => {
+                return Err(anyhow!(
+                    "Line {}: address {:#x} does not fit into 12 bits",
+                    line,
+                    value
+                ))
+            }
+            None => {
+                self.fixups.push(Fixup {
+                    offset: self.rom.len(),
+                    label: word,
+                    line,
+                });
+                self.emit(opcode);
+            }
+        }
+        return Ok(());
+    }
+
+    /// A number literal or a constant
+    fn value(&self, word: &str) -> Option<u16> {
+        if let Some(value) = self.constants.get(word) {
+            return Some(*value);
+        }
+        let parsed = if let Some(hex) = word.strip_prefix("0x") {
+            u16::from_str_radix(hex, 16)
+        } else if let Some(binary) = word.strip_prefix("0b") {
+            u16::from_str_radix(binary, 2)
+        } else {
+            word.parse()
+        };
+        return parsed.ok();
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let (line, word) = self.next()?;
+        return self
+            .value(word)
+            .and_then(|value| u8::try_from(value).ok())
+            .ok_or_else(|| anyhow!("Line {}: '{}' is no byte value", line, word));
+    }
+
+    fn register_index(&self, word: &str) -> Option<u8> {
+        if let Some(register) = self.aliases.get(word) {
+            return Some(*register);
+        }
+        let digit = word.strip_prefix(['v', 'V'])?;
+        if digit.len() != 1 {
+            return None;
+        }
+        return u8::from_str_radix(digit, 16).ok();
+    }
+
+    fn register(&mut self) -> Result<u8> {
+        let (line, word) = self.next()?;
+        return self
+            .register_index(word)
+            .ok_or_else(|| anyhow!("Line {}: '{}' is no register", line, word));
+    }
+
+    fn statement(&mut self) -> Result<()> {
+        let (line, word) = self.next()?;
+        match word {
+            ":" => {
+                let (_, label) = self.next()?;
+                if self.labels.insert(label, self.address()).is_some() {
+                    return Err(anyhow!("Line {}: label '{}' is defined twice", line, label));
+                }
+            }
+            ":const" => {
+                let (_, name) = self.next()?;
+                let (value_line, value) = self.next()?;
+                let value = self
+                    .value(value)
+                    .ok_or_else(|| anyhow!("Line {}: '{}' is no number", value_line, value))?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let (_, name) = self.next()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            "clear" => self.emit(Opcode::ClearDisplay),
+            "return" | ";" => self.emit(Opcode::Return),
+            "jump" => self.emit_with_address(Opcode::Jump { nnn: 0 })?,
+            "jump0" => self.emit_with_address(Opcode::JumpV0 { nnn: 0 })?,
+            "loop" => self.loops.push(self.address()),
+            "again" => {
+                let start = self
+                    .loops
+                    .pop()
+                    .ok_or_else(|| anyhow!("Line {}: 'again' without 'loop'", line))?;
+                self.emit(Opcode::Jump { nnn: start });
+            }
+            "if" => {
+                self.condition()?;
+                self.expect("then")?;
+                // the skip applies to the next instruction, a statement without one would skip the wrong thing
+                let (statement_line, statement) = self.next()?;
+                if matches!(statement, "if" | "loop" | ":" | ":const" | ":alias")
+                    || self.value(statement).is_some()
+                {
+                    return Err(anyhow!(
+                        "Line {}: 'then' has to be followed by a single instruction",
+                        statement_line
+                    ));
+                }
+                self.position -= 1;
+                self.statement()?;
+            }
+            "i" => self.i_statement()?,
+            "delay" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.emit(Opcode::SetDelayTimer { x });
+            }
+            "buzzer" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.emit(Opcode::SetSoundTimer { x });
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(Opcode::StoreBcd { x });
+            }
+            "save" => {
+                let x = self.register()?;
+                self.emit(Opcode::StoreRegisters { x });
+            }
+            "load" => {
+                let x = self.register()?;
+                self.emit(Opcode::LoadRegisters { x });
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let (n_line, n) = self.next()?;
+                let n = self
+                    .value(n)
+                    .filter(|n| *n <= 0xF)
+                    .ok_or_else(|| anyhow!("Line {}: '{}' is no sprite height", n_line, n))?;
+                self.emit(Opcode::DrawSprite { x, y, n: n as u8 });
+            }
+            _ => {
+                if let Some(x) = self.register_index(word) {
+                    self.register_statement(x)?;
+                } else if let Some(value) = self.value(word) {
+                    let byte = u8::try_from(value)
+                        .map_err(|_| anyhow!("Line {}: '{}' is no byte value", line, word))?;
+                    self.rom.push(byte);
+                } else {
+                    // a bare label calls the subroutine
+                    self.position -= 1;
+                    self.emit_with_address(Opcode::Call { nnn: 0 })?;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// `i := <address>`, `i := hex vX` and `i += vX`
+    fn i_statement(&mut self) -> Result<()> {
+        let (line, operator) = self.next()?;
+        match operator {
+            ":=" => {
+                if self.tokens.get(self.position).map(|(_, word)| *word) == Some("hex") {
+                    self.position += 1;
+                    let x = self.register()?;
+                    self.emit(Opcode::SetIToSprite { x });
+                } else {
+                    self.emit_with_address(Opcode::SetI { nnn: 0 })?;
+                }
+            }
+            "+=" => {
+                let x = self.register()?;
+                self.emit(Opcode::AddI { x });
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Line {}: unsupported operator 'i {}'",
+                    line,
+                    operator
+                ))
+            }
+        }
+        return Ok(());
+    }
+
+    /// `vX <operator> <operand>`
+    fn register_statement(&mut self, x: u8) -> Result<()> {
+        let (line, operator) = self.next()?;
+        let (operand_line, operand) = self.next()?;
+        let y = self.register_index(operand);
+        let opcode = match (operator, y) {
+            (":=", Some(y)) => Opcode::CopyRegister { x, y },
+            (":=", None) => match operand {
+                "random" => Opcode::Random {
+                    x,
+                    kk: self.byte()?,
+                },
+                "delay" => Opcode::LoadDelayTimer { x },
+                "key" => Opcode::WaitForKey { x },
+                _ => Opcode::SetRegister {
+                    x,
+                    kk: self.operand_byte(operand_line, operand)?,
+                },
+            },
+            ("+=", Some(y)) => Opcode::Add { x, y },
+            ("+=", None) => Opcode::AddKk {
+                x,
+                kk: self.operand_byte(operand_line, operand)?,
+            },
+            ("-=", Some(y)) => Opcode::Sub { x, y },
+            ("=-", Some(y)) => Opcode::Subn { x, y },
+            ("|=", Some(y)) => Opcode::Or { x, y },
+            ("&=", Some(y)) => Opcode::And { x, y },
+            ("^=", Some(y)) => Opcode::Xor { x, y },
+            (">>=", Some(y)) => Opcode::ShiftRight { x, y },
+            ("<<=", Some(y)) => Opcode::ShiftLeft { x, y },
+            _ => {
+                return Err(anyhow!(
+                    "Line {}: unsupported statement 'v{:X} {} {}'",
+                    line,
+                    x,
+                    operator,
+                    operand
+                ))
+            }
+        };
+        self.emit(opcode);
+        return Ok(());
+    }
+
+    fn operand_byte(&self, line: usize, operand: &str) -> Result<u8> {
+        return self
+            .value(operand)
+            .and_then(|value| u8::try_from(value).ok())
+            .ok_or_else(|| anyhow!("Line {}: '{}' is no byte value", line, operand));
+    }
+
+    /// Emits the skip of `if <condition> then`, which skips the next instruction when the condition does not hold
+    fn condition(&mut self) -> Result<()> {
+        let x = self.register()?;
+        let (line, operator) = self.next()?;
+        let opcode = match operator {
+            "key" => Opcode::SkipIfKeyNotPressed { x },
+            "-key" => Opcode::SkipIfKeyPressed { x },
+            "==" | "!=" => {
+                let (operand_line, operand) = self.next()?;
+                let equal = operator == "==";
+                match self.register_index(operand) {
+                    Some(y) if equal => Opcode::SkipIfNotEqualRegister { x, y },
+                    Some(y) => Opcode::SkipIfEqualRegister { x, y },
+                    None => {
+                        let kk = self.operand_byte(operand_line, operand)?;
+                        if equal {
+                            Opcode::SkipIfNotEqualKk { x, kk }
+                        } else {
+                            Opcode::SkipIfEqualKk { x, kk }
+                        }
+                    }
+                }
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Line {}: unsupported condition operator '{}'",
+                    line,
+                    operator
+                ))
+            }
+        };
+        self.emit(opcode);
+        return Ok(());
+    }
+
+    /// Resolves the labels used before their definition
+    fn finish(mut self) -> Result<Vec<u8>> {
+        if !self.loops.is_empty() {
+            return Err(anyhow!("'loop' without 'again'"));
+        }
+        for fixup in self.fixups.iter() {
+            let address = *self
+                .labels
+                .get(fixup.label)
+                .ok_or_else(|| anyhow!("Line {}: unknown label '{}'", fixup.line, fixup.label))?;
+            if address > 0xFFF {
+                return Err(anyhow!(
+                    "Line {}: label '{}' lies beyond 0xfff",
+                    fixup.line,
+                    fixup.label
+                ));
+            }
+            let word = u16::from_be_bytes([self.rom[fixup.offset], self.rom[fixup.offset + 1]]);
+            self.rom[fixup.offset..fixup.offset + 2]
+                .copy_from_slice(&(word | address).to_be_bytes());
+        }
+        return Ok(self.rom);
+    }
+}
+
+/// The instruction with its `nnn` operand replaced
+fn with_address(opcode: Opcode, nnn: u16) -> Opcode {
+    return match opcode {
+        Opcode::Jump { .. } => Opcode::Jump { nnn },
+        Opcode::JumpV0 { .. } => Opcode::JumpV0 { nnn },
+        Opcode::Call { .. } => Opcode::Call { nnn },
+        Opcode::SetI { .. } => Opcode::SetI { nnn },
+        _ => opcode,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::cpu_with_program;
+
+    /// Counts to 3 in a loop, then draws a 2 row sprite at the top left corner and stops
+    const COUNT_AND_DRAW: &str = "
+        :const COUNT 3
+        :alias counter v1
+        : main
+            v0 := 0
+            counter := 0
+            loop
+                counter += 1
+                if counter == COUNT then jump done
+            again
+        : done
+            i := smile
+            sprite v0 v0 2
+        : halt
+            jump halt
+        : smile
+            0b01100110 # eyes
+            0b10000001
+    ";
+
+    #[test]
+    fn assembled_snippet_runs() {
+        let program = assemble(COUNT_AND_DRAW).unwrap();
+        assert_eq!(program[..4], [0x60, 0x00, 0x61, 0x00]);
+
+        let mut cpu = cpu_with_program(&program);
+        for _ in 0..30 {
+            cpu.execute_next_instruction().unwrap();
+        }
+        assert_eq!(cpu.register(1), 3);
+        let rows = cpu.display_rows_hex();
+        assert_eq!(rows[0], "6600000000000000");
+        assert_eq!(rows[1], "8100000000000000");
+        assert!(rows[2..].iter().all(|row| row == "0000000000000000"));
+    }
+}
@@ -0,0 +1,357 @@
+use core::fmt;
+
+/// A decoded Chip-8 instruction with its operands.
+/// Register operands `x` and `y` are register indices, `n` and `kk` are 4 and 8 bit constants and `nnn` is an address.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Opcode {
+    /// 0000, ignored by the interpreter
+    Nop,
+    /// 00E0
+    ClearDisplay,
+    /// 00EE
+    Return,
+    /// 1nnn
+    Jump { nnn: u16 },
+    /// 2nnn
+    Call { nnn: u16 },
+    /// 3xkk
+    SkipIfEqualKk { x: u8, kk: u8 },
+    /// 4xkk
+    SkipIfNotEqualKk { x: u8, kk: u8 },
+    /// 5xy0
+    SkipIfEqualRegister { x: u8, y: u8 },
+    /// 6xkk
+    SetRegister { x: u8, kk: u8 },
+    /// 7xkk
+    AddKk { x: u8, kk: u8 },
+    /// 8xy0
+    CopyRegister { x: u8, y: u8 },
+    /// 8xy1
+    Or { x: u8, y: u8 },
+    /// 8xy2
+    And { x: u8, y: u8 },
+    /// 8xy3
+    Xor { x: u8, y: u8 },
+    /// 8xy4
+    Add { x: u8, y: u8 },
+    /// 8xy5
+    Sub { x: u8, y: u8 },
+    /// 8xy6
+    ShiftRight { x: u8, y: u8 },
+    /// 8xy7
+    Subn { x: u8, y: u8 },
+    /// 8xyE
+    ShiftLeft { x: u8, y: u8 },
+    /// 9xy0
+    SkipIfNotEqualRegister { x: u8, y: u8 },
+    /// Annn
+    SetI { nnn: u16 },
+    /// Bnnn
+    JumpV0 { nnn: u16 },
+    /// Cxkk
+    Random { x: u8, kk: u8 },
+    /// Dxyn
+    DrawSprite { x: u8, y: u8, n: u8 },
+    /// Ex9E
+    SkipIfKeyPressed { x: u8 },
+    /// ExA1
+    SkipIfKeyNotPressed { x: u8 },
+    /// Fx07
+    LoadDelayTimer { x: u8 },
+    /// Fx0A
+    WaitForKey { x: u8 },
+    /// Fx15
+    SetDelayTimer { x: u8 },
+    /// Fx18
+    SetSoundTimer { x: u8 },
+    /// Fx1E
+    AddI { x: u8 },
+    /// Fx29
+    SetIToSprite { x: u8 },
+    /// Fx33
+    StoreBcd { x: u8 },
+    /// Fx55
+    StoreRegisters { x: u8 },
+    /// Fx65
+    LoadRegisters { x: u8 },
+    /// 02A0, CHIP-8X only
+    CycleBackgroundColor,
+    /// 5xy1, CHIP-8X only
+    AddNibbles { x: u8, y: u8 },
+    /// Bxy0, CHIP-8X only
+    SetForegroundColor { x: u8, y: u8 },
+}
+
+impl Opcode {
+    /// Decodes the big endian 16-bit instruction, returns `None` for unknown instructions
+    pub fn decode(opcode: u16) -> Option<Opcode> {
+        let nibbles = (
+            (opcode >> 12) as u8 & 0xF,
+            (opcode >> 8) as u8 & 0xF,
+            (opcode >> 4) as u8 & 0xF,
+            opcode as u8 & 0xF,
+        );
+        let x = nibbles.1;
+        let y = nibbles.2;
+        let n = nibbles.3;
+        let kk = opcode as u8;
+        let nnn = opcode & 0x0FFF;
+
+        let decoded = match nibbles {
+            (0x0, 0x0, 0x0, 0x0) => Opcode::Nop,
+            (0x0, 0x0, 0xE, 0x0) => Opcode::ClearDisplay,
+            (0x0, 0x0, 0xE, 0xE) => Opcode::Return,
+
+            (0x1, _, _, _) => Opcode::Jump { nnn },
+
+            (0x2, _, _, _) => Opcode::Call { nnn },
+
+            (0x3, _, _, _) => Opcode::SkipIfEqualKk { x, kk },
+
+            (0x4, _, _, _) => Opcode::SkipIfNotEqualKk { x, kk },
+
+            (0x5, _, _, _) => Opcode::SkipIfEqualRegister { x, y },
+
+            (0x6, _, _, _) => Opcode::SetRegister { x, kk },
+
+            (0x7, _, _, _) => Opcode::AddKk { x, kk },
+
+            (0x8, _, _, 0x0) => Opcode::CopyRegister { x, y },
+            (0x8, _, _, 0x1) => Opcode::Or { x, y },
+            (0x8, _, _, 0x2) => Opcode::And { x, y },
+            (0x8, _, _, 0x3) => Opcode::Xor { x, y },
+            (0x8, _, _, 0x4) => Opcode::Add { x, y },
+            (0x8, _, _, 0x5) => Opcode::Sub { x, y },
+            (0x8, _, _, 0x6) => Opcode::ShiftRight { x, y },
+            (0x8, _, _, 0x7) => Opcode::Subn { x, y },
+            (0x8, _, _, 0xE) => Opcode::ShiftLeft { x, y },
+
+            (0x9, _, _, _) => Opcode::SkipIfNotEqualRegister { x, y },
+
+            (0xA, _, _, _) => Opcode::SetI { nnn },
+
+            (0xB, _, _, _) => Opcode::JumpV0 { nnn },
+
+            (0xC, _, _, _) => Opcode::Random { x, kk },
+
+            (0xD, _, _, _) => Opcode::DrawSprite { x, y, n },
+
+            (0xE, _, 0x9, 0xE) => Opcode::SkipIfKeyPressed { x },
+            (0xE, _, 0xA, 0x1) => Opcode::SkipIfKeyNotPressed { x },
+
+            (0xF, _, 0x0, 0x7) => Opcode::LoadDelayTimer { x },
+            (0xF, _, 0x0, 0xA) => Opcode::WaitForKey { x },
+            (0xF, _, 0x1, 0x5) => Opcode::SetDelayTimer { x },
+            (0xF, _, 0x1, 0x8) => Opcode::SetSoundTimer { x },
+            (0xF, _, 0x1, 0xE) => Opcode::AddI { x },
+
+            (0xF, _, 0x2, _) => Opcode::SetIToSprite { x },
+            (0xF, _, 0x3, _) => Opcode::StoreBcd { x },
+            (0xF, _, 0x5, 0x5) => Opcode::StoreRegisters { x },
+            (0xF, _, 0x6, 0x5) => Opcode::LoadRegisters { x },
+            _ => return None,
+        };
+        return Some(decoded);
+    }
+
+    /// Decodes the instruction like the CHIP-8X interpreter, which adds color instructions and has no `Bnnn` jump
+    pub fn decode_chip8x(opcode: u16) -> Option<Opcode> {
+        let x = (opcode >> 8) as u8 & 0xF;
+        let y = (opcode >> 4) as u8 & 0xF;
+        return match (opcode >> 12, opcode & 0xF) {
+            _ if opcode == 0x02A0 => Some(Opcode::CycleBackgroundColor),
+            (0x5, 0x1) => Some(Opcode::AddNibbles { x, y }),
+            (0xB, 0x0) => Some(Opcode::SetForegroundColor { x, y }),
+            // BxyN colors single pixel rows, which is not supported
+            (0xB, _) => None,
+            _ => Opcode::decode(opcode),
+        };
+    }
+
+    /// Encodes the instruction as big endian bytes, the inverse of `decode`.
+    /// The CHIP-8X instructions are the inverse of `decode_chip8x` instead, `decode` reads 5xy1 as `5xy0` and `Bxy0` as `Bnnn`.
+    /// Don't care nibbles like the `n` of `5xy0` are encoded as 0, like the opcode table shows them.
+    pub fn encode(&self) -> [u8; 2] {
+        let xy = |high: u16, x: u8, y: u8, low: u16| -> u16 {
+            return high << 12 | (x as u16) << 8 | (y as u16) << 4 | low;
+        };
+        let xkk = |high: u16, x: u8, kk: u8| -> u16 {
+            return high << 12 | (x as u16) << 8 | kk as u16;
+        };
+        let word = match *self {
+            Opcode::Nop => 0x0000,
+            Opcode::ClearDisplay => 0x00E0,
+            Opcode::Return => 0x00EE,
+            Opcode::Jump { nnn } => 0x1000 | nnn & 0x0FFF,
+            Opcode::Call { nnn } => 0x2000 | nnn & 0x0FFF,
+            Opcode::SkipIfEqualKk { x, kk } => xkk(0x3, x, kk),
+            Opcode::SkipIfNotEqualKk { x, kk } => xkk(0x4, x, kk),
+            Opcode::SkipIfEqualRegister { x, y } => xy(0x5, x, y, 0x0),
+            Opcode::SetRegister { x, kk } => xkk(0x6, x, kk),
+            Opcode::AddKk { x, kk } => xkk(0x7, x, kk),
+            Opcode::CopyRegister { x, y } => xy(0x8, x, y, 0x0),
+            Opcode::Or { x, y } => xy(0x8, x, y, 0x1),
+            Opcode::And { x, y } => xy(0x8, x, y, 0x2),
+            Opcode::Xor { x, y } => xy(0x8, x, y, 0x3),
+            Opcode::Add { x, y } => xy(0x8, x, y, 0x4),
+            Opcode::Sub { x, y } => xy(0x8, x, y, 0x5),
+            Opcode::ShiftRight { x, y } => xy(0x8, x, y, 0x6),
+            Opcode::Subn { x, y } => xy(0x8, x, y, 0x7),
+            Opcode::ShiftLeft { x, y } => xy(0x8, x, y, 0xE),
+            Opcode::SkipIfNotEqualRegister { x, y } => xy(0x9, x, y, 0x0),
+            Opcode::SetI { nnn } => 0xA000 | nnn & 0x0FFF,
+            Opcode::JumpV0 { nnn } => 0xB000 | nnn & 0x0FFF,
+            Opcode::Random { x, kk } => xkk(0xC, x, kk),
+            Opcode::DrawSprite { x, y, n } => xy(0xD, x, y, n as u16),
+            Opcode::SkipIfKeyPressed { x } => xkk(0xE, x, 0x9E),
+            Opcode::SkipIfKeyNotPressed { x } => xkk(0xE, x, 0xA1),
+            Opcode::LoadDelayTimer { x } => xkk(0xF, x, 0x07),
+            Opcode::WaitForKey { x } => xkk(0xF, x, 0x0A),
+            Opcode::SetDelayTimer { x } => xkk(0xF, x, 0x15),
+            Opcode::SetSoundTimer { x } => xkk(0xF, x, 0x18),
+            Opcode::AddI { x } => xkk(0xF, x, 0x1E),
+            Opcode::SetIToSprite { x } => xkk(0xF, x, 0x29),
+            Opcode::StoreBcd { x } => xkk(0xF, x, 0x33),
+            Opcode::StoreRegisters { x } => xkk(0xF, x, 0x55),
+            Opcode::LoadRegisters { x } => xkk(0xF, x, 0x65),
+            Opcode::CycleBackgroundColor => 0x02A0,
+            Opcode::AddNibbles { x, y } => xy(0x5, x, y, 0x1),
+            Opcode::SetForegroundColor { x, y } => xy(0xB, x, y, 0x0),
+        };
+        return word.to_be_bytes();
+    }
+
+    /// The instruction pattern from the opcode table like `Dxyn`, shared by all operands
+    pub fn pattern(&self) -> &'static str {
+        return match *self {
+            Opcode::Nop => "0000",
+            Opcode::ClearDisplay => "00E0",
+            Opcode::Return => "00EE",
+            Opcode::Jump { .. } => "1nnn",
+            Opcode::Call { .. } => "2nnn",
+            Opcode::SkipIfEqualKk { .. } => "3xkk",
+            Opcode::SkipIfNotEqualKk { .. } => "4xkk",
+            Opcode::SkipIfEqualRegister { .. } => "5xy0",
+            Opcode::SetRegister { .. } => "6xkk",
+            Opcode::AddKk { .. } => "7xkk",
+            Opcode::CopyRegister { .. } => "8xy0",
+            Opcode::Or { .. } => "8xy1",
+            Opcode::And { .. } => "8xy2",
+            Opcode::Xor { .. } => "8xy3",
+            Opcode::Add { .. } => "8xy4",
+            Opcode::Sub { .. } => "8xy5",
+            Opcode::ShiftRight { .. } => "8xy6",
+            Opcode::Subn { .. } => "8xy7",
+            Opcode::ShiftLeft { .. } => "8xyE",
+            Opcode::SkipIfNotEqualRegister { .. } => "9xy0",
+            Opcode::SetI { .. } => "Annn",
+            Opcode::JumpV0 { .. } => "Bnnn",
+            Opcode::Random { .. } => "Cxkk",
+            Opcode::DrawSprite { .. } => "Dxyn",
+            Opcode::SkipIfKeyPressed { .. } => "Ex9E",
+            Opcode::SkipIfKeyNotPressed { .. } => "ExA1",
+            Opcode::LoadDelayTimer { .. } => "Fx07",
+            Opcode::WaitForKey { .. } => "Fx0A",
+            Opcode::SetDelayTimer { .. } => "Fx15",
+            Opcode::SetSoundTimer { .. } => "Fx18",
+            Opcode::AddI { .. } => "Fx1E",
+            Opcode::SetIToSprite { .. } => "Fx29",
+            Opcode::StoreBcd { .. } => "Fx33",
+            Opcode::StoreRegisters { .. } => "Fx55",
+            Opcode::LoadRegisters { .. } => "Fx65",
+            Opcode::CycleBackgroundColor => "02A0",
+            Opcode::AddNibbles { .. } => "5xy1",
+            Opcode::SetForegroundColor { .. } => "Bxy0",
+        };
+    }
+
+    /// The assembly mnemonic like `DRW`
+    pub fn mnemonic(&self) -> String {
+        return self
+            .to_string()
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string();
+    }
+}
+
+/// Formats the opcode as assembly in the notation of Cowgod's technical reference
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Opcode::Nop => write!(f, "NOP"),
+            Opcode::ClearDisplay => write!(f, "CLS"),
+            Opcode::Return => write!(f, "RET"),
+            Opcode::Jump { nnn } => write!(f, "JP {:#05x}", nnn),
+            Opcode::Call { nnn } => write!(f, "CALL {:#05x}", nnn),
+            Opcode::SkipIfEqualKk { x, kk } => write!(f, "SE V{:X}, {:#04x}", x, kk),
+            Opcode::SkipIfNotEqualKk { x, kk } => write!(f, "SNE V{:X}, {:#04x}", x, kk),
+            Opcode::SkipIfEqualRegister { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Opcode::SetRegister { x, kk } => write!(f, "LD V{:X}, {:#04x}", x, kk),
+            Opcode::AddKk { x, kk } => write!(f, "ADD V{:X}, {:#04x}", x, kk),
+            Opcode::CopyRegister { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Opcode::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Opcode::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Opcode::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Opcode::Add { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Opcode::Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Opcode::ShiftRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Opcode::Subn { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Opcode::ShiftLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Opcode::SkipIfNotEqualRegister { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Opcode::SetI { nnn } => write!(f, "LD I, {:#05x}", nnn),
+            Opcode::JumpV0 { nnn } => write!(f, "JP V0, {:#05x}", nnn),
+            Opcode::Random { x, kk } => write!(f, "RND V{:X}, {:#04x}", x, kk),
+            Opcode::DrawSprite { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Opcode::SkipIfKeyPressed { x } => write!(f, "SKP V{:X}", x),
+            Opcode::SkipIfKeyNotPressed { x } => write!(f, "SKNP V{:X}", x),
+            Opcode::LoadDelayTimer { x } => write!(f, "LD V{:X}, DT", x),
+            Opcode::WaitForKey { x } => write!(f, "LD V{:X}, K", x),
+            Opcode::SetDelayTimer { x } => write!(f, "LD DT, V{:X}", x),
+            Opcode::SetSoundTimer { x } => write!(f, "LD ST, V{:X}", x),
+            Opcode::AddI { x } => write!(f, "ADD I, V{:X}", x),
+            Opcode::SetIToSprite { x } => write!(f, "LD F, V{:X}", x),
+            Opcode::StoreBcd { x } => write!(f, "LD B, V{:X}", x),
+            Opcode::StoreRegisters { x } => write!(f, "LD [I], V{:X}", x),
+            Opcode::LoadRegisters { x } => write!(f, "LD V{:X}, [I]", x),
+            Opcode::CycleBackgroundColor => write!(f, "BGC"),
+            Opcode::AddNibbles { x, y } => write!(f, "ADDN V{:X}, V{:X}", x, y),
+            Opcode::SetForegroundColor { x, y } => write!(f, "COL V{:X}, V{:X}", x, y),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_is_the_inverse_of_decode() {
+        for word in 0..=u16::MAX {
+            for decode in [Opcode::decode, Opcode::decode_chip8x] {
+                if let Some(opcode) = decode(word) {
+                    let encoded = u16::from_be_bytes(opcode.encode());
+                    assert_eq!(
+                        decode(encoded),
+                        Some(opcode),
+                        "{:04x} encoded as {:04x}",
+                        word,
+                        encoded
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rom_assembles_the_opcodes_in_order() {
+        let rom = rom![
+            SetRegister { x: 0, kk: 5 },
+            AddKk { x: 0, kk: 3 },
+            DrawSprite { x: 1, y: 2, n: 5 },
+            Jump { nnn: 0x200 },
+        ];
+        assert_eq!(rom, [0x60, 0x05, 0x70, 0x03, 0xD1, 0x25, 0x12, 0x00]);
+    }
+}
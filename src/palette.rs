@@ -0,0 +1,60 @@
+/// Background colors of the CHIP-8X color board in the order `02A0` cycles through them: blue, black, green, red
+#[allow(clippy::eq_op, clippy::identity_op)]
+pub const CHIP_8X_BACKGROUND_COLORS: [u32; 4] = [
+    0x00 << 16 | 0x00 << 8 | 0x80,
+    0x00 << 16 | 0x00 << 8 | 0x00,
+    0x00 << 16 | 0x80 << 8 | 0x00,
+    0x80 << 16 | 0x00 << 8 | 0x00,
+];
+
+/// Foreground colors of the CHIP-8X color board by color code:
+/// black, red, blue, violet, green, yellow, aqua, white
+#[allow(clippy::eq_op, clippy::identity_op)]
+pub const CHIP_8X_FOREGROUND_COLORS: [u32; 8] = [
+    0x00 << 16 | 0x00 << 8 | 0x00,
+    0xFF << 16 | 0x00 << 8 | 0x00,
+    0x00 << 16 | 0x00 << 8 | 0xFF,
+    0xFF << 16 | 0x00 << 8 | 0xFF,
+    0x00 << 16 | 0xFF << 8 | 0x00,
+    0xFF << 16 | 0xFF << 8 | 0x00,
+    0x00 << 16 | 0xFF << 8 | 0xFF,
+    0xFF << 16 | 0xFF << 8 | 0xFF,
+];
+
+/// Colors for each combination of the two XO-CHIP display planes.
+/// The classic single plane display only uses `color0` as background and `color1` as foreground.
+#[derive(Clone, Copy)]
+pub struct Palette {
+    /// no plane set
+    pub color0: u32,
+    /// only the first plane set
+    pub color1: u32,
+    /// only the second plane set
+    pub color2: u32,
+    /// both planes set
+    pub color3: u32,
+}
+
+impl Default for Palette {
+    #[allow(clippy::eq_op, clippy::identity_op)]
+    fn default() -> Self {
+        return Self {
+            color0: 0x00 << 16 | 0x00 << 8 | 0x00,
+            color1: 0x00 << 16 | 0x99 << 8 | 0x00,
+            color2: 0xFF << 16 | 0xCC << 8 | 0x00,
+            color3: 0xFF << 16 | 0x66 << 8 | 0x00,
+        };
+    }
+}
+
+impl Palette {
+    /// Color of a pixel, where bit 0 tells whether the first plane is set and bit 1 whether the second plane is set
+    pub fn color(&self, planes: u8) -> u32 {
+        match planes & 0b11 {
+            0 => return self.color0,
+            1 => return self.color1,
+            2 => return self.color2,
+            _ => return self.color3,
+        }
+    }
+}
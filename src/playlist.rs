@@ -0,0 +1,73 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use tracing::warn;
+
+/// The `.ch8` files of the directory in alphabetical order
+pub fn find_roms(directory: &str) -> Result<Vec<PathBuf>> {
+    let mut rom_paths: Vec<PathBuf> = fs::read_dir(directory)
+        .map_err(|e| anyhow!("Failed to read directory '{}': {}", directory, e))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ch8"))
+        .collect();
+    rom_paths.sort();
+    return Ok(rom_paths);
+}
+
+/// Roms of a directory that are played one after another, starting over after the last one
+pub struct Playlist {
+    rom_paths: Vec<PathBuf>,
+    index: usize,
+}
+
+impl Playlist {
+    pub fn new(directory: &str) -> Result<Self> {
+        let rom_paths = find_roms(directory)?;
+        if rom_paths.is_empty() {
+            return Err(anyhow!("No .ch8 roms found in '{}'", directory));
+        }
+        return Ok(Self {
+            rom_paths,
+            index: 0,
+        });
+    }
+
+    /// Path of the rom that was loaded last
+    pub fn current_path(&self) -> &Path {
+        return &self.rom_paths[self.index];
+    }
+
+    /// Loads the first rom that passes the check, skipping the others with a warning
+    pub fn load_first(&mut self, check: impl Fn(&[u8]) -> Result<()>) -> Result<Vec<u8>> {
+        return self.load_from(0, check);
+    }
+
+    /// Loads the rom after the current one that passes the check, skipping the others with a warning
+    pub fn load_next(&mut self, check: impl Fn(&[u8]) -> Result<()>) -> Result<Vec<u8>> {
+        return self.load_from(self.index + 1, check);
+    }
+
+    fn load_from(&mut self, start: usize, check: impl Fn(&[u8]) -> Result<()>) -> Result<Vec<u8>> {
+        for offset in 0..self.rom_paths.len() {
+            let index = (start + offset) % self.rom_paths.len();
+            let path = &self.rom_paths[index];
+            let rom = fs::read(path)
+                .map_err(|e| anyhow!("Failed to read rom '{}': {}", path.display(), e))
+                .and_then(|rom| {
+                    check(&rom)?;
+                    return Ok(rom);
+                });
+            match rom {
+                Ok(rom) => {
+                    self.index = index;
+                    return Ok(rom);
+                }
+                Err(e) => warn!("Skipping '{}': {}", path.display(), e),
+            }
+        }
+        return Err(anyhow!("None of the roms in the playlist can be played"));
+    }
+}
@@ -0,0 +1,308 @@
+use std::time::{Duration, Instant};
+
+use crate::display::Display;
+use crate::memory::{FONT, FONT_CHARACTER_SIZE};
+use crate::palette::{Palette, CHIP_8X_BACKGROUND_COLORS, CHIP_8X_FOREGROUND_COLORS};
+
+#[allow(clippy::eq_op, clippy::identity_op)]
+const GRID_COLOR_RGB: u32 = 0x30 << 16 | 0x30 << 8 | 0x30;
+
+/// How long a changed pixel stays tinted, about 10 frames at 60 Hz
+const CHANGE_HIGHLIGHT_DURATION: Duration = Duration::from_millis(167);
+
+/// Gray level of the pixels erased least often in the collision map, so they stand out from black
+const MIN_COLLISION_MAP_LEVEL: u32 = 0x40;
+
+/// The keys of the original keypad, row by row
+const KEYPAD_LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+/// Window pixels per side of a font pixel in the keypad widget
+const KEYPAD_GLYPH_SCALE: usize = 2;
+/// Window pixels between the digit and the edge of its key
+const KEYPAD_KEY_PADDING: usize = 3;
+/// Distance of the keypad widget from the window corner
+const KEYPAD_MARGIN: usize = 4;
+#[allow(clippy::eq_op, clippy::identity_op)]
+const KEYPAD_LABEL_COLOR_RGB: u32 = 0xA0 << 16 | 0xA0 << 8 | 0xA0;
+
+/// Color of the bars around the letterboxed frame buffer
+const LETTERBOX_COLOR_RGB: u32 = 0;
+
+/// Width the window should roughly have, the display is scaled up by a whole number to reach it
+const TARGET_WINDOW_WIDTH: usize = 1024;
+
+/// Scales the Chip-8 display up to the window size, so overlays can be drawn in between the Chip-8 pixels.
+/// Several displays of the same size can be presented side by side.
+pub struct Presenter {
+    frame_buffer: Vec<u32>,
+    width: usize,
+    height: usize,
+    /// width of a single display in window pixels
+    display_width: usize,
+    /// number of window pixels per side of a Chip-8 pixel
+    pixel_scale: usize,
+    palette: Palette,
+    /// draws the boundaries of the Chip-8 pixels
+    pub show_grid: bool,
+    /// tints pixels that were recently set in color3 and pixels that were recently cleared in color2
+    pub show_changes: bool,
+    /// shades every pixel by how often it was erased by a sprite draw, from dark gray to white
+    pub show_collision_map: bool,
+    /// shows the keypad with the held keys lit in the top right corner
+    pub show_keypad: bool,
+    /// the displays of the previous render, to find the changed pixels
+    previous_displays: Vec<Display>,
+    /// per display and pixel, when it last changed and whether it was set
+    changes: Vec<Vec<Option<(Instant, bool)>>>,
+    /// displays and grid, collision map and keypad setting of the current frame buffer content, to skip rendering unchanged frames
+    rendered: Option<(Vec<Display>, bool, bool, bool)>,
+    /// the frame buffer scaled to the window size by `letterbox`
+    letterbox_buffer: Vec<u32>,
+}
+
+impl Presenter {
+    pub fn new(
+        palette: Palette,
+        display_width: usize,
+        display_height: usize,
+        display_count: usize,
+    ) -> Self {
+        let pixel_scale = (TARGET_WINDOW_WIDTH / (display_width * display_count)).max(1);
+        let width = display_width * pixel_scale * display_count;
+        let height = display_height * pixel_scale;
+        return Self {
+            frame_buffer: vec![palette.color0; width * height],
+            width,
+            height,
+            display_width: display_width * pixel_scale,
+            pixel_scale,
+            palette,
+            show_grid: false,
+            show_changes: false,
+            show_collision_map: false,
+            show_keypad: false,
+            previous_displays: Vec::new(),
+            changes: Vec::new(),
+            rendered: None,
+            letterbox_buffer: Vec::new(),
+        };
+    }
+
+    pub fn width(&self) -> usize {
+        return self.width;
+    }
+
+    pub fn height(&self) -> usize {
+        return self.height;
+    }
+
+    pub fn frame_buffer(&self) -> &[u32] {
+        return &self.frame_buffer;
+    }
+
+    /// Draws the displays from left to right
+    pub fn render(&mut self, displays: &[&Display]) {
+        let changes_shown = self.track_changes(displays);
+        if let Some((rendered_displays, rendered_grid, rendered_collision_map, rendered_keypad)) =
+            &self.rendered
+        {
+            // the keypad is drawn over the frame buffer, so hiding it requires rendering the displays again
+            if !changes_shown
+                && rendered_displays.iter().eq(displays.iter().cloned())
+                && *rendered_grid == self.show_grid
+                && *rendered_collision_map == self.show_collision_map
+                && *rendered_keypad == self.show_keypad
+            {
+                return;
+            }
+        }
+        let max_collision_counts: Vec<u32> = displays
+            .iter()
+            .map(|display| display.max_collision_count())
+            .collect();
+
+        for (i, frame_rgb) in self.frame_buffer.iter_mut().enumerate() {
+            let display_index = (i % self.width) / self.display_width;
+            let display = displays[display_index];
+            let x = i % self.display_width;
+            let y = i / self.width;
+            let pixel_x = x / self.pixel_scale;
+            let pixel_y = y / self.pixel_scale;
+            let change = self
+                .changes
+                .get(display_index)
+                .and_then(|changes| changes[pixel_y * display.width() + pixel_x]);
+
+            let on_grid_line =
+                x.is_multiple_of(self.pixel_scale) || y.is_multiple_of(self.pixel_scale);
+            let collision_count = display.collision_count(pixel_x, pixel_y);
+            let rgb: u32 = if self.show_grid && on_grid_line {
+                GRID_COLOR_RGB
+            } else if self.show_collision_map && collision_count > 0 {
+                collision_map_color(collision_count, max_collision_counts[display_index])
+            } else if display.is_highlighted(pixel_x, pixel_y) {
+                self.palette.color2
+            } else if let Some((_, set)) = change {
+                if set {
+                    self.palette.color3
+                } else {
+                    self.palette.color2
+                }
+            } else if let Some(color_zones) = display.color_zones() {
+                if display.pixel(pixel_x, pixel_y) {
+                    CHIP_8X_FOREGROUND_COLORS[color_zones.foreground(pixel_x, pixel_y) as usize]
+                } else {
+                    CHIP_8X_BACKGROUND_COLORS[color_zones.background() as usize]
+                }
+            } else {
+                self.palette.color(display.pixel(pixel_x, pixel_y) as u8)
+            };
+
+            *frame_rgb = rgb;
+        }
+
+        self.rendered = Some((
+            displays.iter().map(|display| (*display).clone()).collect(),
+            self.show_grid,
+            self.show_collision_map,
+            self.show_keypad,
+        ));
+    }
+
+    /// Draws the Chip-8 keypad over the top right corner of the frame buffer, held keys are lit in `color1`.
+    /// Nothing is drawn when the window is too small for it.
+    pub fn draw_keypad(&mut self, held_keys: &[bool; 16]) {
+        let glyph_height = FONT_CHARACTER_SIZE as usize;
+        let key_width = 4 * KEYPAD_GLYPH_SCALE + 2 * KEYPAD_KEY_PADDING;
+        let key_height = glyph_height * KEYPAD_GLYPH_SCALE + 2 * KEYPAD_KEY_PADDING;
+        let Some(left) = self.width.checked_sub(4 * key_width + KEYPAD_MARGIN) else {
+            return;
+        };
+        let top = KEYPAD_MARGIN;
+        if top + 4 * key_height > self.height {
+            return;
+        }
+
+        for key in 0..16 {
+            let (column, row) = keypad_position(key);
+            let (background, label) = if held_keys[key as usize] {
+                (self.palette.color1, self.palette.color0)
+            } else {
+                (GRID_COLOR_RGB, KEYPAD_LABEL_COLOR_RGB)
+            };
+            let glyph = &FONT[key as usize * glyph_height..(key as usize + 1) * glyph_height];
+            for y in 0..key_height {
+                for x in 0..key_width {
+                    let glyph_x = x.wrapping_sub(KEYPAD_KEY_PADDING) / KEYPAD_GLYPH_SCALE;
+                    let glyph_y = y.wrapping_sub(KEYPAD_KEY_PADDING) / KEYPAD_GLYPH_SCALE;
+                    let on_glyph = glyph_x < 4
+                        && glyph_y < glyph_height
+                        && glyph[glyph_y] & (0x80 >> glyph_x) != 0;
+                    // the last row and column of a key separate it from its neighbours
+                    let rgb = if x == key_width - 1 || y == key_height - 1 {
+                        self.palette.color0
+                    } else if on_glyph {
+                        label
+                    } else {
+                        background
+                    };
+                    let frame_x = left + column * key_width + x;
+                    let frame_y = top + row * key_height + y;
+                    self.frame_buffer[frame_y * self.width + frame_x] = rgb;
+                }
+            }
+        }
+    }
+
+    /// Scales the frame buffer to the given window size, keeping its aspect ratio by filling the rest with black bars
+    pub fn letterbox(&mut self, window_width: usize, window_height: usize) -> &[u32] {
+        let (scaled_width, scaled_height) =
+            if window_width * self.height <= window_height * self.width {
+                (window_width, window_width * self.height / self.width)
+            } else {
+                (window_height * self.width / self.height, window_height)
+            };
+        let left = (window_width - scaled_width) / 2;
+        let top = (window_height - scaled_height) / 2;
+
+        self.letterbox_buffer.clear();
+        self.letterbox_buffer
+            .resize(window_width * window_height, LETTERBOX_COLOR_RGB);
+        for y in 0..scaled_height {
+            let frame_y = y * self.height / scaled_height;
+            for x in 0..scaled_width {
+                let frame_x = x * self.width / scaled_width;
+                self.letterbox_buffer[(top + y) * window_width + left + x] =
+                    self.frame_buffer[frame_y * self.width + frame_x];
+            }
+        }
+        return &self.letterbox_buffer;
+    }
+
+    /// Records the pixels that changed since the previous render and forgets old changes.
+    /// Returns whether the frame buffer has to be rendered again for the changes, even if the displays are unchanged.
+    fn track_changes(&mut self, displays: &[&Display]) -> bool {
+        if !self.show_changes {
+            let changes_shown = !self.changes.is_empty();
+            self.changes.clear();
+            self.previous_displays.clear();
+            return changes_shown;
+        }
+        if self.previous_displays.len() != displays.len() {
+            // nothing to compare with yet
+            self.previous_displays = displays.iter().map(|display| (*display).clone()).collect();
+            self.changes = displays
+                .iter()
+                .map(|display| vec![None; display.width() * display.height()])
+                .collect();
+            return false;
+        }
+
+        let now = Instant::now();
+        let mut changes_shown = false;
+        for (index, display) in displays.iter().enumerate() {
+            let previous_display = &self.previous_displays[index];
+            let changes = &mut self.changes[index];
+            for y in 0..display.height() {
+                for x in 0..display.width() {
+                    let change = &mut changes[y * display.width() + x];
+                    let pixel = display.pixel(x, y);
+                    if pixel != previous_display.pixel(x, y) {
+                        *change = Some((now, pixel));
+                    }
+                    if let Some((changed_at, _)) = change {
+                        // an expired change is still rendered once more to remove its tint
+                        changes_shown = true;
+                        if now.duration_since(*changed_at) >= CHANGE_HIGHLIGHT_DURATION {
+                            *change = None;
+                        }
+                    }
+                }
+            }
+            self.previous_displays[index] = (*display).clone();
+        }
+        return changes_shown;
+    }
+}
+
+/// Column and row of the Chip-8 key on the original keypad
+fn keypad_position(key: u8) -> (usize, usize) {
+    for (row, keys) in KEYPAD_LAYOUT.iter().enumerate() {
+        if let Some(column) = keys.iter().position(|layout_key| *layout_key == key) {
+            return (column, row);
+        }
+    }
+    panic!("{:#x} is no Chip-8 key", key);
+}
+
+/// Gray level of a pixel in the collision map, the pixel erased most often is white
+fn collision_map_color(count: u32, max_count: u32) -> u32 {
+    let level = (MIN_COLLISION_MAP_LEVEL as u64
+        + (0xFF - MIN_COLLISION_MAP_LEVEL) as u64 * count as u64 / max_count as u64)
+        as u32;
+    return level << 16 | level << 8 | level;
+}
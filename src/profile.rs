@@ -0,0 +1,76 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+use crate::opcode::Opcode;
+
+/// Counts how often each kind of instruction is executed, to analyze the behaviour of a rom offline
+pub struct Profile {
+    path: String,
+    /// executed instructions per pattern like `Dxyn`, together with the first instruction of that pattern
+    counts: BTreeMap<&'static str, (Opcode, u64)>,
+    total_cycles: u64,
+    /// elapsed 60 Hz timer frames
+    total_frames: u64,
+}
+
+#[derive(Serialize)]
+struct ProfileReport {
+    total_cycles: u64,
+    total_frames: u64,
+    /// most executed instructions first
+    opcodes: Vec<OpcodeCount>,
+}
+
+#[derive(Serialize)]
+struct OpcodeCount {
+    pattern: &'static str,
+    mnemonic: String,
+    count: u64,
+}
+
+impl Profile {
+    pub fn new(path: String) -> Self {
+        return Self {
+            path,
+            counts: BTreeMap::new(),
+            total_cycles: 0,
+            total_frames: 0,
+        };
+    }
+
+    pub fn record_instruction(&mut self, opcode: Opcode) {
+        self.total_cycles += 1;
+        self.counts.entry(opcode.pattern()).or_insert((opcode, 0)).1 += 1;
+    }
+
+    pub fn record_frames(&mut self, elapsed_frames: u64) {
+        self.total_frames += elapsed_frames;
+    }
+
+    /// Writes the histogram as json
+    pub fn write(&self) -> Result<()> {
+        let mut opcodes: Vec<OpcodeCount> = self
+            .counts
+            .iter()
+            .map(|(pattern, (opcode, count))| OpcodeCount {
+                pattern,
+                mnemonic: opcode.mnemonic(),
+                count: *count,
+            })
+            .collect();
+        opcodes.sort_by_key(|opcode_count| Reverse(opcode_count.count));
+        let report = ProfileReport {
+            total_cycles: self.total_cycles,
+            total_frames: self.total_frames,
+            opcodes,
+        };
+        let json = serde_json::to_string_pretty(&report)
+            .map_err(|e| anyhow!("Failed to serialize profile: {}", e))?;
+        return fs::write(&self.path, json)
+            .map_err(|e| anyhow!("Failed to write profile to '{}': {}", self.path, e));
+    }
+}
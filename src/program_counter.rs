@@ -1,3 +1,4 @@
+#[derive(Clone)]
 pub struct ProgramCounter {
     /// used to store the currently executing address
     ptr: u16,
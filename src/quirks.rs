@@ -0,0 +1,71 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+/// Behaviours that differ between Chip-8 interpreters and that some roms rely on
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Quirks {
+    /// When set, sprites whose start coordinate lies outside of the screen are not drawn at all,
+    /// instead of wrapping the start coordinate around the screen edges
+    pub clip_sprite_start: bool,
+    /// When set, `Dxy0` draws a 16x16 sprite from 32 bytes like SCHIP, instead of a sprite without rows
+    pub large_sprite_dxy0: bool,
+    /// When set, the pixels of a sprite crossing the right edge are drawn at the left edge instead of being clipped
+    pub wrap_x: bool,
+    /// When set, the pixels of a sprite crossing the bottom edge are drawn at the top edge instead of being clipped
+    pub wrap_y: bool,
+    /// When set, a sprite read from `I` that crosses the end of memory continues at the start of memory,
+    /// instead of stopping the emulation
+    pub sprite_read_wraps: bool,
+    /// When set, `Fx33`, `Fx55` and `Fx65` continue at the start of memory when they cross its end,
+    /// instead of stopping the emulation
+    pub memory_access_wraps: bool,
+}
+
+impl Quirks {
+    /// Every quirk with its name in the config file and whether it is enabled
+    pub fn named(&self) -> [(&'static str, bool); 6] {
+        return [
+            ("clip_sprite_start", self.clip_sprite_start),
+            ("large_sprite_dxy0", self.large_sprite_dxy0),
+            ("wrap_x", self.wrap_x),
+            ("wrap_y", self.wrap_y),
+            ("sprite_read_wraps", self.sprite_read_wraps),
+            ("memory_access_wraps", self.memory_access_wraps),
+        ];
+    }
+
+    /// Enables or disables the quirk with the name used in the config file
+    pub fn set(&mut self, name: &str, enabled: bool) -> Result<()> {
+        let quirk = match name {
+            "clip_sprite_start" => &mut self.clip_sprite_start,
+            "large_sprite_dxy0" => &mut self.large_sprite_dxy0,
+            "wrap_x" => &mut self.wrap_x,
+            "wrap_y" => &mut self.wrap_y,
+            "sprite_read_wraps" => &mut self.sprite_read_wraps,
+            "memory_access_wraps" => &mut self.memory_access_wraps,
+            _ => {
+                let names: Vec<&str> = self.named().iter().map(|(name, _)| *name).collect();
+                return Err(anyhow!(
+                    "Unknown quirk '{}', expected one of {}",
+                    name,
+                    names.join(", ")
+                ));
+            }
+        };
+        *quirk = enabled;
+        return Ok(());
+    }
+
+    /// Every quirk switched to the other behaviour, to compare how a rom runs with each
+    pub fn toggled(&self) -> Quirks {
+        return Quirks {
+            clip_sprite_start: !self.clip_sprite_start,
+            large_sprite_dxy0: !self.large_sprite_dxy0,
+            wrap_x: !self.wrap_x,
+            wrap_y: !self.wrap_y,
+            sprite_read_wraps: !self.sprite_read_wraps,
+            memory_access_wraps: !self.memory_access_wraps,
+        };
+    }
+}
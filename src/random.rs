@@ -0,0 +1,34 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Source of the random numbers used by the Cxkk instruction
+pub trait RandomSource: Send {
+    fn next_u8(&mut self) -> u8;
+}
+
+/// Random numbers provided by the operating system backed thread rng
+pub struct OsRandom;
+
+impl RandomSource for OsRandom {
+    fn next_u8(&mut self) -> u8 {
+        return rand::random();
+    }
+}
+
+/// Reproducible random numbers, the same seed always yields the same sequence
+pub struct SeededRandom {
+    rng: StdRng,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> Self {
+        return Self {
+            rng: StdRng::seed_from_u64(seed),
+        };
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_u8(&mut self) -> u8 {
+        return self.rng.random();
+    }
+}
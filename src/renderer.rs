@@ -1,66 +1,219 @@
-use tracing::error;
+use std::ops::RangeInclusive;
 
-pub const SCREEN_WIDTH: usize = 64;
-pub const SCREEN_HEIGHT: usize = 32;
+use tracing::warn;
 
-const SPRITE_WIDTH: usize = 8;
+use crate::display::Display;
+use crate::quirks::Quirks;
 
-pub type DisplaySender = single_value_channel::Updater<Option<[[bool; 64]; 32]>>;
+pub const DEFAULT_SCREEN_WIDTH: usize = 64;
+pub const DEFAULT_SCREEN_HEIGHT: usize = 32;
+/// sprite coordinates are 8-bit values, so larger screens could not be fully drawn on
+pub const MAX_SCREEN_DIMENSION: usize = 256;
+
+/// Number of 60 Hz frames an erased pixel stays highlighted
+const COLLISION_HIGHLIGHT_FRAMES: u8 = 10;
+
+/// Consecutive failed display updates after which the window is considered gone
+const MAX_FAILED_DISPLAY_UPDATES: u32 = 10;
+
+/// A display update together with its position in the sequence of all updates
+#[derive(Clone)]
+pub struct PublishedDisplay {
+    /// counts up with every update, a gap means that updates were replaced before they were presented
+    pub sequence: u64,
+    pub display: Display,
+}
+
+/// Only keeps the most recent display, so a cpu drawing faster than the window renders never blocks
+/// and never queues up frames. The window always presents the display after the last draw.
+pub type DisplaySender = single_value_channel::Updater<Option<PublishedDisplay>>;
+pub type DisplayReceiver = single_value_channel::Receiver<Option<PublishedDisplay>>;
 
 pub struct Renderer {
-    display_content2d: [[bool; SCREEN_WIDTH]; SCREEN_HEIGHT],
+    display: Display,
     display_sender: DisplaySender,
+    published_count: u64,
+    /// number of clears and sprite draws, to notice when the display content was changed
+    change_count: u64,
+    /// highlights the pixels erased by sprite draws
+    show_collisions: bool,
+    /// number of display updates in a row that could not be sent
+    failed_updates: u32,
 }
 
 impl Renderer {
-    pub fn new(display_sender: DisplaySender) -> Self {
+    pub fn new(display_sender: DisplaySender, width: usize, height: usize) -> Self {
+        assert!(
+            (1..=MAX_SCREEN_DIMENSION).contains(&width)
+                && (1..=MAX_SCREEN_DIMENSION).contains(&height),
+            "screen dimensions should be between 1 and {} pixels",
+            MAX_SCREEN_DIMENSION
+        );
         return Renderer {
-            display_content2d: [[false; 64]; 32],
+            display: Display::new(width, height),
             display_sender,
+            published_count: 0,
+            change_count: 0,
+            show_collisions: false,
+            failed_updates: 0,
         };
     }
 
+    pub fn display(&self) -> &Display {
+        return &self.display;
+    }
+
+    /// Whether the display updates kept failing, which means that the window is gone
+    pub fn display_lost(&self) -> bool {
+        return self.failed_updates >= MAX_FAILED_DISPLAY_UPDATES;
+    }
+
+    pub fn change_count(&self) -> u64 {
+        return self.change_count;
+    }
+
+    pub fn set_show_collisions(&mut self, show_collisions: bool) {
+        self.show_collisions = show_collisions;
+    }
+
+    /// Lets the collision highlights fade out
+    pub fn decay_collision_highlights(&mut self, elapsed_frames: u8) {
+        if self.show_collisions && self.display.decay_highlights(elapsed_frames) {
+            self.publish_display();
+        }
+    }
+
+    /// Replaces the whole display, e.g. when a snapshot is restored
+    pub fn restore_display(&mut self, display: Display) {
+        self.display = display;
+        self.change_count += 1;
+        self.publish_display();
+    }
+
     pub fn clear_display(&mut self) {
-        for line in self.display_content2d.iter_mut() {
-            for pixel in line.iter_mut() {
-                *pixel = false;
-            }
+        self.display.clear();
+        self.change_count += 1;
+        self.publish_display();
+    }
+
+    /// Colors the display like the CHIP-8X color board, starting with the power on colors
+    pub fn enable_color_zones(&mut self) {
+        self.display.enable_color_zones();
+        self.publish_display();
+    }
+
+    /// Switches to the next CHIP-8X background color, does nothing without color zones
+    pub fn cycle_background_color(&mut self) {
+        if let Some(color_zones) = self.display.color_zones_mut() {
+            color_zones.cycle_background();
+            self.change_count += 1;
+            self.publish_display();
+        }
+    }
+
+    /// Sets the CHIP-8X foreground color of the zones, does nothing without color zones
+    pub fn set_zone_color(
+        &mut self,
+        columns: RangeInclusive<usize>,
+        rows: RangeInclusive<usize>,
+        color: u8,
+    ) {
+        if let Some(color_zones) = self.display.color_zones_mut() {
+            color_zones.set_foreground(columns, rows, color);
+            self.change_count += 1;
+            self.publish_display();
         }
     }
 
-    pub fn draw_sprite(&mut self, sprite: &[u8], target_x: u8, target_y: u8) -> bool {
+    /// Draws a sprite with `bytes_per_row` bytes per row, the most significant bit is the left most pixel
+    pub fn draw_sprite(
+        &mut self,
+        sprite: &[u8],
+        bytes_per_row: usize,
+        target_x: u8,
+        target_y: u8,
+        quirks: &Quirks,
+    ) -> bool {
+        let screen_width = self.display.width();
+        let screen_height = self.display.height();
         let mut pixel_erased = false;
-        // wrapping around the display when the target location is out of bound
-        let normalized_x = target_x as usize % SCREEN_WIDTH;
-        let normalized_y = target_y as usize % SCREEN_HEIGHT;
-        for (sprite_y, sprite_line_byte) in sprite.iter().enumerate() {
-            for bit_index in (0..SPRITE_WIDTH).rev() {
-                let pixel_x = normalized_x + SPRITE_WIDTH - 1 - bit_index;
-                let pixel_y = normalized_y + sprite_y;
-                if pixel_x >= SCREEN_WIDTH || pixel_y >= SCREEN_HEIGHT {
-                    // the pixel would be out of screen there in wrapping around in this case
+        let (normalized_x, normalized_y) = if quirks.clip_sprite_start {
+            // pixels outside of the screen are skipped below, so the whole sprite is clipped
+            (target_x as usize, target_y as usize)
+        } else {
+            // wrapping around the display when the target location is out of bound
+            (
+                target_x as usize % screen_width,
+                target_y as usize % screen_height,
+            )
+        };
+        if normalized_x >= screen_width || normalized_y >= screen_height {
+            // the start coordinate lies outside of the screen, which only happens when clipping it
+            return false;
+        }
+        for (sprite_y, sprite_row) in sprite.chunks(bytes_per_row).enumerate() {
+            for sprite_x in 0..sprite_row.len() * 8 {
+                let Some(pixel_x) =
+                    wrap_or_clip(normalized_x + sprite_x, screen_width, quirks.wrap_x)
+                else {
                     continue;
-                }
+                };
+                let Some(pixel_y) =
+                    wrap_or_clip(normalized_y + sprite_y, screen_height, quirks.wrap_y)
+                else {
+                    continue;
+                };
 
-                let bit_mask = 1 << bit_index;
-                let masked = sprite_line_byte & bit_mask;
+                let bit_mask = 0x80 >> (sprite_x % 8);
+                let masked = sprite_row[sprite_x / 8] & bit_mask;
                 let bit_set = masked != 0;
-                let previous_value = self.display_content2d[pixel_y][pixel_x];
+                let previous_value = self.display.pixel(pixel_x, pixel_y);
                 let new_value = previous_value != bit_set;
                 if !new_value && previous_value {
-                    pixel_erased = true
+                    pixel_erased = true;
+                    self.display.count_collision(pixel_x, pixel_y);
+                    if self.show_collisions {
+                        self.display
+                            .highlight(pixel_x, pixel_y, COLLISION_HIGHLIGHT_FRAMES);
+                    }
                 }
-                self.display_content2d[pixel_y][pixel_x] = new_value;
+                self.display.set_pixel(pixel_x, pixel_y, new_value);
             }
         }
 
+        self.change_count += 1;
+        self.publish_display();
+
+        return pixel_erased;
+    }
+
+    fn publish_display(&mut self) {
         if !self.display_sender.has_no_receiver() {
-            let update_result = self.display_sender.update(Some(self.display_content2d));
-            if update_result.is_err() {
-                error!("Failed to sent display update");
+            self.published_count += 1;
+            let update_result = self.display_sender.update(Some(PublishedDisplay {
+                sequence: self.published_count,
+                display: self.display.clone(),
+            }));
+            if update_result.is_ok() {
+                self.failed_updates = 0;
+            } else {
+                self.failed_updates += 1;
+                warn!(
+                    "Failed to send display update, {} in a row",
+                    self.failed_updates
+                );
             }
         }
+    }
+}
 
-        return pixel_erased;
+/// Maps a coordinate beyond the screen edge to the other side of the screen or drops it
+fn wrap_or_clip(coordinate: usize, screen_size: usize, wrap: bool) -> Option<usize> {
+    if coordinate < screen_size {
+        return Some(coordinate);
+    }
+    if wrap {
+        return Some(coordinate % screen_size);
     }
+    return None;
 }
@@ -0,0 +1,167 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+use anyhow::{anyhow, Result};
+use tracing::info;
+use u4::{U4x2, U4};
+
+use crate::display::Display;
+use crate::keyboard::{self, KeysChange};
+use crate::renderer::DisplayReceiver;
+use crate::timing::FRAME_DURATION;
+
+/// Sent to the client: width and height as big endian u16, followed by the packed pixels
+const FRAME_MESSAGE: u8 = 0x01;
+/// Sent by the client with the Chip-8 key `0x0` to `0xF`
+const KEY_DOWN_MESSAGE: u8 = 0x02;
+const KEY_UP_MESSAGE: u8 = 0x03;
+/// Clients only send key messages, anything longer is a protocol error
+const MAX_CLIENT_MESSAGE_LENGTH: usize = 2;
+
+/// Streams the display to one TCP client at a time and feeds its key presses to the keyboard.
+/// Every message is prefixed with its length as big endian u32, followed by the message type and its payload.
+pub fn serve(
+    address: &str,
+    mut display_receiver: DisplayReceiver,
+    pressed_keys_sender: mpsc::Sender<KeysChange>,
+    cpu_thread: &JoinHandle<Result<()>>,
+) -> Result<()> {
+    let listener = TcpListener::bind(address)
+        .map_err(|e| anyhow!("Failed to listen on '{}': {}", address, e))?;
+    // polling for clients, so the server stops together with the cpu
+    listener
+        .set_nonblocking(true)
+        .map_err(|e| anyhow!("Failed to configure the server socket: {}", e))?;
+    info!("Serving on {}", address);
+    while !cpu_thread.is_finished() {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                info!("Client {} connected", peer);
+                match serve_client(
+                    stream,
+                    &mut display_receiver,
+                    &pressed_keys_sender,
+                    cpu_thread,
+                ) {
+                    Ok(()) => info!("Client {} disconnected", peer),
+                    Err(e) => info!("Client {} disconnected: {}", peer, e),
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(FRAME_DURATION),
+            Err(e) => return Err(anyhow!("Failed to accept a client: {}", e)),
+        }
+    }
+    return Ok(());
+}
+
+/// Sends every display update, at most once per 60 Hz frame, until the client disconnects
+fn serve_client(
+    mut stream: TcpStream,
+    display_receiver: &mut DisplayReceiver,
+    pressed_keys_sender: &mpsc::Sender<KeysChange>,
+    cpu_thread: &JoinHandle<Result<()>>,
+) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    // waiting for input paces the frames
+    stream.set_read_timeout(Some(FRAME_DURATION))?;
+    stream.set_nodelay(true)?;
+    // a new client always gets the current display first
+    let mut sent_sequence = 0;
+    let mut received: Vec<u8> = Vec::new();
+    let mut read_buffer = [0u8; 256];
+    while !cpu_thread.is_finished() {
+        if let Some(latest) = display_receiver.latest() {
+            if latest.sequence != sent_sequence {
+                write_message(&mut stream, FRAME_MESSAGE, &frame_payload(&latest.display))?;
+                sent_sequence = latest.sequence;
+            }
+        }
+
+        match stream.read(&mut read_buffer) {
+            Ok(0) => return Ok(()),
+            Ok(count) => received.extend_from_slice(&read_buffer[..count]),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => return Err(anyhow!(e)),
+        }
+        while let Some((message_type, payload)) = take_message(&mut received)? {
+            let change = key_change(message_type, &payload)?;
+            pressed_keys_sender
+                .send(change)
+                .map_err(|_| anyhow!("The cpu stopped"))?;
+        }
+    }
+    return Ok(());
+}
+
+/// Width and height as big endian u16, followed by the packed pixels
+fn frame_payload(display: &Display) -> Vec<u8> {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&(display.width() as u16).to_be_bytes());
+    payload.extend_from_slice(&(display.height() as u16).to_be_bytes());
+    payload.extend_from_slice(&display.packed());
+    return payload;
+}
+
+fn write_message(stream: &mut TcpStream, message_type: u8, payload: &[u8]) -> Result<()> {
+    let length = payload.len() as u32 + 1;
+    stream.write_all(&length.to_be_bytes())?;
+    stream.write_all(&[message_type])?;
+    stream.write_all(payload)?;
+    return Ok(());
+}
+
+/// Removes the first complete message from the received bytes, returns its type and payload
+fn take_message(received: &mut Vec<u8>) -> Result<Option<(u8, Vec<u8>)>> {
+    if received.len() < 4 {
+        return Ok(None);
+    }
+    let length = u32::from_be_bytes([received[0], received[1], received[2], received[3]]) as usize;
+    if length == 0 || length > MAX_CLIENT_MESSAGE_LENGTH {
+        return Err(anyhow!("Invalid message length {}", length));
+    }
+    if received.len() < 4 + length {
+        return Ok(None);
+    }
+    let message: Vec<u8> = received.drain(..4 + length).skip(4).collect();
+    return Ok(Some((message[0], message[1..].to_vec())));
+}
+
+fn key_change(message_type: u8, payload: &[u8]) -> Result<KeysChange> {
+    let [key] = payload else {
+        return Err(anyhow!("Key messages should contain a single key"));
+    };
+    if *key > 0xF {
+        return Err(anyhow!("Invalid Chip-8 key {:#x}", key));
+    }
+    let key: U4 = U4x2::from(*key).right();
+    let window_key = keyboard::to_window_key(key);
+    return match message_type {
+        KEY_DOWN_MESSAGE => Ok(KeysChange {
+            pressed: vec![window_key],
+            released: Vec::new(),
+        }),
+        KEY_UP_MESSAGE => Ok(KeysChange {
+            pressed: Vec::new(),
+            released: vec![window_key],
+        }),
+        other => Err(anyhow!("Unknown message type {:#04x}", other)),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_payload_decodes_to_the_display() {
+        let mut display = Display::new(64, 32);
+        for (x, y) in [(0, 0), (63, 0), (10, 5), (0, 31), (63, 31)] {
+            display.set_pixel(x, y, true);
+        }
+        let payload = frame_payload(&display);
+        assert_eq!(payload[..4], [0, 64, 0, 32]);
+        assert!(Display::from_packed(64, 32, &payload[4..]).unwrap() == display);
+    }
+}
@@ -0,0 +1,55 @@
+use std::thread;
+use std::time::Duration;
+
+use crate::quirks::Quirks;
+use crate::renderer::Renderer;
+
+const SPLASH_TEXT: &str = "CHIP-8";
+const GLYPH_WIDTH: usize = 4;
+const GLYPH_HEIGHT: usize = 5;
+const GLYPH_SPACING: usize = 1;
+
+/// Rows of a glyph in the upper nibble, like the Chip-8 font
+fn glyph(character: char) -> [u8; GLYPH_HEIGHT] {
+    return match character {
+        'C' => [0xF0, 0x80, 0x80, 0x80, 0xF0],
+        'H' => [0x90, 0x90, 0xF0, 0x90, 0x90],
+        'I' => [0xE0, 0x40, 0x40, 0x40, 0xE0],
+        'P' => [0xF0, 0x90, 0xF0, 0x80, 0x80],
+        '-' => [0x00, 0x00, 0xF0, 0x00, 0x00],
+        '8' => [0xF0, 0x90, 0xF0, 0x90, 0xF0],
+        _ => [0x00; GLYPH_HEIGHT],
+    };
+}
+
+/// Draws the text centered on the emulated display, characters without a glyph are left blank
+pub fn draw_text(renderer: &mut Renderer, text: &str) {
+    let character_count = text.chars().count();
+    let text_width =
+        (character_count * (GLYPH_WIDTH + GLYPH_SPACING)).saturating_sub(GLYPH_SPACING);
+    let screen_width = renderer.display().width();
+    let start_x = screen_width.saturating_sub(text_width) / 2;
+    let start_y = renderer.display().height().saturating_sub(GLYPH_HEIGHT) / 2;
+    // clipping keeps the text from wrapping around on tiny screens
+    let quirks = Quirks {
+        clip_sprite_start: true,
+        ..Quirks::default()
+    };
+    for (index, character) in text.chars().enumerate() {
+        let x = start_x + index * (GLYPH_WIDTH + GLYPH_SPACING);
+        if x >= screen_width {
+            break;
+        }
+        renderer.draw_sprite(&glyph(character), 1, x as u8, start_y as u8, &quirks);
+    }
+}
+
+/// Shows the splash screen for the duration and clears the display before the rom starts
+pub fn show_splash(renderer: &mut Renderer, duration: Duration) {
+    if duration.is_zero() {
+        return;
+    }
+    draw_text(renderer, SPLASH_TEXT);
+    thread::sleep(duration);
+    renderer.clear_display();
+}
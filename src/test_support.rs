@@ -0,0 +1,146 @@
+//! Helpers shared by the unit tests
+
+/// Assembles the opcodes into the bytes of a ROM, the variants of `Opcode` can be used without the
+/// `Opcode::` prefix, e.g. `rom![SetRegister { x: 0, kk: 5 }, Jump { nnn: 0x200 }]`
+macro_rules! rom {
+    ($($opcode:expr),* $(,)?) => {{
+        #[allow(unused_imports)]
+        use crate::opcode::Opcode::*;
+        let opcodes: Vec<crate::opcode::Opcode> = vec![$($opcode),*];
+        opcodes.iter().flat_map(|opcode| opcode.encode()).collect::<Vec<u8>>()
+    }};
+}
+
+use std::sync::mpsc;
+
+use u4::U4x2;
+
+use crate::cpu::{Cpu, CpuConfig};
+use crate::keyboard::{to_window_key, Keyboard, KeysChange};
+use crate::memory::PROGRAM_START_ADDRESS;
+use crate::opcode::Opcode;
+use crate::random::RandomSource;
+use crate::renderer::{Renderer, DEFAULT_SCREEN_HEIGHT, DEFAULT_SCREEN_WIDTH};
+
+/// The default configuration without audio
+pub fn silent_config() -> CpuConfig {
+    return CpuConfig {
+        audio: false,
+        ..CpuConfig::default()
+    };
+}
+
+/// A silent cpu with the program loaded at the program start
+pub fn cpu_with_program(program: &[u8]) -> Cpu {
+    return cpu_with_config(program, silent_config());
+}
+
+/// A cpu of the given configuration with the program loaded at the program start
+pub fn cpu_with_config(program: &[u8], config: CpuConfig) -> Cpu {
+    let (cpu, _keys_sender) = cpu_with_keys(program, config);
+    return cpu;
+}
+
+/// A cpu like `cpu_with_config`, and the sender to press and release keys on its keyboard
+pub fn cpu_with_keys(program: &[u8], config: CpuConfig) -> (Cpu, mpsc::Sender<KeysChange>) {
+    let (_display_receiver, display_sender) = single_value_channel::channel();
+    let (keys_sender, keys_receiver) = mpsc::channel();
+    let renderer = Renderer::new(display_sender, DEFAULT_SCREEN_WIDTH, DEFAULT_SCREEN_HEIGHT);
+    let mut cpu = Cpu::new(renderer, Keyboard::new(keys_receiver), config);
+    cpu.load_program_into_memory(program);
+    return (cpu, keys_sender);
+}
+
+/// Presses the Chip-8 key `0x0` to `0xF`
+pub fn press(keys_sender: &mpsc::Sender<KeysChange>, key: u8) {
+    let change = KeysChange {
+        pressed: vec![to_window_key(U4x2::from(key).right())],
+        released: Vec::new(),
+    };
+    keys_sender.send(change).unwrap();
+}
+
+/// Releases the Chip-8 key `0x0` to `0xF`
+pub fn release(keys_sender: &mpsc::Sender<KeysChange>, key: u8) {
+    let change = KeysChange {
+        pressed: Vec::new(),
+        released: vec![to_window_key(U4x2::from(key).right())],
+    };
+    keys_sender.send(change).unwrap();
+}
+
+/// Emits instructions into the bytes of a test rom that starts at the program start,
+/// e.g. `ProgramBuilder::new().ld(0, 5).add_kk(0, 3).draw(0, 1, 5).build()`
+#[derive(Default)]
+pub struct ProgramBuilder {
+    bytes: Vec<u8>,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn opcode(mut self, opcode: Opcode) -> Self {
+        self.bytes.extend(opcode.encode());
+        return self;
+    }
+
+    /// The address of the next emitted instruction
+    pub fn address(&self) -> u16 {
+        return PROGRAM_START_ADDRESS + self.bytes.len() as u16;
+    }
+
+    /// 6xkk
+    pub fn ld(self, x: u8, kk: u8) -> Self {
+        return self.opcode(Opcode::SetRegister { x, kk });
+    }
+
+    /// 7xkk
+    pub fn add_kk(self, x: u8, kk: u8) -> Self {
+        return self.opcode(Opcode::AddKk { x, kk });
+    }
+
+    /// Fx29
+    pub fn font(self, x: u8) -> Self {
+        return self.opcode(Opcode::SetIToSprite { x });
+    }
+
+    /// Dxyn
+    pub fn draw(self, x: u8, y: u8, n: u8) -> Self {
+        return self.opcode(Opcode::DrawSprite { x, y, n });
+    }
+
+    /// A jump to itself, which keeps the cpu at the end of the program
+    pub fn halt(self) -> Self {
+        let nnn = self.address();
+        return self.opcode(Opcode::Jump { nnn });
+    }
+
+    pub fn build(self) -> Vec<u8> {
+        return self.bytes;
+    }
+}
+
+/// Returns the given numbers in order, starting over after the last one
+pub struct FixedSequence {
+    numbers: Vec<u8>,
+    next: usize,
+}
+
+impl FixedSequence {
+    pub fn new(numbers: &[u8]) -> Self {
+        return Self {
+            numbers: numbers.to_vec(),
+            next: 0,
+        };
+    }
+}
+
+impl RandomSource for FixedSequence {
+    fn next_u8(&mut self) -> u8 {
+        let number = self.numbers[self.next];
+        self.next = (self.next + 1) % self.numbers.len();
+        return number;
+    }
+}
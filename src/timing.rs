@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use crate::opcode::Opcode;
+
+/// Length of a 60 Hz frame in microseconds
+pub const FRAME_MICROS: u32 = 16_667;
+pub const FRAME_DURATION: Duration = Duration::from_micros(FRAME_MICROS as u64);
+
+/// Base cost of a sprite draw on the COSMAC VIP in microseconds
+const DRAW_BASE_MICROS: u32 = 3_812;
+/// Additional cost of every drawn sprite row in microseconds
+const DRAW_ROW_MICROS: u32 = 1_264;
+
+/// Approximate execution time of an instruction on the COSMAC VIP interpreter in microseconds.
+/// Instructions that can skip are given the cost of the common case.
+pub fn vip_instruction_micros(opcode: &Opcode) -> u32 {
+    return match opcode {
+        Opcode::Nop => 0,
+        Opcode::ClearDisplay => 109,
+        Opcode::Return => 105,
+        Opcode::Jump { .. } => 105,
+        Opcode::Call { .. } => 105,
+        Opcode::SkipIfEqualKk { .. } => 55,
+        Opcode::SkipIfNotEqualKk { .. } => 55,
+        Opcode::SkipIfEqualRegister { .. } => 73,
+        Opcode::SetRegister { .. } => 27,
+        Opcode::AddKk { .. } => 45,
+        Opcode::CopyRegister { .. }
+        | Opcode::Or { .. }
+        | Opcode::And { .. }
+        | Opcode::Xor { .. }
+        | Opcode::Add { .. }
+        | Opcode::Sub { .. }
+        | Opcode::ShiftRight { .. }
+        | Opcode::Subn { .. }
+        | Opcode::ShiftLeft { .. } => 200,
+        Opcode::SkipIfNotEqualRegister { .. } => 73,
+        Opcode::SetI { .. } => 55,
+        Opcode::JumpV0 { .. } => 105,
+        Opcode::Random { .. } => 164,
+        Opcode::DrawSprite { n, .. } => DRAW_BASE_MICROS + DRAW_ROW_MICROS * *n as u32,
+        Opcode::SkipIfKeyPressed { .. } => 73,
+        Opcode::SkipIfKeyNotPressed { .. } => 73,
+        Opcode::LoadDelayTimer { .. } => 45,
+        Opcode::WaitForKey { .. } => 45,
+        Opcode::SetDelayTimer { .. } => 45,
+        Opcode::SetSoundTimer { .. } => 45,
+        Opcode::AddI { .. } => 86,
+        Opcode::SetIToSprite { .. } => 91,
+        Opcode::StoreBcd { .. } => 927,
+        Opcode::StoreRegisters { x } => 605 + 64 * *x as u32,
+        Opcode::LoadRegisters { x } => 605 + 64 * *x as u32,
+        // the CHIP-8X interpreter is not measured, these are the costs of comparable instructions
+        Opcode::CycleBackgroundColor => 109,
+        Opcode::AddNibbles { .. } => 200,
+        Opcode::SetForegroundColor { .. } => 200,
+    };
+}
@@ -0,0 +1,49 @@
+use std::fs;
+use std::process::Command;
+
+use serde_json::Value;
+
+/// Counts to 3 in a loop, then draws a 2 row sprite at the top left corner and stops
+const COUNT_AND_DRAW: &str = "
+: main
+    v0 := 0
+    v1 := 0
+    loop
+        v1 += 1
+        if v1 == 3 then jump done
+    again
+: done
+    i := smile
+    sprite v0 v0 2
+: halt
+    jump halt
+: smile
+    0b01100110
+    0b10000001
+";
+
+#[test]
+fn assembled_program_runs_headless() {
+    let source_path =
+        std::env::temp_dir().join(format!("chip_8_assemble_test_{}.8o", std::process::id()));
+    fs::write(&source_path, COUNT_AND_DRAW).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_chip_8_emulator"))
+        .args([
+            "--headless",
+            "--cycles",
+            "33",
+            "--no-audio",
+            "--dump-state-json",
+            "--assemble",
+        ])
+        .arg(&source_path)
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(&source_path);
+    assert!(output.status.success(), "{:?}", output);
+
+    let state: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(state["general_registers"][1], 3);
+    assert_eq!(state["display"][0], "6600000000000000");
+    assert_eq!(state["display"][1], "8100000000000000");
+}
@@ -0,0 +1,62 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Draws the font character 0 twice at the same position, so the second draw collides, then loops forever
+const COLLIDING_ROM: [u8; 10] = [
+    0x60, 0x00, // V0 := 0
+    0xF0, 0x29, // I := font character of V0
+    0xD0, 0x05, // draw 5 rows at (V0, V0)
+    0xD0, 0x05, // draw again, erasing every pixel
+    0x12, 0x08, // loop
+];
+
+fn write_rom(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("chip_8_{}_{}.ch8", name, std::process::id()));
+    fs::write(&path, COLLIDING_ROM).unwrap();
+    return path;
+}
+
+/// Runs the emulator headless with the tracing options and returns its stdout
+fn traced_stdout(rom_path: &PathBuf, extra_args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_chip_8_emulator"))
+        .args([
+            "--headless",
+            "--cycles",
+            "20",
+            "--no-audio",
+            "--log-instructions",
+            "--log-collisions",
+            "--explain",
+        ])
+        .args(extra_args)
+        .arg(rom_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    return String::from_utf8(output.stdout).unwrap();
+}
+
+#[test]
+fn quiet_mode_prints_no_traces() {
+    let rom_path = write_rom("quiet_test");
+    let traced = traced_stdout(&rom_path, &[]);
+    assert!(traced.contains("0x200: 6000"), "{}", traced);
+    assert!(traced.contains("Sprite collision"), "{}", traced);
+
+    assert_eq!(traced_stdout(&rom_path, &["--quiet"]), "");
+    let _ = fs::remove_file(&rom_path);
+}
+
+#[test]
+fn quiet_validation_prints_nothing() {
+    let rom_path = write_rom("quiet_validate_test");
+    let output = Command::new(env!("CARGO_BIN_EXE_chip_8_emulator"))
+        .args(["--validate", "--quiet"])
+        .arg(&rom_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(output.stdout.is_empty(), "{:?}", output);
+    let _ = fs::remove_file(&rom_path);
+}
@@ -0,0 +1,90 @@
+use std::fs;
+use std::net::TcpListener;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[path = "../examples/client.rs"]
+#[allow(dead_code)]
+mod client;
+
+use client::Client;
+
+/// Clears the display, waits for a key, then draws its font character at the top left corner and loops forever.
+/// Clearing publishes the first frame, the server sends nothing before.
+const DRAW_PRESSED_KEY_ROM: [u8; 10] = [
+    0x00, 0xE0, // clear
+    0xF0, 0x0A, // V0 := key
+    0xF0, 0x29, // I := font character of V0
+    0xD1, 0x15, // draw 5 rows at (V1, V1)
+    0x12, 0x08, // loop
+];
+
+/// Kills the server when the test ends, even when it fails
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn free_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    return listener.local_addr().unwrap().port();
+}
+
+fn connect(address: &str) -> Client {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        match Client::connect(address) {
+            Ok(client) => return client,
+            Err(e) if Instant::now() >= deadline => {
+                panic!("Failed to connect to the server: {}", e)
+            }
+            Err(_) => thread::sleep(Duration::from_millis(50)),
+        }
+    }
+}
+
+#[test]
+fn streams_frames_and_receives_keys() {
+    let rom_path =
+        std::env::temp_dir().join(format!("chip_8_server_test_{}.ch8", std::process::id()));
+    fs::write(&rom_path, DRAW_PRESSED_KEY_ROM).unwrap();
+    let address = format!("127.0.0.1:{}", free_port());
+    let _server = Server(
+        Command::new(env!("CARGO_BIN_EXE_chip_8_emulator"))
+            .args(["--serve", &address, "--no-audio"])
+            .arg(&rom_path)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+
+    let mut client = connect(&address);
+    client.set_read_timeout(Duration::from_secs(10)).unwrap();
+    let frame = client.read_frame().unwrap();
+    assert_eq!((frame.width, frame.height), (64, 32));
+    assert_eq!(frame.pixels.len(), 64 * 32 / 8);
+
+    client.key_down(0x5).unwrap();
+    client.key_up(0x5).unwrap();
+    // the frames before the key reached the cpu still show an empty display
+    let frame = loop {
+        let frame = client.read_frame().unwrap();
+        if frame.pixels.iter().any(|byte| *byte != 0) {
+            break frame;
+        }
+    };
+    // the display holds nothing but the font character 5 at the top left corner
+    let font_5: [u8; 5] = [0xF0, 0x80, 0xF0, 0x10, 0xF0];
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let expected = y < font_5.len() && x < 8 && font_5[y] & (0x80 >> x) != 0;
+            assert_eq!(frame.pixel(x, y), expected, "pixel {},{}", x, y);
+        }
+    }
+    let _ = fs::remove_file(&rom_path);
+}
@@ -0,0 +1,44 @@
+use std::fs;
+use std::process::Command;
+
+use serde_json::Value;
+
+/// Sets a few registers, draws the font character 0 and loops forever
+const ROM: [u8; 10] = [
+    0x63, 0x42, // V3 := 0x42
+    0xA0, 0x00, // I := 0x000
+    0xD0, 0x05, // draw 5 rows at (V0, V0)
+    0xF3, 0x15, // delay := V3
+    0x12, 0x08, // loop
+];
+
+#[test]
+fn dumped_state_is_json() {
+    let rom_path =
+        std::env::temp_dir().join(format!("chip_8_state_dump_test_{}.ch8", std::process::id()));
+    fs::write(&rom_path, ROM).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_chip_8_emulator"))
+        .args([
+            "--headless",
+            "--cycles",
+            "11",
+            "--no-audio",
+            "--dump-state-json",
+        ])
+        .arg(&rom_path)
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(&rom_path);
+    assert!(output.status.success(), "{:?}", output);
+
+    let state: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(state["program_counter"], 0x208);
+    assert_eq!(state["general_registers"][3], 0x42);
+    assert_eq!(state["i"], 0);
+    assert_eq!(state["stack"], Value::Array(Vec::new()));
+    assert_eq!(state["next_instruction"], serde_json::json!([0x12, 0x08]));
+    let display = state["display"].as_array().unwrap();
+    assert_eq!(display.len(), 32);
+    assert_eq!(display[0], "f000000000000000");
+    assert_eq!(display[5], "0000000000000000");
+}